@@ -0,0 +1,167 @@
+use crate::ray::Ray;
+use crate::vec3::{Vec3, F};
+use std::ops::Range;
+
+/// Axis-aligned bounding box.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// A box containing nothing: growing it by anything gives that thing.
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3 {
+            x: F::INFINITY,
+            y: F::INFINITY,
+            z: F::INFINITY,
+        },
+        max: Vec3 {
+            x: F::NEG_INFINITY,
+            y: F::NEG_INFINITY,
+            z: F::NEG_INFINITY,
+        },
+    };
+
+    pub fn new(a: Vec3, b: Vec3) -> Self {
+        Aabb {
+            min: a.min(&b),
+            max: a.max(&b),
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    pub fn grow(&self, p: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(&p),
+            max: self.max.max(&p),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x
+            || self.min.y > self.max.y
+            || self.min.z > self.max.z
+    }
+
+    pub fn surface_area(&self) -> F {
+        if self.is_empty() {
+            return 0.;
+        }
+        let e = self.extent();
+        2. * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    /// The axis (0, 1 or 2) along which the box is widest.
+    pub fn longest_axis(&self) -> usize {
+        let e = self.extent();
+        if e.x >= e.y && e.x >= e.z {
+            0
+        } else if e.y >= e.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Slab test. `inverse_direction` is `1 / ray.direction`, computed once per ray.
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        inverse_direction: &Vec3,
+        t: &Range<F>,
+    ) -> bool {
+        let mut t_min = t.start;
+        let mut t_max = t.end;
+        for axis in 0..3 {
+            let inv = inverse_direction.axis(axis);
+            let origin = ray.origin.axis(axis);
+            let mut t0 =
+                (self.min.axis(axis) - origin) * inv;
+            let mut t1 =
+                (self.max.axis(axis) - origin) * inv;
+            if inv < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // Written so that NaNs (0 * inf) leave the interval untouched.
+            if t0 > t_min {
+                t_min = t0;
+            }
+            if t1 < t_max {
+                t_max = t1;
+            }
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::ZERO, Vec3::ONE)
+    }
+
+    #[test]
+    fn union_and_area() {
+        let b = unit_box().union(&Aabb::new(
+            Vec3::new(2., 0., 0.),
+            Vec3::new(3., 1., 1.),
+        ));
+        assert_eq!(b.min, Vec3::ZERO);
+        assert_eq!(b.max, Vec3::new(3., 1., 1.));
+        assert_eq!(b.surface_area(), 14.);
+        assert_eq!(b.longest_axis(), 0);
+        assert_eq!(Aabb::EMPTY.surface_area(), 0.);
+    }
+
+    #[test]
+    fn ray_hits() {
+        let ray = Ray::new(
+            Vec3::new(0.5, 0.5, -1.),
+            Vec3::new(0., 0., 1.),
+        );
+        let inv = Vec3::ONE / ray.direction;
+        assert!(unit_box().hit(
+            &ray,
+            &inv,
+            &(0.0..10.)
+        ));
+        // Box is behind the allowed interval.
+        assert!(!unit_box().hit(
+            &ray,
+            &inv,
+            &(0.0..0.5)
+        ));
+
+        let miss = Ray::new(
+            Vec3::new(2., 0.5, -1.),
+            Vec3::new(0., 0., 1.),
+        );
+        let inv = Vec3::ONE / miss.direction;
+        assert!(!unit_box().hit(
+            &miss,
+            &inv,
+            &(0.0..10.)
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+//! Spending samples where they're needed: pixels stop being sampled
+//! once their noise is low enough.
+
+use crate::color::Color;
+use crate::image::Image;
+use crate::sampler::SamplerKind;
+use crate::vec3::F;
+
+/// Running mean and variance of a pixel's samples, by Welford's method.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunningStats {
+    count: u32,
+    mean: F,
+    /// Sum of squared differences from the mean.
+    m2: F,
+}
+
+impl RunningStats {
+    pub fn push(&mut self, x: F) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as F;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> F {
+        self.mean
+    }
+
+    pub fn variance(&self) -> F {
+        if self.count < 2 {
+            return F::INFINITY;
+        }
+        self.m2 / (self.count - 1) as F
+    }
+
+    /// Half the width of the 95% confidence interval for the mean.
+    pub fn error(&self) -> F {
+        1.96 * (self.variance() / self.count as F)
+            .sqrt()
+    }
+}
+
+/// How many samples each pixel gets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sampling {
+    /// Every pixel gets at least this many.
+    pub samples: u16,
+    /// Keep sampling pixels whose error is more than this fraction
+    /// of their brightness...
+    pub noise_threshold: Option<F>,
+    /// ...up to this many samples.
+    pub max_samples: u16,
+    /// Where each sample's random numbers come from...
+    pub sampler: SamplerKind,
+    /// ...and which of its equally good sets of them.
+    pub seed: u64,
+}
+
+impl Sampling {
+    /// The same number for every pixel.
+    pub fn fixed(samples: u16) -> Self {
+        Sampling {
+            samples,
+            noise_threshold: None,
+            max_samples: samples,
+            sampler: SamplerKind::default(),
+            seed: 0,
+        }
+    }
+
+    /// Whether a pixel whose samples' luminance is summed up in
+    /// `stats` should be sampled again.
+    pub fn wants_more(
+        &self,
+        stats: &RunningStats,
+    ) -> bool {
+        let count = stats.count();
+        if count < self.samples as u32 {
+            return true;
+        }
+        match self.noise_threshold {
+            None => false,
+            // Very dark pixels would need very many samples to be
+            // relatively precise, for no visible difference.
+            Some(threshold) => {
+                count < self.max_samples as u32
+                    && stats.error()
+                        > threshold
+                            * stats.mean().max(0.01)
+            }
+        }
+    }
+}
+
+/// False colour for a pixel that took `fraction` of the most samples
+/// any pixel could: blue through green to red.
+pub fn heat_color(fraction: F) -> Color {
+    let f = fraction.clamp(0., 1.) * 2.;
+    if f < 1. {
+        Color::new(0., f, 1. - f)
+    } else {
+        Color::new(f - 1., 2. - f, 0.)
+    }
+}
+
+/// Colour in an image of sample counts with `heat_color`.
+pub fn heatmap(
+    sample_counts: &Image,
+    max_samples: u16,
+) -> Image {
+    let mut out = Image::new(
+        sample_counts.width(),
+        sample_counts.height(),
+    );
+    for y in 0..out.height() {
+        for x in 0..out.width() {
+            let count = sample_counts.get(x, y).r();
+            out.set(
+                x,
+                y,
+                heat_color(
+                    count / max_samples.max(1) as F,
+                ),
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_stats() {
+        let mut stats = RunningStats::default();
+        assert_eq!(stats.error(), F::INFINITY);
+        for x in [2., 4., 4., 4., 5., 5., 7., 9.] {
+            stats.push(x);
+        }
+        assert_eq!(stats.count(), 8);
+        assert_eq!(stats.mean(), 5.);
+        assert!(
+            (stats.variance() - 32. / 7.).abs() < 1e-5
+        );
+    }
+
+    #[test]
+    fn stops_when_converged_or_at_the_limit() {
+        let sampling = Sampling {
+            samples: 4,
+            noise_threshold: Some(0.05),
+            max_samples: 100,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+        };
+        let mut flat = RunningStats::default();
+        while sampling.wants_more(&flat) {
+            flat.push(0.5);
+        }
+        assert_eq!(flat.count(), 4);
+
+        let mut noisy = RunningStats::default();
+        let mut n = 0;
+        while sampling.wants_more(&noisy) {
+            noisy.push([0., 10.][n % 2]);
+            n += 1;
+        }
+        assert_eq!(noisy.count(), 100);
+
+        let fixed = Sampling::fixed(3);
+        let mut stats = RunningStats::default();
+        while fixed.wants_more(&stats) {
+            stats.push(stats.count() as F * 100.);
+        }
+        assert_eq!(stats.count(), 3);
+    }
+}
@@ -0,0 +1,320 @@
+//! Arbitrary output variables: images of what camera rays first hit,
+//! rendered alongside the colour for compositing and denoising.
+
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::camera::Camera;
+use crate::hitable::Hit;
+use crate::image::{Image, WriteOptions};
+use crate::ray::Ray;
+use crate::scene::{HitIds, Scene};
+use crate::tonemap::ToneMap;
+use crate::vec3::{Vec3, F};
+use crate::{Error, Res};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Surface colour, without lighting. The background where nothing
+    /// was hit.
+    Albedo,
+    /// Shading normal, facing the camera.
+    Normal,
+    /// Distance in front of the camera, along its view direction.
+    Depth,
+    /// Where in the world.
+    Position,
+    /// Which thing, counting from 1 in the order they were added.
+    ObjectId,
+    /// Which material, counting from 1.
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::Position,
+        Aov::ObjectId,
+        Aov::MaterialId,
+    ];
+
+    /// Whether samples are averaged. IDs can't be blended, so they
+    /// come from the first sample.
+    pub fn is_averaged(self) -> bool {
+        !matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+
+    /// Whether it's a colour, which any image format can hold. The
+    /// others can be negative or far above 1.
+    pub fn is_color(self) -> bool {
+        self == Aov::Albedo
+    }
+
+    /// The value for one camera ray. Depth, position, normal and IDs
+    /// are zero where nothing was hit.
+    pub fn value(
+        self,
+        camera: &Camera,
+        scene: &Scene,
+        ray: &Ray,
+        hit: Option<&(Hit, HitIds)>,
+    ) -> Vec3 {
+        let (hit, ids) = match hit {
+            Some((hit, ids)) => (hit, ids),
+            None => {
+                return match self {
+                    Aov::Albedo => scene
+                        .background
+                        .color(ray)
+                        .into(),
+                    _ => Vec3::ZERO,
+                }
+            }
+        };
+        match self {
+            Aov::Albedo => hit.material.albedo(hit),
+            Aov::Normal => {
+                if ray.direction.dot(&hit.normal) > 0. {
+                    -hit.normal
+                } else {
+                    hit.normal
+                }
+            }
+            Aov::Depth => {
+                Vec3::from(camera.depth(&hit.p))
+            }
+            Aov::Position => hit.p,
+            Aov::ObjectId => {
+                Vec3::from(ids.object as F)
+            }
+            Aov::MaterialId => {
+                Vec3::from(ids.material as F)
+            }
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .iter()
+            .find(|aov| aov.to_string() == s)
+            .copied()
+            .ok_or_else(|| {
+                let names: Vec<String> = Aov::ALL
+                    .iter()
+                    .map(|aov| aov.to_string())
+                    .collect();
+                format!(
+                    "expected one of {}, not {:?}",
+                    names.join(", "),
+                    s
+                )
+            })
+    }
+}
+
+impl Display for Aov {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        f.write_str(match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+        })
+    }
+}
+
+/// Where an AOV goes when it isn't a layer of `path`: `image.png`'s
+/// albedo goes in `image.albedo.png`.
+pub fn separate_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!(
+            "{}.{}.{}",
+            stem,
+            aov,
+            extension.to_string_lossy()
+        ),
+        None => format!("{}.{}", stem, aov),
+    };
+    path.with_file_name(name)
+}
+
+/// Whether AOVs are written as layers of `path`, rather than files
+/// of their own.
+pub fn as_layers(path: &Path, separate: bool) -> bool {
+    !separate
+        && path.extension().is_some_and(|e| e == "exr")
+}
+
+/// Check that `aovs` can be written as layers of `path` or beside it,
+/// before spending time rendering them.
+pub fn check_writable(
+    path: &Path,
+    options: &WriteOptions,
+    aovs: &[Aov],
+    separate: bool,
+) -> Res<()> {
+    if as_layers(path, separate) {
+        return Ok(());
+    }
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy();
+    for &aov in aovs {
+        // Only these keep negative and unbounded values.
+        if !aov.is_color()
+            && !matches!(&*extension, "pfm" | "exr")
+        {
+            return Err(Error::InvalidParameter(format!(
+                "the {} AOV needs .pfm or .exr output, as .{} images can't hold its values",
+                aov, extension
+            )));
+        }
+        Image::check_writable(
+            &separate_path(path, aov),
+            &data_options(options),
+        )?;
+    }
+    Ok(())
+}
+
+/// AOVs hold data rather than light, so separate files aren't
+/// exposed or tone mapped.
+fn data_options(
+    options: &WriteOptions,
+) -> WriteOptions {
+    WriteOptions {
+        tone_map: ToneMap::Clamp,
+        exposure: 0.,
+        ..*options
+    }
+}
+
+/// Write the image and its AOVs, as EXR layers or separate files.
+pub fn write(
+    path: &Path,
+    options: &WriteOptions,
+    image: &Image,
+    aovs: &[(Aov, Image)],
+    separate: bool,
+) -> Res<()> {
+    let names: Vec<Aov> =
+        aovs.iter().map(|(aov, _)| *aov).collect();
+    check_writable(path, options, &names, separate)?;
+    if as_layers(path, separate) {
+        let names: Vec<String> = aovs
+            .iter()
+            .map(|(aov, _)| aov.to_string())
+            .collect();
+        let mut layers = vec![("", image)];
+        layers.extend(names.iter().zip(aovs).map(
+            |(name, (_, aov_image))| {
+                (name.as_str(), aov_image)
+            },
+        ));
+        return Image::write_layers(
+            path, options, &layers,
+        );
+    }
+    image.write(path, options)?;
+    for (aov, aov_image) in aovs {
+        aov_image.write(
+            &separate_path(path, *aov),
+            &data_options(options),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::BitDepth;
+
+    #[test]
+    fn names_round_trip() {
+        for aov in Aov::ALL {
+            assert_eq!(
+                aov.to_string().parse(),
+                Ok(aov)
+            );
+        }
+        assert!("beauty".parse::<Aov>().is_err());
+    }
+
+    #[test]
+    fn data_needs_a_float_format() {
+        // In separate files beside the image.
+        let check = |path: &str, aov| {
+            check_writable(
+                Path::new(path),
+                &WriteOptions::default(),
+                &[aov],
+                true,
+            )
+        };
+        assert!(check("a.png", Aov::Albedo).is_ok());
+        assert!(check("a.pfm", Aov::Depth).is_ok());
+        assert!(check("a.exr", Aov::Normal).is_ok());
+        assert_eq!(
+            check("a.ppm", Aov::Depth)
+                .err()
+                .unwrap()
+                .to_string(),
+            "the depth AOV needs .pfm or .exr output, as .ppm images can't hold its values"
+        );
+        // Layers of an EXR image are always floats.
+        assert!(check_writable(
+            Path::new("a.exr"),
+            &WriteOptions {
+                bit_depth: Some(BitDepth::Sixteen),
+                ..WriteOptions::default()
+            },
+            &[Aov::Depth, Aov::Position],
+            false
+        )
+        .is_ok());
+        // Without layers, the AOVs go beside the image anyway.
+        assert!(check_writable(
+            Path::new("a.png"),
+            &WriteOptions::default(),
+            &[Aov::Depth],
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn separate_paths() {
+        assert_eq!(
+            separate_path(
+                Path::new("out/image.png"),
+                Aov::Depth
+            ),
+            Path::new("out/image.depth.png")
+        );
+        assert_eq!(
+            separate_path(
+                Path::new("render"),
+                Aov::ObjectId
+            ),
+            Path::new("render.object-id")
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use crate::aabb::Aabb;
+use crate::hitable::Hit;
+use crate::ray::Ray;
+use crate::vec3::{Vec3, F};
+use std::ops::Range;
+
+/// Number of buckets used to estimate the surface area heuristic.
+const SAH_BINS: usize = 12;
+/// Relative cost of visiting a node compared to intersecting a primitive.
+const TRAVERSAL_COST: F = 1.;
+const MAX_LEAF_SIZE: usize = 4;
+/// Deeper than this we stop splitting, so traversal can use a fixed stack.
+const MAX_TREE_DEPTH: usize = 60;
+const STACK_SIZE: usize = MAX_TREE_DEPTH + 4;
+
+enum NodeKind {
+    /// Primitives `indices[first..first + count]`.
+    Leaf { first: usize, count: usize },
+    /// The first child directly follows its parent in the node list.
+    Interior { second_child: usize, axis: usize },
+}
+
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+/// Bounding volume hierarchy over a list of primitives.
+///
+/// It only knows their bounding boxes: callers keep the primitives
+/// themselves and are asked to intersect them by index.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+struct BuildItem {
+    bounds: Aabb,
+    centroid: Vec3,
+    index: usize,
+}
+
+impl Bvh {
+    /// Build a tree with a binned surface area heuristic.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut items: Vec<BuildItem> = bounds
+            .iter()
+            .enumerate()
+            .map(|(index, b)| BuildItem {
+                bounds: *b,
+                centroid: b.centroid(),
+                index,
+            })
+            .collect();
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(
+                2 * bounds.len() / MAX_LEAF_SIZE + 1,
+            ),
+            indices: Vec::with_capacity(bounds.len()),
+        };
+        if !items.is_empty() {
+            bvh.build_node(&mut items, 0);
+        }
+        bvh
+    }
+
+    /// Bounds of everything in the tree, or None if it's empty.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    fn build_node(
+        &mut self,
+        items: &mut [BuildItem],
+        depth: usize,
+    ) -> usize {
+        let bounds =
+            items.iter().fold(Aabb::EMPTY, |b, i| {
+                b.union(&i.bounds)
+            });
+        let node_index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            kind: NodeKind::Leaf { first: 0, count: 0 },
+        });
+
+        let split = if depth < MAX_TREE_DEPTH {
+            find_split(items, &bounds)
+        } else {
+            None
+        };
+
+        match split {
+            None => {
+                let first = self.indices.len();
+                self.indices.extend(
+                    items.iter().map(|i| i.index),
+                );
+                self.nodes[node_index].kind =
+                    NodeKind::Leaf {
+                        first,
+                        count: items.len(),
+                    };
+            }
+            Some((axis, mid)) => {
+                let (left, right) =
+                    items.split_at_mut(mid);
+                self.build_node(left, depth + 1);
+                let second_child =
+                    self.build_node(right, depth + 1);
+                self.nodes[node_index].kind =
+                    NodeKind::Interior {
+                        second_child,
+                        axis,
+                    };
+            }
+        }
+        node_index
+    }
+
+    /// Find the closest hit. `hit_primitive` is called with a primitive
+    /// index and the range still worth searching.
+    pub fn hit<'a>(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+        mut hit_primitive: impl FnMut(
+            usize,
+            &Range<F>,
+        )
+            -> Option<Hit<'a>>,
+    ) -> Option<Hit<'a>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inverse_direction =
+            Vec3::ONE / ray.direction;
+
+        let mut hit = None;
+        let mut closest_so_far = t.end;
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let node = &self.nodes[stack[stack_len]];
+            if !node.bounds.hit(
+                ray,
+                &inverse_direction,
+                &(t.start..closest_so_far),
+            ) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    for &index in &self.indices
+                        [first..first + count]
+                    {
+                        if let Some(h) = hit_primitive(
+                            index,
+                            &(t.start..closest_so_far),
+                        ) {
+                            closest_so_far = h.t;
+                            hit = Some(h);
+                        }
+                    }
+                }
+                NodeKind::Interior {
+                    second_child,
+                    axis,
+                } => {
+                    let first_child =
+                        stack[stack_len] + 1;
+                    // Visit the nearer child first, so that it
+                    // can shrink the range for the further one.
+                    let (near, far) =
+                        if inverse_direction.axis(axis)
+                            < 0.
+                        {
+                            (second_child, first_child)
+                        } else {
+                            (first_child, second_child)
+                        };
+                    stack[stack_len] = far;
+                    stack[stack_len + 1] = near;
+                    stack_len += 2;
+                }
+            }
+        }
+        hit
+    }
+}
+
+/// Choose an axis and partition `items` along it.
+///
+/// Returns the number of items in the first half, or None if it's
+/// cheaper to keep them all in one leaf.
+fn find_split(
+    items: &mut [BuildItem],
+    bounds: &Aabb,
+) -> Option<(usize, usize)> {
+    if items.len() <= 1 {
+        return None;
+    }
+    let centroid_bounds = items
+        .iter()
+        .fold(Aabb::EMPTY, |b, i| b.grow(i.centroid));
+    let axis = centroid_bounds.longest_axis();
+    let low = centroid_bounds.min.axis(axis);
+    let width = centroid_bounds.extent().axis(axis);
+
+    if width <= 0. {
+        // Everything is stacked on one point: we can't split spatially.
+        if items.len() <= MAX_LEAF_SIZE {
+            return None;
+        }
+        return Some((axis, items.len() / 2));
+    }
+
+    let bin_of = |item: &BuildItem| {
+        let b = ((item.centroid.axis(axis) - low)
+            / width
+            * SAH_BINS as F) as usize;
+        b.min(SAH_BINS - 1)
+    };
+
+    let mut bin_bounds = [Aabb::EMPTY; SAH_BINS];
+    let mut bin_counts = [0usize; SAH_BINS];
+    for item in items.iter() {
+        let b = bin_of(item);
+        bin_bounds[b] =
+            bin_bounds[b].union(&item.bounds);
+        bin_counts[b] += 1;
+    }
+
+    // Sweep from the right, so the left sweep can price each split.
+    let mut right_areas = [0. as F; SAH_BINS];
+    let mut right_counts = [0usize; SAH_BINS];
+    let mut acc = Aabb::EMPTY;
+    let mut count = 0;
+    for b in (1..SAH_BINS).rev() {
+        acc = acc.union(&bin_bounds[b]);
+        count += bin_counts[b];
+        right_areas[b] = acc.surface_area();
+        right_counts[b] = count;
+    }
+
+    let mut best: Option<(usize, F)> = None;
+    let mut acc = Aabb::EMPTY;
+    let mut count = 0;
+    for b in 0..SAH_BINS - 1 {
+        acc = acc.union(&bin_bounds[b]);
+        count += bin_counts[b];
+        if count == 0 || right_counts[b + 1] == 0 {
+            continue;
+        }
+        let cost = acc.surface_area() * count as F
+            + right_areas[b + 1]
+                * right_counts[b + 1] as F;
+        if best.is_none_or(|(_, c)| cost < c) {
+            best = Some((b, cost));
+        }
+    }
+
+    let (split_bin, cost) = best?;
+    let area = bounds.surface_area();
+    let leaf_cost = items.len() as F;
+    let split_cost = TRAVERSAL_COST
+        + if area > 0. {
+            cost / area
+        } else {
+            leaf_cost
+        };
+    if items.len() <= MAX_LEAF_SIZE
+        && split_cost >= leaf_cost
+    {
+        return None;
+    }
+
+    // Partition in place: items in bins up to split_bin go first.
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if bin_of(&items[i]) <= split_bin {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((axis, mid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::{Hitable, Sphere};
+    use crate::material::Lambertian;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn same_hits_as_linear_search() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut r = || rng.gen::<F>();
+        let spheres: Vec<Sphere> = (0..300)
+            .map(|_| Sphere {
+                center: Vec3::new(
+                    20. * r() - 10.,
+                    20. * r() - 10.,
+                    20. * r() - 10.,
+                ),
+                radius: 0.1 + r(),
+                material: Box::new(Lambertian::new(
+                    Vec3::ONE,
+                )),
+            })
+            .collect();
+        let bounds: Vec<Aabb> = spheres
+            .iter()
+            .map(|s| s.bounding_box().unwrap())
+            .collect();
+        let bvh = Bvh::build(&bounds);
+
+        for _ in 0..500 {
+            let ray = Ray::new(
+                Vec3::new(
+                    r() - 0.5,
+                    r() - 0.5,
+                    r() - 0.5,
+                ) * 30.,
+                Vec3::new(
+                    r() - 0.5,
+                    r() - 0.5,
+                    r() - 0.5,
+                ),
+            );
+            let t = 0.001..F::INFINITY;
+
+            let linear = spheres
+                .iter()
+                .filter_map(|s| s.hit(&ray, &t))
+                .map(|h| h.t)
+                .fold(F::INFINITY, F::min);
+            let tree = bvh
+                .hit(&ray, &t, |i, t| {
+                    spheres[i].hit(&ray, t)
+                })
+                .map_or(F::INFINITY, |h| h.t);
+
+            assert_eq!(linear, tree);
+        }
+    }
+
+    #[test]
+    fn empty_tree() {
+        let bvh = Bvh::build(&[]);
+        assert!(bvh.bounds().is_none());
+        let ray = Ray::new(Vec3::ZERO, Vec3::ONE);
+        assert!(bvh
+            .hit(
+                &ray,
+                &(0.0..1.),
+                |_, _| unreachable!()
+            )
+            .is_none());
+    }
+}
@@ -1,6 +1,73 @@
 use crate::{ray::Ray, vec3::Vec3};
 
-use crate::vec3::{randf, F, PI};
+use crate::sampler::{unit_disk_point, Sampler};
+use crate::vec3::{F, PI};
+use crate::{Error, Res};
+
+/// Where the camera is and how it's set up, independent of the image shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraSettings {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    pub up: Vec3,
+    /// Vertical field of view, in degrees.
+    pub fov: F,
+    pub aperture: F,
+    /// Defaults to the distance between look_from and look_at.
+    pub focus_distance: Option<F>,
+    /// When the shutter opens and closes, for motion blur. Things that
+    /// move go from where they are at time 0 to time 1.
+    pub shutter: (F, F),
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            look_from: Vec3::new(12., 6., 0.51),
+            look_at: Vec3::new(0., 1., 0.),
+            up: Vec3::new(0., 1., 0.),
+            fov: 20.,
+            aperture: 0.6,
+            focus_distance: None,
+            shutter: (0., 0.),
+        }
+    }
+}
+
+impl CameraSettings {
+    pub fn camera(&self, aspect: F) -> Res<Camera> {
+        Camera::new(
+            self.look_from,
+            self.look_at,
+            self.up,
+            self.fov,
+            aspect,
+            self.aperture,
+            self.focus_distance.unwrap_or_else(|| {
+                (self.look_from - self.look_at).length()
+            }),
+        )
+        .with_shutter(self.shutter.0, self.shutter.1)
+    }
+}
+
+/// Whether a shutter opening at `open` and closing at `close` makes
+/// sense.
+pub(crate) fn check_shutter(
+    open: F,
+    close: F,
+) -> Result<(), String> {
+    if (0. ..=1.).contains(&open)
+        && (open..=1.).contains(&close)
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "should open and close at times from 0 to 1, in that order, not {} and {}",
+            open, close
+        ))
+    }
+}
 
 pub struct Camera {
     lower_left_corner: Vec3,
@@ -10,6 +77,7 @@ pub struct Camera {
 
     u_v_w: (Vec3, Vec3, Vec3),
     lens_radius: F,
+    shutter: (F, F),
 }
 
 impl Camera {
@@ -45,13 +113,51 @@ impl Camera {
             origin,
             u_v_w: (u, v, w),
             lens_radius: aperture / 2.,
+            shutter: (0., 0.),
         }
     }
-    pub fn ray(&self, s: F, t: F) -> Ray {
-        let rd =
-            self.lens_radius * random_in_unit_disk();
+
+    /// Send rays at times from `open` to `close`, instead of all at 0.
+    /// Things only move between times 0 and 1, so the shutter must
+    /// open and close within them.
+    pub fn with_shutter(
+        mut self,
+        open: F,
+        close: F,
+    ) -> Res<Self> {
+        check_shutter(open, close).map_err(|m| {
+            Error::InvalidParameter(format!(
+                "the shutter {}",
+                m
+            ))
+        })?;
+        self.shutter = (open, close);
+        Ok(self)
+    }
+    /// How far `p` is in front of the camera, along its view direction.
+    pub fn depth(&self, p: &Vec3) -> F {
+        (self.origin - *p).dot(&self.u_v_w.2)
+    }
+
+    /// The ray through (`s`, `t`) across the image, from a spot on the
+    /// lens and at a time picked by `sampler`.
+    pub fn ray(
+        &self,
+        s: F,
+        t: F,
+        sampler: &mut dyn Sampler,
+    ) -> Ray {
+        let rd = self.lens_radius
+            * unit_disk_point(sampler.get_2d());
         let offset =
             self.u_v_w.0 * rd.x + self.u_v_w.1 * rd.y;
+        let (open, close) = self.shutter;
+        // Still shutters leave the samples for the rest of the path.
+        let time = if close > open {
+            open + (close - open) * sampler.get_1d()
+        } else {
+            open
+        };
         Ray {
             origin: self.origin + offset,
             direction: (self.lower_left_corner
@@ -59,16 +165,7 @@ impl Camera {
                 + self.vertical * t
                 - self.origin
                 - offset),
-        }
-    }
-}
-
-fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let rand_disc = Vec3::new(randf(), randf(), 0.);
-        let p = 2. * rand_disc - Vec3::new(1., 1., 0.);
-        if p.squared_length() < 1. {
-            return p;
+            time,
         }
     }
 }
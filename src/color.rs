@@ -45,6 +45,25 @@ impl Display for WebColor {
     }
 }
 
+/// Undo the sRGB transfer curve, turning an encoded 0-1 value into linear light.
+pub fn srgb_to_linear(c: F) -> F {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Apply the sRGB transfer curve to linear light, clamped to 0-1.
+pub fn linear_to_srgb(c: F) -> F {
+    let c = c.clamp(0., 1.);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
 impl Color {
     pub fn new(r: F, g: F, b: F) -> Color {
         Self {
@@ -62,6 +81,15 @@ impl Color {
         self.v.z
     }
 
+    /// From sRGB-encoded components, as stored in most image files.
+    pub fn from_srgb(r: F, g: F, b: F) -> Color {
+        Color::new(
+            srgb_to_linear(r),
+            srgb_to_linear(g),
+            srgb_to_linear(b),
+        )
+    }
+
     pub fn white() -> Color {
         Color::new(1., 1., 1.)
     }
@@ -85,19 +113,26 @@ impl Color {
 
         Color::from(start * (1. - t) + end * t)
     }
-    pub fn gamma_corrected(self) -> Self {
-        let gamma_correct = |i: F| (i.powf(1.0 / 1.8));
+    /// Relative luminance, with Rec. 709 primaries.
+    pub fn luminance(&self) -> F {
+        0.2126 * self.v.x
+            + 0.7152 * self.v.y
+            + 0.0722 * self.v.z
+    }
+
+    /// sRGB encoded, for storing in low dynamic range images.
+    pub fn to_srgb(self) -> Self {
         Self::new(
-            gamma_correct(self.v.x),
-            gamma_correct(self.v.y),
-            gamma_correct(self.v.z),
+            linear_to_srgb(self.v.x),
+            linear_to_srgb(self.v.y),
+            linear_to_srgb(self.v.z),
         )
     }
 
     pub fn as_web_color(&self) -> WebColor {
-        let to8 = |i: F| (i * 255.99) as u8;
+        let to8 = |i: F| (i * 255.).round() as u8;
 
-        let c = self.gamma_corrected().v;
+        let c = self.to_srgb().v;
         WebColor([to8(c.x), to8(c.y), to8(c.z)])
     }
 
@@ -151,11 +186,51 @@ mod tests {
         assert_eq!(
             Color::from(Vec3::new(0.4, 1.0, 0.0))
                 .as_web_color(),
-            // Without Gamma correction:
+            // Without sRGB encoding:
             // (102, 255, 0)
-            // With gamma:
-            WebColor([153, 255, 0])
-        )
+            // With it:
+            WebColor([170, 255, 0])
+        );
+        // Out of range values are clamped.
+        assert_eq!(
+            Color::new(-1., 2., 0.5).as_web_color(),
+            WebColor([0, 255, 188])
+        );
+    }
+
+    #[test]
+    fn srgb_decoding() {
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert!((srgb_to_linear(1.) - 1.).abs() < 1e-6);
+        // The linear toe, and either side of where the curve joins it.
+        assert_eq!(srgb_to_linear(0.02), 0.02 / 12.92);
+        assert!(
+            (srgb_to_linear(0.04045)
+                - srgb_to_linear(0.04046))
+            .abs()
+                < 1e-6
+        );
+        assert!(
+            (srgb_to_linear(0.5) - 0.2140).abs() < 1e-4
+        );
+    }
+
+    #[test]
+    fn srgb_encoding_inverts_decoding() {
+        for i in 0..=100 {
+            let c = i as F / 100.;
+            assert!(
+                (linear_to_srgb(srgb_to_linear(c)) - c)
+                    .abs()
+                    < 1e-5,
+                "{}",
+                c
+            );
+        }
+        assert!(
+            (linear_to_srgb(0.001) - 0.01292).abs()
+                < 1e-7
+        );
     }
 
     #[test]
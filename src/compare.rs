@@ -0,0 +1,643 @@
+//! Measuring how different two images look.
+//!
+//! Besides the usual mean squared error and SSIM, this scores images
+//! the way FLIP (Andersson et al. 2020) does: both are blurred as the
+//! eye would blur them at a typical viewing distance, then their
+//! colour differences are weighed up along with any edges or points
+//! that one has and the other doesn't.
+
+use std::f32::consts::SQRT_2;
+
+use crate::adaptive::heat_color;
+use crate::color::Color;
+use crate::image::Image;
+use crate::vec3::{Vec3, F};
+use crate::{Error, Res};
+
+/// Widths, in pixels, of the eye's blurring of lightness, red-green and
+/// blue-yellow, as FLIP models it at 67 pixels per degree of view: a
+/// monitor at arm's length.
+const CONTRAST_SIGMAS: [F; 3] = [1.03, 1.10, 3.01];
+/// Width of the edge and point detectors, in pixels, at the same
+/// viewing distance.
+const FEATURE_SIGMA: F = 2.75;
+/// Colour differences below this fraction of the largest...
+const COLOR_PIVOT: F = 0.4;
+/// ...are mapped below this error, leaving the rest for larger ones.
+const COLOR_PIVOT_ERROR: F = 0.95;
+/// Width, in pixels, of the window SSIM compares statistics over.
+const SSIM_SIGMA: F = 1.5;
+/// Keep SSIM stable in flat, dark areas.
+const SSIM_C1: F = 0.01 * 0.01;
+const SSIM_C2: F = 0.03 * 0.03;
+
+/// How different a test image is from a reference.
+pub struct Comparison {
+    /// Mean squared difference of the sRGB encoded channels, from 0
+    /// to 1.
+    pub mse: F,
+    /// Peak signal-to-noise ratio in decibels: infinite for identical
+    /// images.
+    pub psnr: F,
+    /// Mean structural similarity of luma: 1 for identical images.
+    pub ssim: F,
+    /// Mean perceptual error, FLIP-style: 0 for identical images, up
+    /// to 1.
+    pub flip: F,
+    width: usize,
+    height: usize,
+    /// Each pixel's perceptual error.
+    flip_errors: Vec<F>,
+}
+
+impl Comparison {
+    /// Compare two images of the same size, both already tone mapped
+    /// into 0-1.
+    pub fn new(
+        reference: &Image,
+        test: &Image,
+    ) -> Res<Self> {
+        let (width, height) =
+            (reference.width(), reference.height());
+        if (test.width(), test.height())
+            != (width, height)
+        {
+            return Err(Error::InvalidParameter(format!(
+                "can't compare a {}x{} image with a {}x{} one",
+                width,
+                height,
+                test.width(),
+                test.height()
+            )));
+        }
+        let pixels = |image: &Image| -> Vec<Color> {
+            (0..height)
+                .flat_map(|y| {
+                    (0..width).map(move |x| (x, y))
+                })
+                .map(|(x, y)| {
+                    let c: Vec3 =
+                        image.get(x, y).into();
+                    Color::new(
+                        c.x.clamp(0., 1.),
+                        c.y.clamp(0., 1.),
+                        c.z.clamp(0., 1.),
+                    )
+                })
+                .collect()
+        };
+        let (reference, test) =
+            (pixels(reference), pixels(test));
+
+        let encoded = |pixels: &[Color]| -> Vec<Vec3> {
+            pixels
+                .iter()
+                .map(|c| c.to_srgb().into())
+                .collect()
+        };
+        let (encoded_reference, encoded_test) =
+            (encoded(&reference), encoded(&test));
+
+        let mut squared_error = 0.;
+        for (r, t) in
+            encoded_reference.iter().zip(&encoded_test)
+        {
+            squared_error +=
+                (*r - *t).squared_length() as f64;
+        }
+        let mse = (squared_error
+            / (reference.len() * 3).max(1) as f64)
+            as F;
+        let psnr = if mse > 0. {
+            -10. * mse.log10()
+        } else {
+            F::INFINITY
+        };
+
+        let luma = |encoded: &[Vec3]| -> Vec<F> {
+            encoded
+                .iter()
+                .map(|&c| Color::from(c).luminance())
+                .collect()
+        };
+        let ssim = mean(&ssim_map(
+            &luma(&encoded_reference),
+            &luma(&encoded_test),
+            width,
+            height,
+        ));
+
+        let flip_errors =
+            flip_map(&reference, &test, width, height);
+        Ok(Comparison {
+            mse,
+            psnr,
+            ssim,
+            flip: mean(&flip_errors),
+            width,
+            height,
+            flip_errors,
+        })
+    }
+
+    /// Each pixel's perceptual error in false colour, from blue for
+    /// none to red for the most.
+    pub fn error_image(&self) -> Image {
+        let mut out =
+            Image::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.set(
+                    x,
+                    y,
+                    heat_color(
+                        self.flip_errors
+                            [y * self.width + x],
+                    ),
+                );
+            }
+        }
+        out
+    }
+}
+
+fn mean(values: &[F]) -> F {
+    let sum: f64 =
+        values.iter().map(|&v| v as f64).sum();
+    (sum / values.len().max(1) as f64) as F
+}
+
+/// Structural similarity of each pixel's neighbourhood.
+fn ssim_map(
+    a: &[F],
+    b: &[F],
+    width: usize,
+    height: usize,
+) -> Vec<F> {
+    let window = gaussian(SSIM_SIGMA);
+    let blur = |values: Vec<F>| {
+        convolve(
+            &values, width, height, &window, &window,
+        )
+    };
+    let product = |p: &[F], q: &[F]| -> Vec<F> {
+        p.iter().zip(q).map(|(x, y)| x * y).collect()
+    };
+    let (mean_a, mean_b) =
+        (blur(a.to_vec()), blur(b.to_vec()));
+    let (mean_aa, mean_bb, mean_ab) = (
+        blur(product(a, a)),
+        blur(product(b, b)),
+        blur(product(a, b)),
+    );
+    (0..a.len())
+        .map(|i| {
+            let (ma, mb) = (mean_a[i], mean_b[i]);
+            let variance_a = mean_aa[i] - ma * ma;
+            let variance_b = mean_bb[i] - mb * mb;
+            let covariance = mean_ab[i] - ma * mb;
+            ((2. * ma * mb + SSIM_C1)
+                * (2. * covariance + SSIM_C2))
+                / ((ma * ma + mb * mb + SSIM_C1)
+                    * (variance_a
+                        + variance_b
+                        + SSIM_C2))
+        })
+        .collect()
+}
+
+/// FLIP's error for each pixel, from 0 to 1.
+fn flip_map(
+    reference: &[Color],
+    test: &[Color],
+    width: usize,
+    height: usize,
+) -> Vec<F> {
+    let (seen_reference, seen_test) = (
+        perceived_colors(reference, width, height),
+        perceived_colors(test, width, height),
+    );
+    let (features_reference, features_test) = (
+        features(reference, width, height),
+        features(test, width, height),
+    );
+    // Green and blue are as far apart as colours get.
+    let most = hyab(
+        hunt(lab(Color::new(0., 1., 0.))),
+        hunt(lab(Color::new(0., 0., 1.))),
+    )
+    .powf(0.7);
+
+    (0..reference.len())
+        .map(|i| {
+            let difference =
+                hyab(seen_reference[i], seen_test[i])
+                    .powf(0.7);
+            let pivot = COLOR_PIVOT * most;
+            let color_error = if difference < pivot {
+                COLOR_PIVOT_ERROR * difference / pivot
+            } else {
+                COLOR_PIVOT_ERROR
+                    + (difference - pivot) / (most - pivot)
+                        * (1. - COLOR_PIVOT_ERROR)
+            }
+            .min(1.);
+
+            let ((edge_r, point_r), (edge_t, point_t)) =
+                (features_reference[i], features_test[i]);
+            let feature_error = ((edge_r - edge_t)
+                .abs()
+                .max((point_r - point_t).abs())
+                / SQRT_2)
+                .sqrt()
+                .min(1.);
+            // Differing features make colour errors stand out.
+            color_error.powf(1. - feature_error)
+        })
+        .collect()
+}
+
+/// Each pixel as the eye takes it in: blurred in an opponent colour
+/// space, then in L*a*b* with chroma scaled by lightness.
+fn perceived_colors(
+    pixels: &[Color],
+    width: usize,
+    height: usize,
+) -> Vec<Vec3> {
+    let opponent: Vec<Vec3> =
+        pixels.iter().map(|&c| ycxcz(xyz(c))).collect();
+    let blurred: Vec<Vec<F>> = CONTRAST_SIGMAS
+        .iter()
+        .enumerate()
+        .map(|(channel, &sigma)| {
+            let values: Vec<F> = opponent
+                .iter()
+                .map(|v| [v.x, v.y, v.z][channel])
+                .collect();
+            let kernel = gaussian(sigma);
+            convolve(
+                &values, width, height, &kernel,
+                &kernel,
+            )
+        })
+        .collect();
+    (0..pixels.len())
+        .map(|i| {
+            let v = Vec3::new(
+                blurred[0][i],
+                blurred[1][i],
+                blurred[2][i],
+            );
+            // Blurring can leave colours no screen could show.
+            let c: Vec3 =
+                linear_rgb(xyz_from_ycxcz(v)).into();
+            hunt(lab(Color::new(
+                c.x.clamp(0., 1.),
+                c.y.clamp(0., 1.),
+                c.z.clamp(0., 1.),
+            )))
+        })
+        .collect()
+}
+
+/// How strongly each pixel is on an edge and on a point, from its
+/// luminance's first and second derivatives.
+fn features(
+    pixels: &[Color],
+    width: usize,
+    height: usize,
+) -> Vec<(F, F)> {
+    let luminance: Vec<F> =
+        pixels.iter().map(|c| c.luminance()).collect();
+    let smooth = gaussian(FEATURE_SIGMA);
+    let (first, second) = (
+        balanced(derivative(&smooth, FEATURE_SIGMA, 1)),
+        balanced(derivative(&smooth, FEATURE_SIGMA, 2)),
+    );
+    let magnitude = |kernel: &[F]| -> Vec<F> {
+        let across = convolve(
+            &luminance, width, height, kernel, &smooth,
+        );
+        let down = convolve(
+            &luminance, width, height, &smooth, kernel,
+        );
+        across
+            .iter()
+            .zip(&down)
+            .map(|(x, y)| (x * x + y * y).sqrt())
+            .collect()
+    };
+    magnitude(&first)
+        .into_iter()
+        .zip(magnitude(&second))
+        .collect()
+}
+
+/// Weights of a Gaussian out to three widths either side, summing to 1.
+fn gaussian(sigma: F) -> Vec<F> {
+    let radius = (3. * sigma).ceil() as isize;
+    let weights: Vec<F> = (-radius..=radius)
+        .map(|x| {
+            (-(x * x) as F / (2. * sigma * sigma)).exp()
+        })
+        .collect();
+    let sum: F = weights.iter().sum();
+    weights.iter().map(|w| w / sum).collect()
+}
+
+/// The first or second derivative of a `gaussian` kernel.
+fn derivative(
+    kernel: &[F],
+    sigma: F,
+    order: u8,
+) -> Vec<F> {
+    let radius = (kernel.len() / 2) as isize;
+    kernel
+        .iter()
+        .zip(-radius..=radius)
+        .map(|(w, x)| {
+            let x = x as F / sigma;
+            match order {
+                1 => -x * w,
+                _ => (x * x - 1.) * w,
+            }
+        })
+        .collect()
+}
+
+/// Scale a kernel's positive weights to sum to 1 and its negative ones
+/// to -1, so it finds features of any size equally.
+fn balanced(kernel: Vec<F>) -> Vec<F> {
+    let positive: F =
+        kernel.iter().filter(|&&w| w > 0.).sum();
+    let negative: F =
+        -kernel.iter().filter(|&&w| w < 0.).sum::<F>();
+    kernel
+        .into_iter()
+        .map(|w| {
+            if w > 0. {
+                w / positive
+            } else {
+                w / negative
+            }
+        })
+        .collect()
+}
+
+/// Filter rows with `horizontal` then columns with `vertical`, both
+/// centred on the pixel, repeating edge pixels outwards.
+fn convolve(
+    values: &[F],
+    width: usize,
+    height: usize,
+    horizontal: &[F],
+    vertical: &[F],
+) -> Vec<F> {
+    let rows = filter(
+        values,
+        width,
+        height,
+        horizontal,
+        (1, 0),
+    );
+    filter(&rows, width, height, vertical, (0, 1))
+}
+
+fn filter(
+    values: &[F],
+    width: usize,
+    height: usize,
+    kernel: &[F],
+    (dx, dy): (isize, isize),
+) -> Vec<F> {
+    let radius = (kernel.len() / 2) as isize;
+    let mut out = Vec::with_capacity(values.len());
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let mut sum = 0.;
+            for (k, weight) in kernel.iter().enumerate()
+            {
+                let offset = k as isize - radius;
+                let qx = (x + offset * dx)
+                    .clamp(0, width as isize - 1);
+                let qy = (y + offset * dy)
+                    .clamp(0, height as isize - 1);
+                sum += weight
+                    * values[qy as usize * width
+                        + qx as usize];
+            }
+            out.push(sum);
+        }
+    }
+    out
+}
+
+/// D65 reference white.
+const WHITE: Vec3 = Vec3 {
+    x: 0.950_47,
+    y: 1.,
+    z: 1.088_83,
+};
+
+/// CIE XYZ of linear sRGB.
+fn xyz(c: Color) -> Vec3 {
+    let (r, g, b) = (c.r(), c.g(), c.b());
+    Vec3::new(
+        0.412_456_4 * r
+            + 0.357_576_1 * g
+            + 0.180_437_5 * b,
+        0.212_672_9 * r
+            + 0.715_152_2 * g
+            + 0.072_175 * b,
+        0.019_333_9 * r
+            + 0.119_192 * g
+            + 0.950_304_1 * b,
+    )
+}
+
+fn linear_rgb(v: Vec3) -> Color {
+    Color::new(
+        3.240_454_2 * v.x
+            - 1.537_138_5 * v.y
+            - 0.498_531_4 * v.z,
+        -0.969_266 * v.x
+            + 1.876_010_8 * v.y
+            + 0.041_556 * v.z,
+        0.055_643_4 * v.x - 0.204_025_9 * v.y
+            + 1.057_225_2 * v.z,
+    )
+}
+
+/// L*a*b* without its cube roots: an opponent space that blurring
+/// treats like light does.
+fn ycxcz(v: Vec3) -> Vec3 {
+    let (x, y, z) =
+        (v.x / WHITE.x, v.y / WHITE.y, v.z / WHITE.z);
+    Vec3::new(
+        116. * y - 16.,
+        500. * (x - y),
+        200. * (y - z),
+    )
+}
+
+fn xyz_from_ycxcz(v: Vec3) -> Vec3 {
+    let y = (v.x + 16.) / 116.;
+    Vec3::new(
+        (v.y / 500. + y) * WHITE.x,
+        y * WHITE.y,
+        (y - v.z / 200.) * WHITE.z,
+    )
+}
+
+/// CIE L*a*b*.
+fn lab(c: Color) -> Vec3 {
+    let v = xyz(c);
+    let f = |t: F| {
+        let delta: F = 6. / 29.;
+        if t > delta.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3. * delta * delta) + 4. / 29.
+        }
+    };
+    let (x, y, z) = (
+        f(v.x / WHITE.x),
+        f(v.y / WHITE.y),
+        f(v.z / WHITE.z),
+    );
+    Vec3::new(
+        116. * y - 16.,
+        500. * (x - y),
+        200. * (y - z),
+    )
+}
+
+/// Colours look less saturated in the dark.
+fn hunt(lab: Vec3) -> Vec3 {
+    Vec3::new(
+        lab.x,
+        0.01 * lab.x * lab.y,
+        0.01 * lab.x * lab.z,
+    )
+}
+
+/// Colour distance: lightness and chroma differences added, since
+/// large differences don't combine like small ones.
+fn hyab(a: Vec3, b: Vec3) -> F {
+    let d = a - b;
+    d.x.abs() + (d.y * d.y + d.z * d.z).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// A soft gradient with a bright square in the middle.
+    fn picture() -> Image {
+        let mut image = Image::new(32, 24);
+        for y in 0..24 {
+            for x in 0..32 {
+                let square = (8..24).contains(&x)
+                    && (6..18).contains(&y);
+                image.set(
+                    x,
+                    y,
+                    if square {
+                        Color::new(0.9, 0.7, 0.2)
+                    } else {
+                        Color::new(
+                            0.1,
+                            0.2,
+                            x as F / 40.,
+                        )
+                    },
+                );
+            }
+        }
+        image
+    }
+
+    fn noisy(image: &Image, amount: F) -> Image {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut out =
+            Image::new(image.width(), image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let c: Vec3 = image.get(x, y).into();
+                let noise = amount
+                    * rng.gen_range(-1.0..1.0 as F);
+                out.set(
+                    x,
+                    y,
+                    (c + Vec3::from(noise)).into(),
+                );
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn identical_images_match_perfectly() {
+        let image = picture();
+        let c =
+            Comparison::new(&image, &image).unwrap();
+        assert_eq!(c.mse, 0.);
+        assert_eq!(c.psnr, F::INFINITY);
+        assert!(
+            (c.ssim - 1.).abs() < 1e-4,
+            "{}",
+            c.ssim
+        );
+        assert!(c.flip < 1e-4, "{}", c.flip);
+    }
+
+    #[test]
+    fn more_noise_scores_worse() {
+        let image = picture();
+        let (slight, heavy) = (
+            Comparison::new(
+                &image,
+                &noisy(&image, 0.02),
+            )
+            .unwrap(),
+            Comparison::new(
+                &image,
+                &noisy(&image, 0.2),
+            )
+            .unwrap(),
+        );
+        assert!(slight.mse < heavy.mse);
+        assert!(slight.psnr > heavy.psnr);
+        assert!(slight.ssim > heavy.ssim);
+        assert!(slight.flip < heavy.flip);
+        assert!(heavy.ssim < 0.9 && heavy.flip > 0.05);
+        for c in [slight, heavy] {
+            assert!((0. ..=1.).contains(&c.flip));
+        }
+    }
+
+    #[test]
+    fn colour_spaces_invert() {
+        let c = Color::new(0.2, 0.5, 0.9);
+        let back: Vec3 =
+            linear_rgb(xyz_from_ycxcz(ycxcz(xyz(c))))
+                .into();
+        assert!((back - Vec3::from(c)).length() < 1e-4);
+        let white = lab(Color::white());
+        assert!(
+            (white - Vec3::new(100., 0., 0.)).length()
+                < 0.01
+        );
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        assert!(Comparison::new(
+            &Image::new(2, 2),
+            &Image::new(2, 3)
+        )
+        .is_err());
+    }
+}
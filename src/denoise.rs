@@ -0,0 +1,346 @@
+//! Cleaning up noisy renders, guided by what camera rays first hit.
+//!
+//! An edge-avoiding à-trous wavelet filter (Dammertz et al. 2010):
+//! repeated 5x5 blurs with ever wider gaps between taps, each tap
+//! weighted down where the normal, surface plane or brightness
+//! changes, so edges and shadows stay sharp.
+
+use crate::color::Color;
+use crate::image::Image;
+use crate::vec3::{Vec3, F};
+
+/// B3 spline, separable.
+const KERNEL: [F; 5] =
+    [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+/// Taps reach 2 * 2^(ITERATIONS - 1) pixels out on the last pass.
+const ITERATIONS: u32 = 5;
+/// Higher keeps creases sharper.
+const NORMAL_POWER: i32 = 64;
+/// How far off a pixel's plane a neighbour can be, as the sine of
+/// the angle between them.
+const PLANE_SIGMA: F = 0.2;
+/// How many local standard deviations of brightness apart still
+/// count as noise.
+const COLOR_SIGMA: F = 4.;
+
+/// The AOVs the denoiser is guided by, all the image's size.
+pub struct Guides<'a> {
+    pub albedo: &'a Image,
+    pub normal: &'a Image,
+    pub position: &'a Image,
+}
+
+/// A less noisy version of `image`, still in linear light.
+pub fn denoise(
+    image: &Image,
+    guides: &Guides,
+) -> Image {
+    let (width, height) =
+        (image.width(), image.height());
+    let pixels = |image: &Image| -> Vec<Vec3> {
+        (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| (x, y))
+            })
+            .map(|(x, y)| image.get(x, y).into())
+            .collect()
+    };
+    let albedo: Vec<Vec3> = pixels(guides.albedo)
+        .into_iter()
+        .map(|a| per_channel(a, |c| c.max(0.01)))
+        .collect();
+    let normals: Vec<Vec3> = pixels(guides.normal)
+        .into_iter()
+        .map(|n| {
+            if n.squared_length() < 1e-6 {
+                Vec3::ZERO
+            } else {
+                n.unit()
+            }
+        })
+        .collect();
+    let positions = pixels(guides.position);
+
+    // Filter the light falling on surfaces, not their texture, which
+    // is put back at the end.
+    let mut lighting: Vec<Vec3> = pixels(image)
+        .into_iter()
+        .zip(&albedo)
+        .map(|(c, a)| c / *a)
+        .collect();
+
+    for iteration in 0..ITERATIONS {
+        let step = 1isize << iteration;
+        let luminance: Vec<F> = lighting
+            .iter()
+            .map(|&c| Color::from(c).luminance())
+            .collect();
+        let deviation =
+            local_deviation(&luminance, width, height);
+
+        let mut next =
+            Vec::with_capacity(lighting.len());
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let mut sum = Vec3::ZERO;
+                let mut weights = 0.;
+                for (j, ky) in KERNEL.iter().enumerate()
+                {
+                    let qy = y as isize
+                        + (j as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (i, kx) in
+                        KERNEL.iter().enumerate()
+                    {
+                        let qx = x as isize
+                            + (i as isize - 2) * step;
+                        if qx < 0
+                            || qx >= width as isize
+                        {
+                            continue;
+                        }
+                        let q = qy as usize * width
+                            + qx as usize;
+                        let weight = kx
+                            * ky
+                            * geometry_weight(
+                                (
+                                    normals[p],
+                                    positions[p],
+                                ),
+                                (
+                                    normals[q],
+                                    positions[q],
+                                ),
+                            )
+                            * (-(luminance[p]
+                                - luminance[q])
+                                .abs()
+                                / (COLOR_SIGMA
+                                    * deviation[p]
+                                    + 1e-4))
+                                .exp();
+                        sum += lighting[q] * weight;
+                        weights += weight;
+                    }
+                }
+                // The pixel itself always has some weight.
+                next.push(sum / weights);
+            }
+        }
+        lighting = next;
+    }
+
+    let mut out = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let p = y * width + x;
+            out.set(
+                x,
+                y,
+                (lighting[p] * albedo[p]).into(),
+            );
+        }
+    }
+    out
+}
+
+fn per_channel(v: Vec3, f: impl Fn(F) -> F) -> Vec3 {
+    Vec3::new(f(v.x), f(v.y), f(v.z))
+}
+
+/// How alike two pixels' surfaces are, from 0 to 1. Zero normals
+/// mean nothing was hit.
+fn geometry_weight(
+    (normal_p, position_p): (Vec3, Vec3),
+    (normal_q, position_q): (Vec3, Vec3),
+) -> F {
+    let (missed_p, missed_q) = (
+        normal_p == Vec3::ZERO,
+        normal_q == Vec3::ZERO,
+    );
+    if missed_p || missed_q {
+        return if missed_p == missed_q {
+            1.
+        } else {
+            0.
+        };
+    }
+    let normal_weight = normal_p
+        .dot(&normal_q)
+        .max(0.)
+        .powi(NORMAL_POWER);
+    let offset = position_q - position_p;
+    let distance = offset.length();
+    if distance < 1e-6 {
+        return normal_weight;
+    }
+    let off_plane =
+        normal_p.dot(&offset).abs() / distance;
+    normal_weight
+        * (-(off_plane * off_plane)
+            / (PLANE_SIGMA * PLANE_SIGMA))
+            .exp()
+}
+
+/// Standard deviation of each pixel's 3x3 neighbourhood.
+fn local_deviation(
+    values: &[F],
+    width: usize,
+    height: usize,
+) -> Vec<F> {
+    let mut out = Vec::with_capacity(values.len());
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut sum_squares, mut n) =
+                (0., 0., 0.);
+            for qy in
+                y.saturating_sub(1)..(y + 2).min(height)
+            {
+                for qx in x.saturating_sub(1)
+                    ..(x + 2).min(width)
+                {
+                    let v = values[qy * width + qx];
+                    sum += v;
+                    sum_squares += v * v;
+                    n += 1.;
+                }
+            }
+            let mean = sum / n;
+            out.push(
+                (sum_squares / n - mean * mean)
+                    .max(0.)
+                    .sqrt(),
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// A wall facing the camera, split into a left half facing +z and
+    /// a right half facing +x.
+    fn guides(
+        width: usize,
+        height: usize,
+    ) -> [Image; 3] {
+        let mut albedo = Image::new(width, height);
+        let mut normal = Image::new(width, height);
+        let mut position = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                albedo.set(
+                    x,
+                    y,
+                    Color::new(0.5, 0.5, 0.5),
+                );
+                let n = if x < width / 2 {
+                    Vec3::new(0., 0., 1.)
+                } else {
+                    Vec3::new(1., 0., 0.)
+                };
+                normal.set(x, y, n.into());
+                position.set(
+                    x,
+                    y,
+                    Vec3::new(x as F, y as F, 0.)
+                        .into(),
+                );
+            }
+        }
+        [albedo, normal, position]
+    }
+
+    #[test]
+    fn smooths_noise_and_keeps_edges() {
+        let (width, height) = (32, 16);
+        let mut rng = StdRng::seed_from_u64(1);
+        let truth = |x: usize| {
+            if x < width / 2 {
+                0.2
+            } else {
+                0.8
+            }
+        };
+        let mut noisy = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let v =
+                    truth(x) * rng.gen_range(0.0..2.0);
+                noisy.set(x, y, Color::new(v, v, v));
+            }
+        }
+        let [albedo, normal, position] =
+            guides(width, height);
+        let denoised = denoise(
+            &noisy,
+            &Guides {
+                albedo: &albedo,
+                normal: &normal,
+                position: &position,
+            },
+        );
+        let error = |image: &Image| -> F {
+            let mut sum = 0.;
+            for y in 0..height {
+                for x in 0..width {
+                    sum += (image.get(x, y).r()
+                        - truth(x))
+                    .powi(2);
+                }
+            }
+            (sum / (width * height) as F).sqrt()
+        };
+        assert!(
+            error(&denoised) < error(&noisy) / 3.,
+            "{} {}",
+            error(&denoised),
+            error(&noisy)
+        );
+        // Either side of the crease keeps its own brightness.
+        let (left, right) = (
+            denoised.get(width / 2 - 1, height / 2).r(),
+            denoised.get(width / 2, height / 2).r(),
+        );
+        assert!((left - 0.2).abs() < 0.1, "{}", left);
+        assert!((right - 0.8).abs() < 0.2, "{}", right);
+    }
+
+    #[test]
+    fn leaves_clean_images_alone() {
+        let [albedo, normal, position] = guides(8, 8);
+        let mut image = Image::new(8, 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                image.set(
+                    x,
+                    y,
+                    Color::new(0.3, 0.2, 0.1),
+                );
+            }
+        }
+        let denoised = denoise(
+            &image,
+            &Guides {
+                albedo: &albedo,
+                normal: &normal,
+                position: &position,
+            },
+        );
+        for y in 0..8 {
+            for x in 0..8 {
+                let d: Vec3 = denoised.get(x, y).into();
+                let i: Vec3 = image.get(x, y).into();
+                assert!((d - i).length() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub use crate::parse::ParseError;
+
+/// Everything that can go wrong rendering, reading or writing.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed, at `path` if we know it.
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// An image file extension we can't read or write.
+    UnsupportedFormat { extension: String },
+    /// A scene, model or image file that doesn't make sense.
+    Parse(ParseError),
+    /// Options or arguments that can't be used together, or at all.
+    InvalidParameter(String),
+    /// A rendering thread panicked, with its message.
+    WorkerPanic(String),
+}
+
+impl Error {
+    /// For `map_err` on I/O to or from `path`.
+    pub(crate) fn io(
+        path: &Path,
+    ) -> impl FnOnce(io::Error) -> Self + '_ {
+        move |source| Error::Io {
+            path: Some(path.to_owned()),
+            source,
+        }
+    }
+
+    /// Blame `path` for an I/O error that doesn't say where it
+    /// happened.
+    pub(crate) fn in_file(self, path: &Path) -> Self {
+        match self {
+            Error::Io { path: None, source } => {
+                Error::Io {
+                    path: Some(path.to_owned()),
+                    source,
+                }
+            }
+            e => e,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {}", path.display(), source),
+            Error::Io { path: None, source } => {
+                write!(f, "{}", source)
+            }
+            Error::UnsupportedFormat { extension } => write!(
+                f,
+                "Unsupported image type {:?}. Perhaps you want '.png'?",
+                extension
+            ),
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::InvalidParameter(message)
+            | Error::WorkerPanic(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(
+        &self,
+    ) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Parse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Self {
+        Error::Parse(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_errors_learn_their_path_once() {
+        let error = Error::from(io::Error::new(
+            io::ErrorKind::NotFound,
+            "gone",
+        ));
+        assert_eq!(error.to_string(), "gone");
+        let error = error.in_file(Path::new("a.png"));
+        assert_eq!(error.to_string(), "a.png: gone");
+        assert_eq!(
+            error
+                .in_file(Path::new("b.png"))
+                .to_string(),
+            "a.png: gone"
+        );
+    }
+}
@@ -0,0 +1,803 @@
+//! Scanline OpenEXR images, uncompressed or ZIP compressed.
+//!
+//! Only what we need to hand linear renders to compositors: half or
+//! float channels, optionally grouped in named layers.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Display, Formatter};
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::color::Color;
+use crate::image::{self, Image};
+use crate::vec3::Vec3;
+use crate::zlib;
+use crate::{Error, Res};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+/// How scanlines are compressed.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+pub enum Compression {
+    None,
+    /// zlib, 16 scanlines at a time.
+    #[default]
+    Zip,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zip => 3,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zip" => Ok(Compression::Zip),
+            _ => Err(format!(
+                "expected \"none\" or \"zip\", not {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+/// How each channel value is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+impl PixelType {
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Float => 4,
+        }
+    }
+}
+
+/// Nearest half-precision float, rounding ties to even. Too large
+/// values become infinite.
+pub fn f32_to_f16(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let round = |value: u32, shift: u32| {
+        let rest = value & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let truncated = value >> shift;
+        if rest > halfway
+            || (rest == halfway && truncated & 1 == 1)
+        {
+            truncated + 1
+        } else {
+            truncated
+        }
+    };
+    let e = exponent - 127 + 15;
+    if e >= 31 {
+        sign | 0x7c00
+    } else if e <= 0 {
+        // Subnormal, or too small for even that.
+        if e < -10 {
+            return sign;
+        }
+        sign | round(
+            mantissa | 0x80_0000,
+            (14 - e) as u32,
+        ) as u16
+    } else {
+        // Rounding up may carry into the exponent, which is right.
+        sign | round((e as u32) << 23 | mantissa, 13)
+            as u16
+    }
+}
+
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = if h & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * (2f32).powi(-24),
+        0x1f if mantissa == 0. => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => {
+            sign * (1. + mantissa / 1024.)
+                * (2f32).powi(exponent - 15)
+        }
+    }
+}
+
+/// One channel of one layer, as EXR names it: "R", or "albedo.R".
+struct Channel<'a> {
+    name: String,
+    image: &'a Image,
+    /// 0, 1 or 2 for red, green or blue.
+    component: usize,
+}
+
+/// Write layers of RGB images, all the same size, to one file. The
+/// layer named "" holds the main image.
+pub fn write(
+    mut out: impl Write,
+    layers: &[(&str, &Image)],
+    pixel_type: PixelType,
+    compression: Compression,
+) -> Res<()> {
+    let (width, height) = match layers.first() {
+        Some((_, image)) => {
+            (image.width(), image.height())
+        }
+        None => {
+            return Err(Error::InvalidParameter(
+                "no layers to write".to_owned(),
+            ))
+        }
+    };
+    if layers.iter().any(|(_, i)| {
+        i.width() != width || i.height() != height
+    }) {
+        return Err(Error::InvalidParameter(
+            "all EXR layers must be the same size"
+                .to_owned(),
+        ));
+    }
+
+    let mut channels: Vec<Channel> = layers
+        .iter()
+        .flat_map(|&(layer, image)| {
+            ["R", "G", "B"].iter().enumerate().map(
+                move |(component, c)| Channel {
+                    name: if layer.is_empty() {
+                        c.to_string()
+                    } else {
+                        format!("{}.{}", layer, c)
+                    },
+                    image,
+                    component,
+                },
+            )
+        })
+        .collect();
+    // Readers expect channels in name order.
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = vec![];
+    let mut attribute =
+        |name: &str, kind: &str, value: &[u8]| {
+            header.extend(name.as_bytes());
+            header.push(0);
+            header.extend(kind.as_bytes());
+            header.push(0);
+            header.extend(
+                (value.len() as i32).to_le_bytes(),
+            );
+            header.extend(value);
+        };
+    let mut list = vec![];
+    for channel in &channels {
+        list.extend(channel.name.as_bytes());
+        list.push(0);
+        let id: i32 = match pixel_type {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        };
+        list.extend(id.to_le_bytes());
+        // Not perceptually linear, reserved bytes, then x and y sampling.
+        list.extend([0, 0, 0, 0]);
+        list.extend(1i32.to_le_bytes());
+        list.extend(1i32.to_le_bytes());
+    }
+    list.push(0);
+    attribute("channels", "chlist", &list);
+    attribute(
+        "compression",
+        "compression",
+        &[compression.id()],
+    );
+    let window: Vec<u8> =
+        [0, 0, width as i32 - 1, height as i32 - 1]
+            .iter()
+            .flat_map(|n| n.to_le_bytes())
+            .collect();
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    // Increasing y, which is top to bottom.
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute(
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    let lines_per_chunk = match compression {
+        Compression::None => 1,
+        Compression::Zip => 16,
+    };
+    let mut chunks = vec![];
+    for first in (0..height).step_by(lines_per_chunk) {
+        let mut data = vec![];
+        for y in
+            first..(first + lines_per_chunk).min(height)
+        {
+            // Our images count rows up from the bottom.
+            let row = height - 1 - y;
+            for channel in &channels {
+                for x in 0..width {
+                    let v: Vec3 = channel
+                        .image
+                        .get(x, row)
+                        .into();
+                    let v = [v.x, v.y, v.z]
+                        [channel.component];
+                    match pixel_type {
+                        PixelType::Half => data.extend(
+                            f32_to_f16(v).to_le_bytes(),
+                        ),
+                        PixelType::Float => {
+                            data.extend(v.to_le_bytes())
+                        }
+                    }
+                }
+            }
+        }
+        let data = match compression {
+            Compression::None => data,
+            Compression::Zip => {
+                let packed =
+                    zlib::compress(&zip_predict(&data));
+                // Stored raw when compression doesn't help.
+                if packed.len() < data.len() {
+                    packed
+                } else {
+                    data
+                }
+            }
+        };
+        chunks.push((first as i32, data));
+    }
+
+    out.write_all(&MAGIC)?;
+    // Version 2, single part scanline.
+    out.write_all(&2u32.to_le_bytes())?;
+    out.write_all(&header)?;
+    let mut offset =
+        (8 + header.len() + 8 * chunks.len()) as u64;
+    for (_, data) in &chunks {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+    for (y, data) in &chunks {
+        out.write_all(&y.to_le_bytes())?;
+        out.write_all(
+            &(data.len() as i32).to_le_bytes(),
+        )?;
+        out.write_all(data)?;
+    }
+    Ok(())
+}
+
+/// Split even and odd bytes apart, then store differences, so zlib
+/// sees smoother data.
+fn zip_predict(data: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = data
+        .iter()
+        .step_by(2)
+        .chain(data.iter().skip(1).step_by(2))
+        .copied()
+        .collect();
+    let mut previous =
+        out.first().copied().unwrap_or_default();
+    for b in out.iter_mut().skip(1) {
+        let d =
+            b.wrapping_sub(previous).wrapping_add(128);
+        previous = *b;
+        *b = d;
+    }
+    out
+}
+
+/// Undo `zip_predict`.
+fn zip_unpredict(data: &[u8]) -> Vec<u8> {
+    let mut t = data.to_vec();
+    for i in 1..t.len() {
+        t[i] = t[i - 1]
+            .wrapping_add(t[i])
+            .wrapping_sub(128);
+    }
+    let (even, odd) = t.split_at(t.len().div_ceil(2));
+    let mut out = Vec::with_capacity(t.len());
+    for i in 0..t.len() {
+        out.push(if i % 2 == 0 {
+            even[i / 2]
+        } else {
+            odd[i / 2]
+        });
+    }
+    out
+}
+
+/// Read the main RGB (or Y) layer of a scanline EXR image.
+pub fn read(bytes: &[u8]) -> Result<Image, String> {
+    let cut_short =
+        || "EXR data is cut short".to_owned();
+    if bytes.get(..4) != Some(&MAGIC[..]) {
+        return Err("not an OpenEXR image".to_owned());
+    }
+    let flags =
+        bytes.get(4..8).ok_or_else(cut_short)?;
+    // Tiled, deep or multi-part images have bits set here.
+    if flags[0] != 2 || flags[1] & 0x1a != 0 {
+        return Err(
+            "only single-part scanline EXR images are supported"
+                .to_owned(),
+        );
+    }
+
+    let mut at = 8;
+    let string =
+        |at: &mut usize| -> Result<String, String> {
+            let end = bytes[*at..]
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(cut_short)?;
+            let s = String::from_utf8_lossy(
+                &bytes[*at..*at + end],
+            )
+            .into_owned();
+            *at += end + 1;
+            Ok(s)
+        };
+    let mut attributes: HashMap<String, &[u8]> =
+        HashMap::new();
+    loop {
+        let name = string(&mut at)?;
+        if name.is_empty() {
+            break;
+        }
+        let _kind = string(&mut at)?;
+        let size = bytes
+            .get(at..at + 4)
+            .map(|s| {
+                i32::from_le_bytes([
+                    s[0], s[1], s[2], s[3],
+                ])
+            })
+            .ok_or_else(cut_short)?;
+        let size =
+            usize::try_from(size).map_err(|_| {
+                format!(
+                    "EXR {} attribute size is corrupt",
+                    name
+                )
+            })?;
+        let value = bytes
+            .get(at + 4..)
+            .and_then(|b| b.get(..size))
+            .ok_or_else(cut_short)?;
+        attributes.insert(name, value);
+        at += 4 + size;
+    }
+    let attribute = |name: &str| {
+        attributes.get(name).copied().ok_or(format!(
+            "EXR header has no {}",
+            name
+        ))
+    };
+    let i32_at = |b: &[u8], i: usize| {
+        i32::from_le_bytes([
+            b[i],
+            b[i + 1],
+            b[i + 2],
+            b[i + 3],
+        ])
+    };
+
+    let window = attribute("dataWindow")?;
+    if window.len() != 16 {
+        return Err(
+            "EXR data window is corrupt".to_owned()
+        );
+    }
+    let (x_min, y_min) =
+        (i32_at(window, 0), i32_at(window, 4));
+    let size = |min: i32, max: i32| {
+        (max as i64 - min as i64 + 1).max(0) as usize
+    };
+    let width = size(x_min, i32_at(window, 8));
+    let height = size(y_min, i32_at(window, 12));
+    let lines_per_chunk = match attribute("compression")? {
+        [0] | [2] => 1,
+        [3] => 16,
+        [c] => {
+            return Err(format!(
+                "EXR compression type {} isn't supported, only none and ZIP",
+                c
+            ))
+        }
+        _ => return Err("EXR compression is corrupt".to_owned()),
+    };
+
+    // Name, type, and where each channel's values start in a scanline,
+    // in bytes per pixel of width.
+    let list = attribute("channels")?;
+    let mut channels = vec![];
+    let mut i = 0;
+    let mut pixel_bytes = 0;
+    while i < list.len() && list[i] != 0 {
+        let end = list[i..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or("EXR channel list is corrupt")?;
+        let name =
+            String::from_utf8_lossy(&list[i..i + end])
+                .into_owned();
+        i += end + 1;
+        if i + 16 > list.len() {
+            return Err("EXR channel list is corrupt"
+                .to_owned());
+        }
+        let pixel_type = match i32_at(list, i) {
+            1 => PixelType::Half,
+            2 => PixelType::Float,
+            t => {
+                return Err(format!(
+                    "EXR pixel type {} isn't supported, only half and float",
+                    t
+                ))
+            }
+        };
+        if i32_at(list, i + 8) != 1
+            || i32_at(list, i + 12) != 1
+        {
+            return Err(
+                "subsampled EXR channels aren't supported".to_owned()
+            );
+        }
+        i += 16;
+        channels.push((name, pixel_type, pixel_bytes));
+        pixel_bytes += pixel_type.size();
+    }
+    let find = |name: &str| {
+        channels.iter().find(|c| c.0 == name)
+    };
+    let components = match (
+        find("R"),
+        find("G"),
+        find("B"),
+        find("Y"),
+    ) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err(
+            "EXR image has no R, G and B or Y channels"
+                .to_owned(),
+        ),
+    };
+
+    // Deflate shrinks data by at most 1032 times.
+    let most_bytes = match attribute("compression")? {
+        [0] => bytes.len(),
+        _ => bytes.len().saturating_mul(1032),
+    };
+    image::pixel_count(
+        width,
+        height,
+        most_bytes / pixel_bytes,
+    )?;
+    let line_bytes = width * pixel_bytes;
+    let chunk_count = height.div_ceil(lines_per_chunk);
+    let mut pixels = vec![0u8; line_bytes * height];
+    for c in 0..chunk_count {
+        let offset = bytes
+            .get(at + 8 * c..at + 8 * c + 8)
+            .map(|o| {
+                u64::from_le_bytes([
+                    o[0], o[1], o[2], o[3], o[4], o[5],
+                    o[6], o[7],
+                ])
+            })
+            .ok_or_else(cut_short)?;
+        let offset = usize::try_from(offset)
+            .map_err(|_| cut_short())?;
+        let chunk_header = bytes
+            .get(offset..)
+            .and_then(|b| b.get(..8))
+            .ok_or_else(cut_short)?;
+        let first = usize::try_from(
+            i64::from(i32_at(chunk_header, 0))
+                - i64::from(y_min),
+        )
+        .ok()
+        .filter(|&first| first < height)
+        .ok_or("EXR chunk is outside the image")?;
+        let size =
+            usize::try_from(i32_at(chunk_header, 4))
+                .map_err(|_| {
+                    "EXR chunk size is corrupt"
+                        .to_owned()
+                })?;
+        let data = bytes[offset + 8..]
+            .get(..size)
+            .ok_or_else(cut_short)?;
+        let lines = lines_per_chunk.min(height - first);
+        let expected = lines * line_bytes;
+        let data = if data.len() < expected {
+            zip_unpredict(&zlib::decompress(
+                data, expected,
+            )?)
+        } else {
+            data.to_vec()
+        };
+        let start = first * line_bytes;
+        pixels
+            .get_mut(start..start + expected)
+            .ok_or("EXR chunk is outside the image")?
+            .copy_from_slice(
+                data.get(..expected)
+                    .ok_or_else(cut_short)?,
+            );
+    }
+
+    let value =
+        |y: usize,
+         x: usize,
+         channel: &(String, PixelType, usize)| {
+            let i = y * line_bytes
+                + channel.2 * width
+                + x * channel.1.size();
+            let b = &pixels[i..];
+            match channel.1 {
+                PixelType::Half => {
+                    f16_to_f32(u16::from_le_bytes([
+                        b[0], b[1],
+                    ]))
+                }
+                PixelType::Float => {
+                    f32::from_le_bytes([
+                        b[0], b[1], b[2], b[3],
+                    ])
+                }
+            }
+        };
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b] =
+                components.map(|c| value(y, x, c));
+            image.set(
+                x,
+                height - 1 - y,
+                Color::new(r as _, g as _, b as _),
+            );
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn half_floats() {
+        for (f, h) in [
+            (0., 0x0000),
+            (-0., 0x8000),
+            (1., 0x3c00),
+            (-2., 0xc000),
+            (65504., 0x7bff),
+            (1e6, 0x7c00),
+            (f32::INFINITY, 0x7c00),
+            // Smallest subnormal.
+            ((2f32).powi(-24), 0x0001),
+            (0.333_333_34, 0x3555),
+        ] {
+            assert_eq!(f32_to_f16(f), h, "{}", f);
+        }
+        // Ties go to even.
+        assert_eq!(
+            f32_to_f16(1. + (2f32).powi(-11)),
+            0x3c00
+        );
+        assert_eq!(
+            f32_to_f16(1. + 3. * (2f32).powi(-11)),
+            0x3c02
+        );
+        assert!(
+            f16_to_f32(f32_to_f16(f32::NAN)).is_nan()
+        );
+        for h in (0..0x7c00).step_by(7) {
+            assert_eq!(f32_to_f16(f16_to_f32(h)), h);
+        }
+    }
+
+    #[test]
+    fn zip_predictor_inverts() {
+        let data: Vec<u8> = (0..101u32)
+            .map(|i| (i * i) as u8)
+            .collect();
+        assert_eq!(
+            zip_unpredict(&zip_predict(&data)),
+            data
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut image = Image::new(5, 20);
+        for y in 0..20 {
+            for x in 0..5 {
+                image.set(
+                    x,
+                    y,
+                    Color::new(
+                        x as _,
+                        y as f32 * 10.,
+                        0.25,
+                    ),
+                );
+            }
+        }
+        for pixel_type in
+            [PixelType::Half, PixelType::Float]
+        {
+            for compression in
+                [Compression::None, Compression::Zip]
+            {
+                let mut bytes = vec![];
+                write(
+                    &mut bytes,
+                    &[("", &image)],
+                    pixel_type,
+                    compression,
+                )
+                .unwrap();
+                assert_eq!(
+                    read(&bytes).unwrap(),
+                    image
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn huge_data_windows_are_errors() {
+        let mut bytes = vec![];
+        write(
+            &mut bytes,
+            &[("", &Image::new(2, 2))],
+            PixelType::Half,
+            Compression::None,
+        )
+        .unwrap();
+        // The maximum corner of the data window.
+        let window = bytes
+            .windows(10)
+            .position(|w| w == b"dataWindow")
+            .unwrap()
+            + 10
+            + 1
+            + 5
+            + 1
+            + 4;
+        bytes[window + 8..window + 16].copy_from_slice(
+            &[0xff, 0xff, 0xff, 0x7f].repeat(2),
+        );
+        assert_eq!(
+            read(&bytes).err().unwrap(),
+            "image data is cut short"
+        );
+    }
+
+    #[test]
+    fn bad_chunk_tables_are_errors() {
+        let mut bytes = vec![];
+        write(
+            &mut bytes,
+            &[("", &Image::new(2, 2))],
+            PixelType::Half,
+            Compression::None,
+        )
+        .unwrap();
+        // Two one-line chunks, the first right after the table.
+        let u64_at = |b: &[u8], i: usize| {
+            u64::from_le_bytes(
+                b[i..i + 8].try_into().unwrap(),
+            ) as usize
+        };
+        let table = (0..bytes.len() - 8)
+            .find(|&i| u64_at(&bytes, i) == i + 16)
+            .unwrap();
+        let chunk = table + 16;
+        let error = |change: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = bytes.clone();
+            change(&mut bytes);
+            read(&bytes).err().unwrap()
+        };
+
+        assert_eq!(
+            error(&|b| b.truncate(table + 12)),
+            "EXR data is cut short"
+        );
+        assert_eq!(
+            error(&|b| b[table..table + 8]
+                .copy_from_slice(&[0xff; 8])),
+            "EXR data is cut short"
+        );
+        assert_eq!(
+            error(&|b| b[chunk..chunk + 4]
+                .copy_from_slice(
+                    &i32::MIN.to_le_bytes()
+                )),
+            "EXR chunk is outside the image"
+        );
+        assert_eq!(
+            error(&|b| b[chunk + 4..chunk + 8]
+                .copy_from_slice(
+                    &(-8i32).to_le_bytes()
+                )),
+            "EXR chunk size is corrupt"
+        );
+        assert_eq!(
+            error(&|b| b[chunk + 4..chunk + 8]
+                .copy_from_slice(
+                    &i32::MAX.to_le_bytes()
+                )),
+            "EXR data is cut short"
+        );
+        // The first attribute's size.
+        assert_eq!(
+            error(&|b| {
+                let at = b
+                    .windows(9)
+                    .position(|w| w == b"channels\0")
+                    .unwrap()
+                    + 9
+                    + b"chlist\0".len();
+                b[at..at + 4].copy_from_slice(
+                    &(-1i32).to_le_bytes(),
+                )
+            }),
+            "EXR channels attribute size is corrupt"
+        );
+    }
+}
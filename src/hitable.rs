@@ -1,12 +1,17 @@
+use crate::aabb::Aabb;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::{Vec3, F};
+use crate::vec3::{Vec3, F, PI};
 use std::ops::Range;
+use std::sync::Arc;
 
 pub struct Hit<'a> {
     pub t: F,
     pub p: Vec3,
     pub normal: Vec3,
+    /// Surface coordinates, for textures.
+    pub u: F,
+    pub v: F,
     pub material: &'a (dyn Material + Send + Sync),
 }
 
@@ -15,7 +20,81 @@ pub trait Hitable {
         &self,
         ray: &Ray,
         t: &Range<F>,
-    ) -> Option<Hit>;
+    ) -> Option<Hit<'_>>;
+
+    /// Box enclosing the whole object, or None if it's unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Whether this glows, and can be sampled as a light with
+    /// `random_direction_toward` and `pdf_toward`.
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    /// A random unit direction from `origin` towards this object,
+    /// picked by a `sample` from 0 up to 1 in each dimension.
+    fn random_direction_toward(
+        &self,
+        _origin: &Vec3,
+        _sample: (F, F),
+    ) -> Option<Vec3> {
+        None
+    }
+
+    /// Probability density (per solid angle) of `random_direction_toward`
+    /// giving unit `direction`.
+    fn pdf_toward(
+        &self,
+        _origin: &Vec3,
+        _direction: &Vec3,
+    ) -> F {
+        0.
+    }
+}
+
+/// A shared object is hit where the object is, so one loaded mesh can
+/// be added to a scene without copying it.
+impl<H: Hitable + ?Sized> Hitable for Arc<H> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        (**self).hit(ray, t)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn is_light(&self) -> bool {
+        (**self).is_light()
+    }
+
+    fn random_direction_toward(
+        &self,
+        origin: &Vec3,
+        sample: (F, F),
+    ) -> Option<Vec3> {
+        (**self).random_direction_toward(origin, sample)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> F {
+        (**self).pdf_toward(origin, direction)
+    }
+}
+
+/// Longitude and latitude of a point on the unit sphere, each 0-1.
+///
+/// `u` goes round from -x through +z, +x, and -z; `v` goes up from -y to +y.
+pub fn sphere_uv(p: &Vec3) -> (F, F) {
+    let theta = (-p.y).clamp(-1., 1.).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2. * PI), theta / PI)
 }
 
 pub struct Sphere<'a> {
@@ -25,48 +104,300 @@ pub struct Sphere<'a> {
 }
 
 impl Hitable for Sphere<'_> {
-    /// Does the ray hit our sphere?
-    /// If so, return the time t of the hit.
     fn hit(
         &self,
         ray: &Ray,
         within_t: &Range<F>,
-    ) -> Option<Hit> {
-        let oc: Vec3 = ray.origin - self.center;
-        let a = ray.direction.squared_length();
-        let b = oc.dot(&ray.direction);
-        let c = oc.squared_length()
-            - self.radius * self.radius;
-        let discriminant = b * b - a * c;
-
-        if discriminant <= 0. {
+    ) -> Option<Hit<'_>> {
+        hit_sphere(
+            ray,
+            within_t,
+            &self.center,
+            self.radius,
+            self.material.as_ref(),
+        )
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Uniform over the cone of directions the sphere covers.
+    fn random_direction_toward(
+        &self,
+        origin: &Vec3,
+        (s, t): (F, F),
+    ) -> Option<Vec3> {
+        let cos_theta_max =
+            self.cos_theta_max(origin)?;
+        let w = (self.center - *origin).unit();
+        let (u, v) = w.orthonormal_basis();
+
+        let phi = 2. * PI * s;
+        let cos_theta = 1. + t * (cos_theta_max - 1.);
+        let sin_theta =
+            (1. - cos_theta * cos_theta).max(0.).sqrt();
+        Some(
+            u * (phi.cos() * sin_theta)
+                + v * (phi.sin() * sin_theta)
+                + w * cos_theta,
+        )
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> F {
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max)
+                if direction.dot(
+                    &(self.center - *origin).unit(),
+                ) >= cos_theta_max =>
+            {
+                1. / (2. * PI * (1. - cos_theta_max))
+            }
+            _ => 0.,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Radius may be negative (for hollow glass)
+        let r = Vec3::from(self.radius.abs());
+        Some(Aabb::new(
+            self.center - r,
+            self.center + r,
+        ))
+    }
+}
+
+impl Sphere<'_> {
+    /// Cosine of the half-angle the sphere covers, seen from `origin`.
+    /// None if `origin` is inside it.
+    fn cos_theta_max(
+        &self,
+        origin: &Vec3,
+    ) -> Option<F> {
+        let distance_squared =
+            (self.center - *origin).squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
             return None;
         }
+        Some(
+            (1. - radius_squared / distance_squared)
+                .sqrt(),
+        )
+    }
+}
 
-        let hit_t = |t: F| {
-            if within_t.contains(&t) {
-                let p = ray.point_at(t);
-                return Some(Hit {
-                    t,
-                    p,
-                    normal: (p - self.center)
-                        / self.radius,
-                    material: self.material.as_ref(),
-                });
-            }
-            None
-        };
+/// A sphere moving in a straight line, from `start` at time 0 to `end`
+/// at time 1, and staying put before and after.
+///
+/// Not sampled as a light, but glows when hit.
+pub struct MovingSphere {
+    pub start: Vec3,
+    pub end: Vec3,
+    pub radius: F,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: F) -> Vec3 {
+        self.start
+            + (self.end - self.start)
+                * time.clamp(0., 1.)
+    }
+}
+
+impl Hitable for MovingSphere {
+    fn hit(
+        &self,
+        ray: &Ray,
+        within_t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        hit_sphere(
+            ray,
+            within_t,
+            &self.center(ray.time),
+            self.radius,
+            self.material.as_ref(),
+        )
+    }
+
+    /// Everywhere it goes.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vec3::from(self.radius.abs());
+        Some(Aabb::new(
+            self.start.min(&self.end) - r,
+            self.start.max(&self.end) + r,
+        ))
+    }
+}
+
+/// Does the ray hit the sphere? Negative radii turn normals inwards.
+fn hit_sphere<'a>(
+    ray: &Ray,
+    within_t: &Range<F>,
+    center: &Vec3,
+    radius: F,
+    material: &'a (dyn Material + Send + Sync),
+) -> Option<Hit<'a>> {
+    let oc: Vec3 = ray.origin - *center;
+    let a = ray.direction.squared_length();
+    let b = oc.dot(&ray.direction);
+    let c = oc.squared_length() - radius * radius;
+    let discriminant = b * b - a * c;
+
+    if discriminant <= 0. {
+        return None;
+    }
 
-        let t = (-b - (b * b - a * c).sqrt()) / a;
-        if let Some(h) = hit_t(t) {
-            return Some(h);
+    let hit_t = |t: F| {
+        if within_t.contains(&t) {
+            let p = ray.point_at(t);
+            let normal = (p - *center) / radius;
+            let (u, v) =
+                sphere_uv(&(normal * radius.signum()));
+            return Some(Hit {
+                t,
+                p,
+                normal,
+                u,
+                v,
+                material,
+            });
         }
+        None
+    };
+
+    let t = (-b - discriminant.sqrt()) / a;
+    if let Some(h) = hit_t(t) {
+        return Some(h);
+    }
+
+    let t = (-b + discriminant.sqrt()) / a;
+    if let Some(h) = hit_t(t) {
+        return Some(h);
+    }
+
+    None
+}
 
-        let t = (-b + (b * b - a * c).sqrt()) / a;
-        if let Some(h) = hit_t(t) {
-            return Some(h);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::DiffuseLight;
+
+    #[test]
+    fn sphere_uv_corners() {
+        let close =
+            |(u, v): (F, F), (eu, ev): (F, F)| {
+                assert!(
+                    (u - eu).abs() < 1e-5
+                        && (v - ev).abs() < 1e-5
+                )
+            };
+        close(
+            sphere_uv(&Vec3::new(-1., 0., 0.)),
+            (0., 0.5),
+        );
+        close(
+            sphere_uv(&Vec3::new(0., 0., 1.)),
+            (0.25, 0.5),
+        );
+        close(
+            sphere_uv(&Vec3::new(1., 0., 0.)),
+            (0.5, 0.5),
+        );
+        assert_eq!(
+            sphere_uv(&Vec3::new(0., -1., 0.)).1,
+            0.
+        );
+        assert_eq!(
+            sphere_uv(&Vec3::new(0., 1., 0.)).1,
+            1.
+        );
+    }
+
+    #[test]
+    fn light_directions_hit_the_sphere() {
+        let sphere = Sphere {
+            center: Vec3::new(0., 5., 0.),
+            radius: 1.,
+            material: Box::new(DiffuseLight {
+                emit: Color::white(),
+            }),
+        };
+        assert!(sphere.is_light());
+
+        let origin = Vec3::ZERO;
+        let cone =
+            2. * PI * (1. - (24. as F).sqrt() / 5.);
+        for i in 0..100 {
+            let sample = (
+                i as F / 100.,
+                (i * 37 % 100) as F / 100.,
+            );
+            let direction = sphere
+                .random_direction_toward(
+                    &origin, sample,
+                )
+                .unwrap();
+            assert!(
+                (direction.length() - 1.).abs() < 1e-4
+            );
+            assert!(sphere
+                .hit(
+                    &Ray::new(origin, direction),
+                    &(0.0..F::INFINITY)
+                )
+                .is_some());
+            let pdf =
+                sphere.pdf_toward(&origin, &direction);
+            assert!((pdf * cone - 1.).abs() < 1e-2);
         }
+        let away = Vec3::new(0., -1., 0.);
+        assert_eq!(
+            sphere.pdf_toward(&origin, &away),
+            0.
+        );
+        // No directions from inside.
+        assert!(sphere
+            .random_direction_toward(
+                &Vec3::new(0., 5., 0.),
+                (0.5, 0.5)
+            )
+            .is_none());
+    }
 
-        None
+    #[test]
+    fn moving_spheres_are_where_they_are_at_the_time() {
+        let sphere = MovingSphere {
+            start: Vec3::ZERO,
+            end: Vec3::new(4., 0., 0.),
+            radius: 1.,
+            material: Box::new(DiffuseLight {
+                emit: Color::white(),
+            }),
+        };
+        let at = |time| Ray {
+            origin: Vec3::new(4., 0., 5.),
+            direction: Vec3::new(0., 0., -1.),
+            time,
+        };
+        let all = 0.0..F::INFINITY;
+        assert!(sphere.hit(&at(0.), &all).is_none());
+        let hit = sphere.hit(&at(1.), &all).unwrap();
+        assert!((hit.t - 4.).abs() < 1e-5);
+        assert_eq!(hit.normal, Vec3::new(0., 0., 1.));
+        // It stops at the end.
+        assert!(sphere.hit(&at(2.), &all).is_some());
+
+        let bounds = sphere.bounding_box().unwrap();
+        assert_eq!(bounds.min, Vec3::from(-1.));
+        assert_eq!(bounds.max, Vec3::new(5., 1., 1.));
+        assert!(!sphere.is_light());
     }
 }
@@ -1,32 +1,62 @@
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::{BufWriter, Write};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
 use std::{fmt, ops::AddAssign, path::Path};
 
 use crate::color::Color;
-use crate::vec3::F;
-use crate::Res;
+use crate::exr;
+use crate::parse::ParseError;
+use crate::tonemap::ToneMap;
+use crate::vec3::{Vec3, F};
 
+pub use crate::exr::Compression as ExrCompression;
+use crate::zlib;
+use crate::{Error, Res};
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Image {
     image: Vec<Vec<Color>>,
 }
 
-#[derive(Debug)]
-pub struct UnsupportedFormatError {
-    unsupported_extension: String,
+/// Bits per channel, for formats that offer a choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    /// Half floats, for formats that store floats.
+    Sixteen,
+    /// Only for formats that store floats.
+    ThirtyTwo,
 }
 
-impl Display for UnsupportedFormatError {
+impl Display for BitDepth {
     fn fmt(
         &self,
         f: &mut Formatter<'_>,
     ) -> fmt::Result {
-        write!(f, "Unsupported image type {:?}. Perhaps you want '.ff'?", &self.unsupported_extension)?;
-        Ok(())
+        match self {
+            BitDepth::Eight => write!(f, "8-bit"),
+            BitDepth::Sixteen => write!(f, "16-bit"),
+            BitDepth::ThirtyTwo => write!(f, "32-bit"),
+        }
     }
 }
 
-impl std::error::Error for UnsupportedFormatError {}
+impl FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            "32" => Ok(BitDepth::ThirtyTwo),
+            _ => Err(format!(
+                "expected 8, 16 or 32, not {:?}",
+                s
+            )),
+        }
+    }
+}
 
 impl Image {
     pub fn new(width: usize, height: usize) -> Self {
@@ -43,6 +73,46 @@ impl Image {
     pub fn height(&self) -> usize {
         self.image.len()
     }
+
+    /// Get a pixel. `y` counts up from the bottom row.
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.image[y][x]
+    }
+
+    /// Bilinearly filtered colour at `u` (left to right) and `v`
+    /// (bottom to top), repeating outside 0-1.
+    pub fn sample(&self, u: F, v: F) -> Color {
+        let (width, height) =
+            (self.width(), self.height());
+        // Pixel centres are at half coordinates.
+        let x = u.rem_euclid(1.) * width as F - 0.5;
+        let y = v.rem_euclid(1.) * height as F - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let pixel = |x: F, y: F| -> Vec3 {
+            let x = (x as i64).rem_euclid(width as i64)
+                as usize;
+            let y = (y as i64).rem_euclid(height as i64)
+                as usize;
+            self.get(x, y).into()
+        };
+        let bottom = pixel(x0, y0) * (1. - tx)
+            + pixel(x0 + 1., y0) * tx;
+        let top = pixel(x0, y0 + 1.) * (1. - tx)
+            + pixel(x0 + 1., y0 + 1.) * tx;
+        Color::from(bottom * (1. - ty) + top * ty)
+    }
+
+    /// Set a pixel. `y` counts up from the bottom row.
+    pub fn set(
+        &mut self,
+        x: usize,
+        y: usize,
+        color: Color,
+    ) {
+        self.image[y][x] = color;
+    }
     pub fn add_average(&mut self, others: &[Self]) {
         for (j, row) in
             self.image.iter_mut().enumerate()
@@ -59,11 +129,25 @@ impl Image {
         }
     }
 
-    pub fn average(images: &mut [Self]) -> Image {
+    /// The mean of images of the same size. There must be at least one.
+    pub fn average(images: &mut [Self]) -> Res<Image> {
         let len = images.len() as f32;
         let (first, remaining) = images
             .split_first()
-            .expect("cannot average zero images");
+            .ok_or_else(|| {
+            Error::InvalidParameter(
+                "can't average zero images".to_owned(),
+            )
+        })?;
+        if remaining.iter().any(|other| {
+            (other.width(), other.height())
+                != (first.width(), first.height())
+        }) {
+            return Err(Error::InvalidParameter(
+                "can't average images of different sizes"
+                    .to_owned(),
+            ));
+        }
         let mut image =
             Image::new(first.width(), first.height());
         for (j, row) in
@@ -78,7 +162,7 @@ impl Image {
                 *color = color.darken(len);
             }
         }
-        image
+        Ok(image)
     }
 
     /// Loop over every pixel, top to bottom, left to right.
@@ -101,38 +185,130 @@ impl Image {
         Ok(())
     }
 
-    pub fn write(&self, path: &Path) -> Res<()> {
-        match path
+    /// Write in the format named by the file extension.
+    ///
+    /// `.hdr`, `.pfm` and `.exr` keep the linear colours as they are.
+    /// The rest are exposed, tone mapped and sRGB encoded.
+    pub fn write(
+        &self,
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Res<()> {
+        let format = output_format(path, options)?;
+        let out = BufWriter::new(
+            File::create(path)
+                .map_err(Error::io(path))?,
+        );
+        let display = || {
+            self.tone_mapped(
+                options.tone_map,
+                options.exposure,
+            )
+        };
+        match format {
+            OutputFormat::Farbfeld => {
+                write_farbfeld_file(&display(), out)
+            }
+            OutputFormat::Ppm => {
+                write_ppm_file(&display(), out)
+            }
+            OutputFormat::Tga => {
+                write_tga_file(&display(), out)
+            }
+            OutputFormat::Png(depth, alpha) => {
+                write_png_file(
+                    &display(),
+                    out,
+                    depth,
+                    alpha,
+                )
+            }
+            OutputFormat::Hdr => {
+                write_hdr_file(self, out)
+            }
+            OutputFormat::Pfm => {
+                write_pfm_file(self, out)
+            }
+            OutputFormat::Exr(
+                pixel_type,
+                compression,
+            ) => exr::write(
+                out,
+                &[("", self)],
+                pixel_type,
+                compression,
+            ),
+        }
+        .map_err(|e| e.in_file(path))
+    }
+
+    /// Write images as layers of one EXR. The one named "" is the
+    /// main image.
+    pub fn write_layers(
+        path: &Path,
+        options: &WriteOptions,
+        layers: &[(&str, &Image)],
+    ) -> Res<()> {
+        match output_format(path, options)? {
+            OutputFormat::Exr(
+                pixel_type,
+                compression,
+            ) => exr::write(
+                BufWriter::new(
+                    File::create(path)
+                        .map_err(Error::io(path))?,
+                ),
+                layers,
+                pixel_type,
+                compression,
+            )
+            .map_err(|e| e.in_file(path)),
+            _ => Err(Error::InvalidParameter(
+                "only .exr images can hold layers"
+                    .to_owned(),
+            )),
+        }
+    }
+
+    /// Check that `write` will accept a path and options, before
+    /// spending time making an image to write.
+    pub fn check_writable(
+        path: &Path,
+        options: &WriteOptions,
+    ) -> Res<()> {
+        output_format(path, options).map(|_| ())
+    }
+
+    /// Read a file in one of the formats `write` supports. Low
+    /// dynamic range formats have their sRGB values made linear.
+    pub fn read(path: &Path) -> Res<Image> {
+        let extension = path
             .extension()
             .unwrap_or_default()
             .to_str()
-            .expect("Non-utf-8 file extension")
-        {
-            "ff" => write_farbfeld_file(
-                self,
-                BufWriter::new(File::create(path)?),
-            ),
-            "ppm" => write_ppm_file(
-                self,
-                BufWriter::new(File::create(path)?),
-            ),
-            "tga" => write_tga_file(
-                self,
-                BufWriter::new(File::create(path)?),
-            ),
+            .unwrap_or("<invalid-utf-8>");
+        let decode = match extension {
+            "ff" => read_farbfeld,
+            "ppm" => read_ppm,
+            "tga" => read_tga,
+            "png" => read_png,
+            "hdr" => read_hdr,
+            "pfm" => read_pfm,
+            "exr" => exr::read,
             _ => {
-                Err(Box::new(UnsupportedFormatError {
-                    unsupported_extension: path
-                        .extension()
-                        .unwrap_or_default()
-                        .to_str()
-                        .unwrap_or("<invalid-utf-8>")
-                        .to_owned(),
-                }))
+                return Err(Error::UnsupportedFormat {
+                    extension: extension.to_owned(),
+                })
             }
-        }
+        };
+        let bytes =
+            fs::read(path).map_err(Error::io(path))?;
+        Ok(decode(&bytes).map_err(|m| {
+            ParseError::new(path, None, m)
+        })?)
     }
 }
+
 impl From<Image> for Vec<Vec<Color>> {
     fn from(value: Image) -> Self {
         value.image
@@ -159,6 +335,101 @@ impl AddAssign for Image {
     }
 }
 
+/// Choices for formats that offer them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WriteOptions {
+    /// Bits per channel. Each format has its own default.
+    pub bit_depth: Option<BitDepth>,
+    pub exr_compression: ExrCompression,
+    /// How low dynamic range formats fit in highlights.
+    pub tone_map: ToneMap,
+    /// Stops to brighten low dynamic range formats by, before tone
+    /// mapping.
+    pub exposure: F,
+    /// Give PNGs an opaque alpha channel. Farbfeld images always
+    /// have one, and other formats can't.
+    pub alpha: bool,
+}
+
+enum OutputFormat {
+    Farbfeld,
+    Ppm,
+    Tga,
+    Png(BitDepth, bool),
+    Hdr,
+    Pfm,
+    Exr(exr::PixelType, exr::Compression),
+}
+
+fn output_format(
+    path: &Path,
+    options: &WriteOptions,
+) -> Res<OutputFormat> {
+    let extension = path
+        .extension()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or("<invalid-utf-8>");
+    let bit_depth = options.bit_depth;
+    if options.alpha
+        && !matches!(extension, "png" | "ff")
+    {
+        return Err(Error::InvalidParameter(format!(
+            ".{} images can't have an alpha channel",
+            extension
+        )));
+    }
+    match (extension, bit_depth) {
+        ("ff", None | Some(BitDepth::Sixteen)) => {
+            Ok(OutputFormat::Farbfeld)
+        }
+        ("ppm", None | Some(BitDepth::Eight)) => {
+            Ok(OutputFormat::Ppm)
+        }
+        ("tga", None | Some(BitDepth::Eight)) => {
+            Ok(OutputFormat::Tga)
+        }
+        (
+            "png",
+            None
+            | Some(BitDepth::Eight | BitDepth::Sixteen),
+        ) => Ok(OutputFormat::Png(
+            bit_depth.unwrap_or(BitDepth::Eight),
+            options.alpha,
+        )),
+        ("hdr", None) => Ok(OutputFormat::Hdr),
+        ("pfm", None | Some(BitDepth::ThirtyTwo)) => {
+            Ok(OutputFormat::Pfm)
+        }
+        (
+            "exr",
+            None
+            | Some(
+                BitDepth::Sixteen | BitDepth::ThirtyTwo,
+            ),
+        ) => Ok(OutputFormat::Exr(
+            match bit_depth {
+                Some(BitDepth::ThirtyTwo) => {
+                    exr::PixelType::Float
+                }
+                _ => exr::PixelType::Half,
+            },
+            options.exr_compression,
+        )),
+        (
+            "ff" | "ppm" | "tga" | "png" | "hdr"
+            | "pfm" | "exr",
+            Some(depth),
+        ) => Err(Error::InvalidParameter(format!(
+            ".{} images can't be {}",
+            extension, depth
+        ))),
+        _ => Err(Error::UnsupportedFormat {
+            extension: extension.to_owned(),
+        }),
+    }
+}
+
 /// Write as TARGA file format
 fn write_tga_file(
     image: &Image,
@@ -178,9 +449,9 @@ fn write_tga_file(
     out.write_all(&header)?;
     image.for_each_pixel(
         move |_, _, color: &Color| {
-            let pixel: [u8; 3] =
+            let [r, g, b]: [u8; 3] =
                 color.as_web_color().into();
-            out.write_all(&pixel)?;
+            out.write_all(&[b, g, r])?;
             Ok(())
         },
     )?;
@@ -188,6 +459,11 @@ fn write_tga_file(
     Ok(())
 }
 
+/// A 0-1 value as a 16-bit sample.
+fn to16(f: F) -> u16 {
+    (f * u16::MAX as F).round() as u16
+}
+
 /// Write as farbfeld image format
 fn write_farbfeld_file(
     image: &Image,
@@ -203,13 +479,9 @@ fn write_farbfeld_file(
 
     image.for_each_pixel(
         move |_, _, color: &Color| {
-            let color = color.gamma_corrected();
+            let color = color.to_srgb();
             let mut write_pixel = |f: F| {
-                out.write(
-                    &((f * (u16::max_value() as F))
-                        as u16)
-                        .to_be_bytes(),
-                )
+                out.write(&to16(f).to_be_bytes())
             };
 
             write_pixel(color.r())?;
@@ -224,6 +496,675 @@ fn write_farbfeld_file(
     Ok(())
 }
 
+/// Write as PNG, with an opaque alpha channel if `alpha`. Anything
+/// but 8 bits a channel is written as 16.
+fn write_png_file(
+    image: &Image,
+    mut out: impl Write,
+    bit_depth: BitDepth,
+    alpha: bool,
+) -> Res<()> {
+    let channels = if alpha { 4 } else { 3 };
+    let (depth, sample_bytes) = match bit_depth {
+        BitDepth::Eight => (8, 1),
+        _ => (16, 2),
+    };
+    let pixel_bytes = channels * sample_bytes;
+    let row_bytes = image.width() * pixel_bytes;
+
+    let mut raw =
+        Vec::with_capacity(image.height() * row_bytes);
+    image.for_each_pixel(|_, _, color: &Color| {
+        match bit_depth {
+            BitDepth::Eight => {
+                let pixel: [u8; 3] =
+                    color.as_web_color().into();
+                raw.extend(pixel);
+            }
+            _ => {
+                let c = color.to_srgb();
+                for f in [c.r(), c.g(), c.b()] {
+                    raw.extend(to16(f).to_be_bytes());
+                }
+            }
+        }
+        if alpha {
+            raw.extend(vec![255; sample_bytes]);
+        }
+        Ok(())
+    })?;
+
+    let mut filtered =
+        Vec::with_capacity(raw.len() + image.height());
+    let no_row = vec![0; row_bytes];
+    for (j, row) in raw.chunks(row_bytes).enumerate() {
+        let above = match j {
+            0 => &no_row[..],
+            _ => {
+                &raw[(j - 1) * row_bytes..j * row_bytes]
+            }
+        };
+        filtered.extend(best_png_filter(
+            row,
+            above,
+            pixel_bytes,
+        ));
+    }
+
+    let mut header = vec![];
+    header.extend((image.width() as u32).to_be_bytes());
+    header
+        .extend((image.height() as u32).to_be_bytes());
+    // Bit depth, colour type, then default compression,
+    // filtering and no interlacing.
+    header.extend([
+        depth,
+        if alpha { 6 } else { 2 },
+        0,
+        0,
+        0,
+    ]);
+
+    out.write_all(PNG_SIGNATURE)?;
+    write_png_chunk(&mut out, b"IHDR", &header)?;
+    // sRGB encoded, with perceptual rendering intent.
+    write_png_chunk(&mut out, b"sRGB", &[0])?;
+    write_png_chunk(
+        &mut out,
+        b"IDAT",
+        &zlib::compress(&filtered),
+    )?;
+    write_png_chunk(&mut out, b"IEND", &[])?;
+    Ok(())
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn write_png_chunk(
+    out: &mut impl Write,
+    kind: &[u8; 4],
+    data: &[u8],
+) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let checked: Vec<u8> =
+        kind.iter().chain(data).copied().collect();
+    out.write_all(&zlib::crc32(&checked).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// The bytes before `row[i]` that PNG filters predict it from:
+/// left, above, and above left.
+fn png_neighbours(
+    row: &[u8],
+    above: &[u8],
+    i: usize,
+    pixel_bytes: usize,
+) -> (u8, u8, u8) {
+    if i < pixel_bytes {
+        (0, above[i], 0)
+    } else {
+        (
+            row[i - pixel_bytes],
+            above[i],
+            above[i - pixel_bytes],
+        )
+    }
+}
+
+fn png_predict(kind: u8, a: u8, b: u8, c: u8) -> u8 {
+    match kind {
+        1 => a,
+        2 => b,
+        3 => ((a as u16 + b as u16) / 2) as u8,
+        4 => paeth(a, b, c),
+        _ => 0,
+    }
+}
+
+/// Try every filter on a row, keeping the one most likely to compress well.
+fn best_png_filter(
+    row: &[u8],
+    above: &[u8],
+    pixel_bytes: usize,
+) -> Vec<u8> {
+    (0..5u8)
+        .map(|kind| {
+            let mut filtered =
+                Vec::with_capacity(row.len() + 1);
+            filtered.push(kind);
+            for i in 0..row.len() {
+                let (a, b, c) = png_neighbours(
+                    row,
+                    above,
+                    i,
+                    pixel_bytes,
+                );
+                filtered.push(row[i].wrapping_sub(
+                    png_predict(kind, a, b, c),
+                ));
+            }
+            filtered
+        })
+        .min_by_key(|f| {
+            f[1..]
+                .iter()
+                .map(|&x| {
+                    (x as i8).unsigned_abs() as u64
+                })
+                .sum::<u64>()
+        })
+        .unwrap_or_default()
+}
+
+/// Read a non-interlaced 8 or 16-bit greyscale or true-colour PNG.
+/// Alpha is ignored.
+fn read_png(bytes: &[u8]) -> Result<Image, String> {
+    let mut rest = bytes
+        .strip_prefix(PNG_SIGNATURE)
+        .ok_or("not a PNG image")?;
+    let mut header = None;
+    let mut compressed = vec![];
+    loop {
+        let cut_short = "PNG data is cut short";
+        let length = match rest {
+            [a, b, c, d, ..] => {
+                u32::from_be_bytes([*a, *b, *c, *d])
+                    as usize
+            }
+            _ => return Err(cut_short.to_owned()),
+        };
+        let chunk =
+            rest.get(4..8 + length).ok_or(cut_short)?;
+        let crc = rest
+            .get(8 + length..12 + length)
+            .ok_or(cut_short)?;
+        if crc != zlib::crc32(chunk).to_be_bytes() {
+            return Err(format!(
+                "PNG {} chunk is corrupt",
+                String::from_utf8_lossy(&chunk[..4])
+            ));
+        }
+        let (kind, data) = chunk.split_at(4);
+        match kind {
+            b"IHDR" if data.len() == 13 => {
+                header = Some(data)
+            }
+            b"IDAT" => compressed.extend(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[12 + length..];
+    }
+
+    let header = header.ok_or("PNG has no header")?;
+    let u32_at = |i: usize| {
+        u32::from_be_bytes([
+            header[i],
+            header[i + 1],
+            header[i + 2],
+            header[i + 3],
+        ]) as usize
+    };
+    let (width, height) = (u32_at(0), u32_at(4));
+    let (depth, color_type) = (header[8], header[9]);
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => {
+            return Err(format!(
+                "PNG colour type {} isn't supported",
+                color_type
+            ))
+        }
+    };
+    let sample_bytes = match depth {
+        8 => 1,
+        16 => 2,
+        _ => {
+            return Err(format!(
+                "PNG bit depth {} isn't supported, only 8 and 16",
+                depth
+            ))
+        }
+    };
+    if header[12] != 0 {
+        return Err("interlaced PNGs aren't supported"
+            .to_owned());
+    }
+
+    // Deflate shrinks data by at most 1032 times.
+    let pixel_bytes = channels * sample_bytes;
+    pixel_count(
+        width,
+        height,
+        compressed.len().saturating_mul(1032)
+            / pixel_bytes,
+    )?;
+    let row_bytes = width * pixel_bytes;
+    let filtered = zlib::decompress(
+        &compressed,
+        height * (row_bytes + 1),
+    )?;
+    let mut raw: Vec<u8> =
+        Vec::with_capacity(height * row_bytes);
+    let no_row = vec![0; row_bytes];
+    for j in 0..height {
+        let start = j * (row_bytes + 1);
+        let line = filtered
+            .get(start..start + row_bytes + 1)
+            .ok_or("PNG image data is cut short")?;
+        let (kind, line) = (line[0], &line[1..]);
+        if kind > 4 {
+            return Err(format!(
+                "unknown PNG filter type {}",
+                kind
+            ));
+        }
+        let above = match j {
+            0 => &no_row[..],
+            _ => &raw[(j - 1) * row_bytes..],
+        };
+        let mut row = Vec::with_capacity(row_bytes);
+        for (i, &x) in line.iter().enumerate() {
+            let (a, b, c) = png_neighbours(
+                &row,
+                above,
+                i,
+                pixel_bytes,
+            );
+            row.push(x.wrapping_add(png_predict(
+                kind, a, b, c,
+            )));
+        }
+        raw.extend(row);
+    }
+
+    let sample = |p: &[u8], c: usize| match sample_bytes
+    {
+        1 => p[c] as F / 255.,
+        _ => {
+            u16::from_be_bytes([p[2 * c], p[2 * c + 1]])
+                as F
+                / u16::MAX as F
+        }
+    };
+    from_top_down(
+        width,
+        height,
+        raw.chunks_exact(pixel_bytes).map(|p| {
+            if channels < 3 {
+                let grey = sample(p, 0);
+                Color::from_srgb(grey, grey, grey)
+            } else {
+                Color::from_srgb(
+                    sample(p, 0),
+                    sample(p, 1),
+                    sample(p, 2),
+                )
+            }
+        }),
+    )
+}
+
+/// Write as Radiance RGBE, run-length encoded.
+fn write_hdr_file(
+    image: &Image,
+    mut out: impl Write,
+) -> Res<()> {
+    let (width, height) =
+        (image.width(), image.height());
+    write!(
+        out,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+    let mut row = Vec::with_capacity(width);
+    for j in (0..height).rev() {
+        row.clear();
+        row.extend(
+            (0..width)
+                .map(|i| to_rgbe(image.get(i, j))),
+        );
+        // Widths outside this range can't be run-length encoded.
+        if !(8..0x8000).contains(&width) {
+            for pixel in &row {
+                out.write_all(pixel)?;
+            }
+            continue;
+        }
+        out.write_all(&[
+            2,
+            2,
+            (width >> 8) as u8,
+            width as u8,
+        ])?;
+        for c in 0..4 {
+            let channel: Vec<u8> =
+                row.iter().map(|p| p[c]).collect();
+            write_rgbe_runs(&mut out, &channel)?;
+        }
+    }
+    Ok(())
+}
+
+/// A shared exponent and three mantissas.
+fn to_rgbe(color: Color) -> [u8; 4] {
+    let c: Vec3 = color.into();
+    let c = Vec3::new(
+        c.x.max(0.),
+        c.y.max(0.),
+        c.z.max(0.),
+    );
+    let max = c.x.max(c.y).max(c.z);
+    if max < 1e-32 || !max.is_finite() {
+        return [0; 4];
+    }
+    // max = m * 2^exponent, with m in [0.5, 1).
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256. / (2. as F).powi(exponent);
+    let mantissa = |f: F| (f * scale).min(255.) as u8;
+    [
+        mantissa(c.x),
+        mantissa(c.y),
+        mantissa(c.z),
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+fn from_rgbe(p: &[u8]) -> Color {
+    if p[3] == 0 {
+        return Color::black();
+    }
+    let scale = (2. as F).powi(p[3] as i32 - 128 - 8);
+    let channel = |m: u8| (m as F + 0.5) * scale;
+    Color::new(
+        channel(p[0]),
+        channel(p[1]),
+        channel(p[2]),
+    )
+}
+
+/// Runs of four or more of the same byte, with other bytes between.
+fn write_rgbe_runs(
+    out: &mut impl Write,
+    data: &[u8],
+) -> io::Result<()> {
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(127)
+            .take_while(|&&b| b == data[i])
+            .count();
+        if run >= 4 {
+            out.write_all(&[128 + run as u8, data[i]])?;
+            i += run;
+            continue;
+        }
+        // Copy literally up to the next long run.
+        let mut end = i;
+        while end < data.len()
+            && end - i < 128
+            && !data[end..].starts_with(&[data[end]; 4])
+        {
+            end += 1;
+        }
+        out.write_all(&[(end - i) as u8])?;
+        out.write_all(&data[i..end])?;
+        i = end;
+    }
+    Ok(())
+}
+
+/// Read a Radiance RGBE image, flat or run-length encoded, with the
+/// usual top-to-bottom orientation.
+fn read_hdr(bytes: &[u8]) -> Result<Image, String> {
+    if !bytes.starts_with(b"#?") {
+        return Err(
+            "not a Radiance HDR image".to_owned()
+        );
+    }
+    let mut lines = bytes.split(|&b| b == b'\n');
+    let mut at = 0;
+    let mut next_line = || {
+        let line = lines.next()?;
+        at += line.len() + 1;
+        Some(String::from_utf8_lossy(line).into_owned())
+    };
+    // The header ends with an empty line.
+    loop {
+        match next_line() {
+            None => {
+                return Err("HDR header is cut short"
+                    .to_owned())
+            }
+            Some(line) if line.is_empty() => break,
+            Some(line) => {
+                if let Some(format) =
+                    line.strip_prefix("FORMAT=")
+                {
+                    if format != "32-bit_rle_rgbe" {
+                        return Err(format!(
+                            "HDR format {} isn't supported",
+                            format
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    let size = next_line().unwrap_or_default();
+    let (height, width) = match size
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>()
+                .map_err(|e| e.to_string())?,
+            w.parse::<usize>()
+                .map_err(|e| e.to_string())?,
+        ),
+        _ => {
+            return Err(format!(
+                "HDR orientation {:?} isn't supported",
+                size
+            ))
+        }
+    };
+
+    let mut data = bytes.get(at..).unwrap_or_default();
+    // A run-length encoded byte pair can give a channel of 127 pixels,
+    // so every byte is at most 16 pixels.
+    let count = pixel_count(
+        width,
+        height,
+        data.len().saturating_mul(16),
+    )?;
+    let cut_short =
+        || "HDR image data is cut short".to_owned();
+    let mut pixels: Vec<[u8; 4]> =
+        Vec::with_capacity(count);
+    for _ in 0..height {
+        let run_length_encoded = (8..0x8000)
+            .contains(&width)
+            && data.len() >= 4
+            && data[0] == 2
+            && data[1] == 2
+            && data[2] & 0x80 == 0;
+        if !run_length_encoded {
+            let row = data
+                .get(..4 * width)
+                .ok_or_else(cut_short)?;
+            pixels.extend(
+                row.chunks_exact(4)
+                    .map(|p| [p[0], p[1], p[2], p[3]]),
+            );
+            data = &data[4 * width..];
+            continue;
+        }
+        if ((data[2] as usize) << 8 | data[3] as usize)
+            != width
+        {
+            return Err(
+                "HDR scanline has the wrong width"
+                    .to_owned(),
+            );
+        }
+        data = &data[4..];
+        let mut row = vec![[0u8; 4]; width];
+        for c in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let (&count, rest) = data
+                    .split_first()
+                    .ok_or_else(cut_short)?;
+                if count > 128 {
+                    let n = count as usize - 128;
+                    let value = *rest
+                        .first()
+                        .ok_or_else(cut_short)?;
+                    for p in row
+                        .get_mut(x..x + n)
+                        .ok_or("HDR run is too long")?
+                    {
+                        p[c] = value;
+                    }
+                    x += n;
+                    data = &rest[1..];
+                } else {
+                    let n = count as usize;
+                    let values = rest
+                        .get(..n)
+                        .ok_or_else(cut_short)?;
+                    for (p, &v) in row
+                        .get_mut(x..x + n)
+                        .ok_or("HDR run is too long")?
+                        .iter_mut()
+                        .zip(values)
+                    {
+                        p[c] = v;
+                    }
+                    x += n;
+                    data = &rest[n..];
+                }
+            }
+        }
+        pixels.extend(row);
+    }
+    from_top_down(
+        width,
+        height,
+        pixels.iter().map(|p| from_rgbe(p)),
+    )
+}
+
+/// Write as a little-endian Portable Float Map.
+fn write_pfm_file(
+    image: &Image,
+    mut out: impl Write,
+) -> Res<()> {
+    // A negative scale means little-endian.
+    write!(
+        out,
+        "PF\n{} {}\n-1.0\n",
+        image.width(),
+        image.height()
+    )?;
+    // Rows go bottom to top, like ours.
+    for j in 0..image.height() {
+        for i in 0..image.width() {
+            let c = image.get(i, j);
+            for f in [c.r(), c.g(), c.b()] {
+                out.write_all(&f.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read a colour (PF) or greyscale (Pf) Portable Float Map.
+fn read_pfm(bytes: &[u8]) -> Result<Image, String> {
+    let mut header = PpmHeader { bytes, at: 0 };
+    let channels = match header.token() {
+        Some(b"PF") => 3,
+        Some(b"Pf") => 1,
+        _ => return Err("not a PFM image".to_owned()),
+    };
+    let width = header.number("width")?;
+    let height = header.number("height")?;
+    let little_endian = header
+        .token()
+        .and_then(|t| {
+            std::str::from_utf8(t)
+                .ok()?
+                .parse::<F>()
+                .ok()
+        })
+        .ok_or("bad or missing PFM scale")?
+        < 0.;
+    let data =
+        bytes.get(header.at + 1..).unwrap_or_default();
+    pixel_count(
+        width,
+        height,
+        data.len() / (4 * channels),
+    )?;
+    let floats: Vec<F> = data
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b) as F
+            } else {
+                f32::from_be_bytes(b) as F
+            }
+        })
+        .collect();
+    // Rows are stored bottom to top.
+    let rows: Vec<&[F]> = floats
+        .chunks(width * channels)
+        .take(height)
+        .rev()
+        .collect();
+    if rows.len() < height {
+        return Err(
+            "image data is cut short".to_owned()
+        );
+    }
+    from_top_down(
+        width,
+        height,
+        rows.into_iter().flat_map(|row| {
+            row.chunks_exact(channels).map(
+                |p| match p {
+                    [r, g, b] => Color::new(*r, *g, *b),
+                    _ => Color::new(p[0], p[0], p[0]),
+                },
+            )
+        }),
+    )
+}
+
 /// Write as NetPPM file format (text-based)
 fn write_ppm_file(
     image: &Image,
@@ -252,3 +1193,664 @@ fn write_ppm_file(
 
     Ok(())
 }
+
+/// How many pixels a `width` by `height` image from a file has, if it
+/// isn't empty and its data could hold at most `max_pixels`. Readers
+/// check this before allocating anything, so a bad header can't ask
+/// for more memory than the file could fill.
+pub(crate) fn pixel_count(
+    width: usize,
+    height: usize,
+    max_pixels: usize,
+) -> Result<usize, String> {
+    if width == 0 || height == 0 {
+        return Err("image is empty".to_owned());
+    }
+    match width.checked_mul(height) {
+        Some(count) if count <= max_pixels => Ok(count),
+        _ => Err("image data is cut short".to_owned()),
+    }
+}
+
+/// An image from pixels given top to bottom, left to right. The size
+/// should have been checked with `pixel_count`.
+fn from_top_down(
+    width: usize,
+    height: usize,
+    pixels: impl IntoIterator<Item = Color>,
+) -> Result<Image, String> {
+    let mut image = Image::new(width, height);
+    let mut pixels = pixels.into_iter();
+    for j in (0..height).rev() {
+        for i in 0..width {
+            let color = pixels
+                .next()
+                .ok_or("image data is cut short")?;
+            image.set(i, j, color);
+        }
+    }
+    Ok(image)
+}
+
+/// Read a farbfeld image. Alpha is ignored.
+fn read_farbfeld(
+    bytes: &[u8],
+) -> Result<Image, String> {
+    if bytes.len() < 16 || &bytes[..8] != b"farbfeld" {
+        return Err("not a farbfeld image".to_owned());
+    }
+    let u32_at = |i: usize| {
+        u32::from_be_bytes([
+            bytes[i],
+            bytes[i + 1],
+            bytes[i + 2],
+            bytes[i + 3],
+        ]) as usize
+    };
+    let (width, height) = (u32_at(8), u32_at(12));
+    pixel_count(width, height, (bytes.len() - 16) / 8)?;
+    let channel = |p: &[u8], c: usize| {
+        u16::from_be_bytes([p[2 * c], p[2 * c + 1]])
+            as F
+            / u16::MAX as F
+    };
+    from_top_down(
+        width,
+        height,
+        bytes[16..].chunks_exact(8).map(|p| {
+            Color::from_srgb(
+                channel(p, 0),
+                channel(p, 1),
+                channel(p, 2),
+            )
+        }),
+    )
+}
+
+/// Reads the whitespace-separated header fields of a NetPBM file,
+/// skipping comments.
+struct PpmHeader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> PpmHeader<'a> {
+    fn token(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.at)? {
+                b'#' => {
+                    while self.bytes.get(self.at)?
+                        != &b'\n'
+                    {
+                        self.at += 1;
+                    }
+                }
+                c if c.is_ascii_whitespace() => {
+                    self.at += 1
+                }
+                _ => break,
+            }
+        }
+        let start = self.at;
+        while self
+            .bytes
+            .get(self.at)
+            .is_some_and(|c| !c.is_ascii_whitespace())
+        {
+            self.at += 1;
+        }
+        Some(&self.bytes[start..self.at])
+    }
+
+    fn number(
+        &mut self,
+        what: &str,
+    ) -> Result<usize, String> {
+        self.token()
+            .and_then(|t| {
+                std::str::from_utf8(t)
+                    .ok()?
+                    .parse()
+                    .ok()
+            })
+            .ok_or_else(|| {
+                format!("bad or missing {}", what)
+            })
+    }
+}
+
+/// Read a NetPPM image, either text (P3) or binary (P6).
+fn read_ppm(bytes: &[u8]) -> Result<Image, String> {
+    let mut header = PpmHeader { bytes, at: 0 };
+    let binary = match header.token() {
+        Some(b"P3") => false,
+        Some(b"P6") => true,
+        _ => {
+            return Err(
+                "not a P3 or P6 PPM image".to_owned()
+            )
+        }
+    };
+    let width = header.number("width")?;
+    let height = header.number("height")?;
+    let max = header.number("maximum value")?;
+    if !(1..=u16::MAX as usize).contains(&max) {
+        return Err(format!(
+            "maximum value {} should be 1 to 65535",
+            max
+        ));
+    }
+    let scale = |n: usize| -> Result<F, String> {
+        if n > max {
+            return Err(format!(
+                "value {} is over the maximum {}",
+                n, max
+            ));
+        }
+        Ok(n as F / max as F)
+    };
+
+    let data =
+        bytes.get(header.at + 1..).unwrap_or_default();
+    pixel_count(
+        width,
+        height,
+        if binary {
+            data.len() / if max < 256 { 3 } else { 6 }
+        } else {
+            // A digit and a space for each value.
+            (data.len() + 1) / 6
+        },
+    )?;
+    let values: Vec<F> = if binary {
+        // Exactly one whitespace byte separates the header from the data.
+        if max < 256 {
+            data.iter()
+                .map(|&n| scale(n as usize))
+                .collect::<Result<_, _>>()?
+        } else {
+            data.chunks_exact(2)
+                .map(|n| {
+                    scale(u16::from_be_bytes([
+                        n[0], n[1],
+                    ])
+                        as usize)
+                })
+                .collect::<Result<_, _>>()?
+        }
+    } else {
+        let mut values = vec![];
+        while header.bytes.get(header.at).is_some() {
+            match header.token() {
+                Some(t) if !t.is_empty() => {
+                    let n = std::str::from_utf8(t)
+                        .ok()
+                        .and_then(|t| t.parse().ok())
+                        .ok_or_else(|| {
+                            format!(
+                                "bad value {:?}",
+                                String::from_utf8_lossy(
+                                    t
+                                )
+                            )
+                        })?;
+                    values.push(scale(n)?);
+                }
+                _ => break,
+            }
+        }
+        values
+    };
+
+    from_top_down(
+        width,
+        height,
+        values.chunks_exact(3).map(|c| {
+            Color::from_srgb(c[0], c[1], c[2])
+        }),
+    )
+}
+
+/// Read a true-colour TARGA image, uncompressed or run-length encoded.
+fn read_tga(bytes: &[u8]) -> Result<Image, String> {
+    if bytes.len() < 18 {
+        return Err(
+            "TGA header is cut short".to_owned()
+        );
+    }
+    let u16_at = |i: usize| {
+        u16::from_le_bytes([bytes[i], bytes[i + 1]])
+            as usize
+    };
+    let id_length = bytes[0] as usize;
+    let color_map_bytes = if bytes[1] == 1 {
+        u16_at(5) * (bytes[7] as usize).div_ceil(8)
+    } else {
+        0
+    };
+    let run_length_encoded = match bytes[2] {
+        2 => false,
+        10 => true,
+        t => {
+            return Err(format!(
+                "TGA image type {} isn't supported, only true-colour (2 and 10)",
+                t
+            ))
+        }
+    };
+    let (width, height) = (u16_at(12), u16_at(14));
+    let pixel_size = match bytes[16] {
+        24 => 3,
+        32 => 4,
+        depth => {
+            return Err(format!(
+                "TGA pixel depth {} isn't supported, only 24 and 32",
+                depth
+            ))
+        }
+    };
+    let descriptor = bytes[17];
+    let mut data = bytes
+        .get(18 + id_length + color_map_bytes..)
+        .unwrap_or_default();
+
+    let count = pixel_count(
+        width,
+        height,
+        if run_length_encoded {
+            // A packet of a pixel can repeat it 128 times.
+            data.len() / (1 + pixel_size) * 128
+        } else {
+            data.len() / pixel_size
+        },
+    )?;
+    let mut pixels: Vec<&[u8]> =
+        Vec::with_capacity(count);
+    let cut_short =
+        || "TGA image data is cut short".to_owned();
+    while pixels.len() < count {
+        if !run_length_encoded {
+            let pixel = data
+                .get(..pixel_size)
+                .ok_or_else(cut_short)?;
+            pixels.push(pixel);
+            data = &data[pixel_size..];
+            continue;
+        }
+        let (&packet, rest) =
+            data.split_first().ok_or_else(cut_short)?;
+        let run = (packet & 0x7f) as usize + 1;
+        if packet & 0x80 != 0 {
+            let pixel = rest
+                .get(..pixel_size)
+                .ok_or_else(cut_short)?;
+            pixels.extend(std::iter::repeat_n(
+                pixel, run,
+            ));
+            data = &rest[pixel_size..];
+        } else {
+            let raw = rest
+                .get(..run * pixel_size)
+                .ok_or_else(cut_short)?;
+            pixels.extend(raw.chunks_exact(pixel_size));
+            data = &rest[run * pixel_size..];
+        }
+    }
+    pixels.truncate(count);
+
+    // Rows are stored bottom to top unless bit 5 is set, and
+    // right to left if bit 4 is.
+    let rows: Vec<&[&[u8]]> = if descriptor & 0x20 != 0
+    {
+        pixels.chunks(width).collect()
+    } else {
+        pixels.chunks(width).rev().collect()
+    };
+    let right_to_left = descriptor & 0x10 != 0;
+    let channel = |n: u8| n as F / 255.;
+    from_top_down(
+        width,
+        height,
+        rows.into_iter().flat_map(|row| {
+            let row: Box<dyn Iterator<Item = &&[u8]>> =
+                if right_to_left {
+                    Box::new(row.iter().rev())
+                } else {
+                    Box::new(row.iter())
+                };
+            row.map(|p| {
+                Color::from_srgb(
+                    channel(p[2]),
+                    channel(p[1]),
+                    channel(p[0]),
+                )
+            })
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 image of colours on the 8-bit sRGB grid, so every
+    /// format can store them.
+    fn test_card() -> Image {
+        let srgb = |r: u8, g: u8, b: u8| {
+            Color::from_srgb(
+                r as F / 255.,
+                g as F / 255.,
+                b as F / 255.,
+            )
+        };
+        let mut image = Image::new(3, 2);
+        image.set(0, 0, Color::red());
+        image.set(1, 0, srgb(51, 102, 153));
+        image.set(2, 0, srgb(0, 1, 254));
+        image.set(0, 1, Color::white());
+        image.set(1, 1, srgb(128, 128, 0));
+        image.set(2, 1, srgb(3, 200, 17));
+        image
+    }
+
+    fn round_trip(
+        write: fn(&Image, &mut Vec<u8>) -> Res<()>,
+        read: fn(&[u8]) -> Result<Image, String>,
+    ) {
+        let mut bytes = vec![];
+        write(&test_card(), &mut bytes).unwrap();
+        assert_is_test_card(&read(&bytes).unwrap());
+    }
+
+    fn assert_is_test_card(decoded: &Image) {
+        let image = test_card();
+        assert_eq!(decoded.width(), 3);
+        assert_eq!(decoded.height(), 2);
+        for j in 0..2 {
+            for i in 0..3 {
+                let (a, b): (Vec3, Vec3) = (
+                    image.get(i, j).into(),
+                    decoded.get(i, j).into(),
+                );
+                assert!(
+                    (a - b).length() < 1e-6,
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(
+            |i, o| write_ppm_file(i, o),
+            read_ppm,
+        );
+        round_trip(
+            |i, o| write_tga_file(i, o),
+            read_tga,
+        );
+        round_trip(
+            |i, o| write_farbfeld_file(i, o),
+            read_farbfeld,
+        );
+        for depth in
+            [BitDepth::Eight, BitDepth::Sixteen]
+        {
+            for alpha in [false, true] {
+                let mut bytes = vec![];
+                write_png_file(
+                    &test_card(),
+                    &mut bytes,
+                    depth,
+                    alpha,
+                )
+                .unwrap();
+                assert_is_test_card(
+                    &read_png(&bytes).unwrap(),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn high_dynamic_range_round_trips() {
+        let mut image = Image::new(20, 3);
+        for j in 0..3 {
+            for i in 0..20 {
+                // Runs, repeats, and values far above one.
+                let v = ((i / 5) * (j + 1)) as F * 37.5;
+                image.set(
+                    i,
+                    j,
+                    Color::new(v, 0.25, i as F),
+                );
+            }
+        }
+        let mut pfm = vec![];
+        write_pfm_file(&image, &mut pfm).unwrap();
+        assert_eq!(read_pfm(&pfm).unwrap(), image);
+
+        let mut hdr = vec![];
+        write_hdr_file(&image, &mut hdr).unwrap();
+        let decoded = read_hdr(&hdr).unwrap();
+        for j in 0..3 {
+            for i in 0..20 {
+                let (a, b): (Vec3, Vec3) = (
+                    image.get(i, j).into(),
+                    decoded.get(i, j).into(),
+                );
+                // Eight bits of mantissa shared by all three.
+                assert!(
+                    (a - b).length()
+                        <= a.length() / 64. + 1e-3,
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rgbe_encoding() {
+        assert_eq!(to_rgbe(Color::black()), [0; 4]);
+        assert_eq!(
+            to_rgbe(Color::new(1., 0.5, 0.)),
+            [128, 64, 0, 129]
+        );
+        assert_eq!(
+            from_rgbe(&[128, 64, 0, 129]).r(),
+            1. + 1. / 256.
+        );
+    }
+
+    #[test]
+    fn png_filters_invert() {
+        let above: Vec<u8> =
+            (0..30).map(|i| i * 7).collect();
+        let row: Vec<u8> =
+            (0..30).map(|i| 200 - i * 3).collect();
+        for kind in 0..5 {
+            let mut restored = vec![];
+            for i in 0..row.len() {
+                let (a, b, c) =
+                    png_neighbours(&row, &above, i, 3);
+                let filtered = row[i].wrapping_sub(
+                    png_predict(kind, a, b, c),
+                );
+                let (a, b, c) = png_neighbours(
+                    &restored, &above, i, 3,
+                );
+                restored.push(filtered.wrapping_add(
+                    png_predict(kind, a, b, c),
+                ));
+            }
+            assert_eq!(restored, row);
+        }
+    }
+
+    #[test]
+    fn ppm_variants() {
+        let text = read_ppm(
+            b"P3 # comment\n2 1\n# another\n15\n0 0 0  15 15 15\n",
+        )
+        .unwrap();
+        assert_eq!(
+            text.get(1, 0),
+            Color::new(1., 1., 1.)
+        );
+
+        let binary = read_ppm(b"P6\n1 2\n65535\n\xff\xff\0\0\0\0\0\0\0\0\0\0")
+            .unwrap();
+        // The first pixel in the file is the top row.
+        assert_eq!(binary.get(0, 1), Color::red());
+        assert_eq!(binary.get(0, 0), Color::black());
+
+        assert_eq!(
+            read_ppm(b"P3\n1 1\n255\n0 0 256\n")
+                .err()
+                .unwrap(),
+            "value 256 is over the maximum 255"
+        );
+        assert_eq!(
+            read_ppm(b"P3\n2 1\n255\n0 0 0\n")
+                .err()
+                .unwrap(),
+            "image data is cut short"
+        );
+    }
+
+    #[test]
+    fn tga_run_length_encoded_bottom_up() {
+        let mut bytes = vec![0u8; 18];
+        bytes[2] = 10;
+        bytes[12] = 3;
+        bytes[14] = 2;
+        bytes[16] = 24;
+        // Bottom row: three blue pixels in one run.
+        bytes.extend([0x82, 255, 0, 0]);
+        // Top row: red then two raw pixels.
+        bytes.extend([
+            0x80, 0, 0, 255, 0x01, 0, 255, 0, 255, 255,
+            255,
+        ]);
+        let image = read_tga(&bytes).unwrap();
+        for i in 0..3 {
+            assert_eq!(
+                image.get(i, 0),
+                Color::new(0., 0., 1.)
+            );
+        }
+        assert_eq!(image.get(0, 1), Color::red());
+        assert_eq!(
+            image.get(1, 1),
+            Color::new(0., 1., 0.)
+        );
+        assert_eq!(image.get(2, 1), Color::white());
+    }
+
+    #[test]
+    fn sizes_are_checked_before_reading() {
+        let error = |result: Result<Image, String>| {
+            result.err().unwrap()
+        };
+        assert_eq!(
+            error(read_ppm(
+                b"P6\n4000000000 4000000000\n255\n"
+            )),
+            "image data is cut short"
+        );
+        assert_eq!(
+            error(read_pfm(b"PF\n0 1\n-1\n\0\0\0\0")),
+            "image is empty"
+        );
+        let mut tga = vec![0u8; 18];
+        tga[2] = 2;
+        tga[14] = 1;
+        tga[16] = 24;
+        assert_eq!(
+            error(read_tga(&tga)),
+            "image is empty"
+        );
+        tga[12] = 1;
+        assert_eq!(
+            error(read_tga(&tga)),
+            "image data is cut short"
+        );
+        let mut farbfeld = b"farbfeld".to_vec();
+        farbfeld.extend([255; 8]);
+        assert_eq!(
+            error(read_farbfeld(&farbfeld)),
+            "image data is cut short"
+        );
+        // A 1x1 grey PNG whose data inflates to a megabyte.
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut header = [0u8; 13];
+        header[3] = 1;
+        header[7] = 1;
+        header[8] = 8;
+        write_png_chunk(&mut png, b"IHDR", &header)
+            .unwrap();
+        write_png_chunk(
+            &mut png,
+            b"IDAT",
+            &zlib::compress(&[0; 1 << 20]),
+        )
+        .unwrap();
+        write_png_chunk(&mut png, b"IEND", &[])
+            .unwrap();
+        assert_eq!(
+            error(read_png(&png)),
+            "deflate data is longer than expected"
+        );
+    }
+
+    #[test]
+    fn alpha_is_only_for_png() {
+        let alpha = WriteOptions {
+            alpha: true,
+            ..WriteOptions::default()
+        };
+        for name in ["a.png", "a.ff"] {
+            assert!(Image::check_writable(
+                Path::new(name),
+                &alpha
+            )
+            .is_ok());
+        }
+        assert_eq!(
+            Image::check_writable(
+                Path::new("a.ppm"),
+                &alpha
+            )
+            .err()
+            .unwrap()
+            .to_string(),
+            ".ppm images can't have an alpha channel"
+        );
+    }
+
+    #[test]
+    fn averages_need_images_of_one_size() {
+        let mut same =
+            [Image::new(2, 1), Image::new(2, 1)];
+        same[1].set(1, 0, Color::white());
+        let mean = Image::average(&mut same).unwrap();
+        assert_eq!(
+            mean.get(1, 0),
+            Color::from(Vec3::from(0.5))
+        );
+        for mut images in [
+            vec![],
+            vec![Image::new(2, 1), Image::new(1, 2)],
+        ] {
+            assert!(matches!(
+                Image::average(&mut images),
+                Err(Error::InvalidParameter(_))
+            ));
+        }
+    }
+}
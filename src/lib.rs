@@ -0,0 +1,53 @@
+//! A path tracer.
+//!
+//! Build a `Scene` out of `Hitable` things with `Material`s, or load
+//! one with `scene_file::load`, point a `Camera` at it and hand both to
+//! a `Renderer` to get an `Image`, which can be tone mapped and written
+//! out in several formats.
+
+pub mod aabb;
+pub mod adaptive;
+pub mod aov;
+mod bvh;
+pub mod camera;
+pub mod color;
+pub mod error;
+mod exr;
+pub mod hitable;
+pub mod image;
+pub mod material;
+pub mod obj;
+mod parse;
+pub mod ray;
+mod render;
+pub mod sampler;
+pub mod scene;
+pub mod scene_file;
+pub mod shapes;
+pub mod texture;
+mod tiles;
+pub mod tonemap;
+pub mod transformed;
+pub mod triangle;
+pub mod vec3;
+mod zlib;
+
+// For the command line tool. These may change at any time.
+#[doc(hidden)]
+pub mod compare;
+#[doc(hidden)]
+pub mod denoise;
+#[doc(hidden)]
+pub mod scenes;
+#[doc(hidden)]
+pub mod time;
+
+pub use camera::{Camera, CameraSettings};
+pub use error::Error;
+pub use hitable::Hitable;
+pub use image::Image;
+pub use material::Material;
+pub use render::{Progress, Rendered, Renderer};
+pub use scene::Scene;
+
+pub type Res<T> = Result<T, Error>;
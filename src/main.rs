@@ -1,289 +1,564 @@
-#![allow(dead_code)]
-#![allow(unused_variables)]
-
-use std::ops::Rem;
-use std::path::{Path, PathBuf};
-use std::sync::mpsc;
-use std::sync::mpsc::Receiver;
-use std::sync::Arc;
-use std::thread;
+use std::io::Write;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use clap::Parser;
-
-use camera::Camera;
-use color::Color;
-use image::Image;
-use vec3::F;
-
-use crate::hitable::Hitable;
-use crate::material::Scatter;
-use crate::ray::Ray;
-use crate::scene::Scene;
-use crate::time::format_rough_duration;
-use crate::vec3::{randf, Vec3};
-use std::io::Write;
+use clap::{Args, Parser, Subcommand};
+use rand::SeedableRng;
+
+use chambray::adaptive::{self, Sampling};
+use chambray::aov::{self, Aov};
+use chambray::compare::Comparison;
+use chambray::denoise;
+use chambray::image::{
+    BitDepth, ExrCompression, Image, WriteOptions,
+};
+use chambray::sampler::SamplerKind;
+use chambray::scene::Background;
+use chambray::scene_file::{self, RenderSettings};
+use chambray::scenes::{self, Preset, SceneRng};
+use chambray::time::{
+    format_remaining_secs, format_rough_duration,
+};
+use chambray::tonemap::ToneMap;
+use chambray::vec3::F;
+use chambray::{Error, Progress, Renderer, Res};
 
-mod camera;
-mod color;
-mod hitable;
-mod image;
-mod material;
-mod ray;
-mod scene;
-mod scenes;
-mod time;
-mod vec3;
-
-type Error = Box<dyn std::error::Error>;
-type Res<T> = Result<T, Error>;
-
-const MAX_DEPTH: i32 = 50;
 const CLEAR_LINE: &str = "\x1b[2K";
+/// Least time between redrawing the progress bar.
+const REDRAW_INTERVAL: Duration =
+    Duration::from_millis(200);
 
-fn ray_color(
-    ray: &Ray,
-    scene: &dyn Hitable,
-    depth: i32,
-) -> Color {
-    if let Some(hit) =
-        scene.hit(ray, &((0.001 as F)..F::INFINITY))
-    {
-        return if depth > MAX_DEPTH {
-            Color::black()
-        } else if let Scatter::Scattered {
-            ray: scattered_ray,
-            attenuation: scattered_attenuation,
-        } = hit.material.scatter(ray, &hit)
-        {
-            ray_color(&scattered_ray, scene, depth + 1)
-                .attenuate(scattered_attenuation)
-        } else {
-            Color::black()
-        };
-    }
-    let unit_direction = ray.direction.unit();
-    let t = 0.5 * (unit_direction.y + 1.);
+#[derive(Debug, Parser)]
+#[command(
+    author,
+    version,
+    about = "Create a ray-traced image",
+    long_about = None,
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    Color::linear(Color::white(), Color::sky_blue(), t)
+    /// Without a command, render.
+    #[command(flatten)]
+    render: RenderArgs,
 }
 
-#[derive(Debug, Parser)]
-#[command(author, version, about = "Create a ray-traced image", long_about = None)]
-struct Cli {
-    #[arg(long, default_value = "400")]
-    width: usize,
-    #[arg(long, default_value = "200")]
-    height: usize,
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create a ray-traced image
+    Render(RenderArgs),
+    /// Measure how different two images look
+    Compare(CompareArgs),
+}
+
+#[derive(Debug, Args)]
+struct RenderArgs {
+    #[arg(long, help = "[default: 400]")]
+    width: Option<usize>,
+    #[arg(long, help = "[default: 200]")]
+    height: Option<usize>,
 
     #[arg(
         value_name = "OUTPUT_FILE",
-        default_value = "image.ppm",
-        help = "Output image path. Supported extensions are .ppm, .tga or .ff"
+        default_value = "image.png",
+        help = "Output image path. Supported extensions are .png, .ppm, .tga, .ff, .hdr, .pfm or .exr"
     )]
     output: PathBuf,
 
-    #[arg(long, default_value = "64")]
-    samples: u16,
+    #[arg(
+        long,
+        value_name = "BITS",
+        help = "Bits per channel, for formats that offer a choice: 8 or 16 for PNG, 16 (half) or 32 (float) for EXR"
+    )]
+    bit_depth: Option<BitDepth>,
+
+    #[arg(
+        long,
+        value_name = "METHOD",
+        default_value = "zip",
+        help = "EXR compression: none or zip"
+    )]
+    exr_compression: ExrCompression,
+
+    #[arg(
+        long,
+        help = "Give PNG output an opaque alpha channel"
+    )]
+    alpha: bool,
+
+    #[arg(
+        long,
+        value_name = "OPERATOR",
+        default_value = "clamp",
+        help = "How highlights are fitted into formats other than .hdr, .pfm and .exr: clamp, reinhard, extended-reinhard[:WHITE], aces or hable"
+    )]
+    tone_map: ToneMap,
+
+    #[arg(
+        long,
+        value_name = "STOPS",
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Brighten or darken by this many stops before tone mapping"
+    )]
+    exposure: F,
+
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        help = "Extra images of what camera rays first hit, as layers of an .exr output or files like image.albedo.png: albedo, normal, depth, position, object-id, material-id"
+    )]
+    aov: Vec<Aov>,
+
+    #[arg(
+        long,
+        help = "Write AOVs to files of their own, even when the output is .exr"
+    )]
+    separate_aovs: bool,
+
+    #[arg(
+        long,
+        help = "Smooth out noise after rendering, guided by albedo, normal and position AOVs"
+    )]
+    denoise: bool,
+
+    #[arg(long, help = "[default: 64]")]
+    samples: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        help = "Keep sampling pixels until their 95% confidence interval is within this fraction of their brightness, from --samples up to --max-samples"
+    )]
+    noise_threshold: Option<F>,
+
+    #[arg(
+        long,
+        value_name = "SAMPLES",
+        requires = "noise_threshold",
+        help = "Most samples for any pixel, with --noise-threshold [default: 16 times --samples]"
+    )]
+    max_samples: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write an image of the samples each pixel took, from blue for few to red for --max-samples"
+    )]
+    heatmap: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "KIND",
+        default_value_t = SamplerKind::default(),
+        help = "Where each sample's random numbers come from: independent, stratified, halton or sobol"
+    )]
+    sampler: SamplerKind,
 
     #[arg(long, default_value = "8")]
     threads: usize,
+
+    #[arg(
+        long,
+        help = "Render exactly the same image every time, whatever the number of threads: the scene's random choices and every sample come from this [default: random, and printed]"
+    )]
+    seed: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Scene description file to render, instead of a preset. Its render settings are used unless overridden here",
+        conflicts_with = "preset"
+    )]
+    scene: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Built-in scene to render [default: random]"
+    )]
+    preset: Option<String>,
+
+    #[arg(
+        long,
+        help = "List the built-in scenes and exit"
+    )]
+    list_presets: bool,
+
+    #[arg(
+        long,
+        value_name = "BACKGROUND",
+        help = "Light from beyond the scene, replacing the scene's own: sky, none, an R,G,B colour or an image file"
+    )]
+    background: Option<Background>,
 }
 
-fn main() -> Res<()> {
-    let opt = Cli::parse();
+#[derive(Debug, Args)]
+struct CompareArgs {
+    #[arg(help = "The image to compare against")]
+    reference: PathBuf,
 
-    let scene = scenes::random_scene();
-    let aspect = (opt.width as F) / (opt.height as F);
-    eprintln!(
-        "{}x{}, {} samples, {} threads.\nOutput: \"{}\"",
-        opt.width,
-        opt.height,
-        opt.samples,
-        opt.threads,
-        bold(
-            opt.output
-                .to_str()
-                .unwrap_or("<non-utf-8>")
-        )
-    );
+    #[arg(help = "The image to score")]
+    test: PathBuf,
 
-    let look_from = Vec3::new(12., 6., 0.51);
-    let look_at = Vec3::new(0., 1., 0.);
-    let dist_to_focus = (look_from - look_at).length();
-    let aperture = 0.6;
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        Vec3::new(0., 1., 0.),
-        20.,
-        aspect,
-        aperture,
-        dist_to_focus,
-    );
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Also write an image of each pixel's perceptual error, from blue for none to red"
+    )]
+    diff: Option<PathBuf>,
 
-    render(
-        scene,
-        camera,
-        opt.width,
-        opt.height,
-        opt.output.as_path(),
-        opt.samples,
-        opt.threads,
-    )
+    #[arg(
+        long,
+        value_name = "OPERATOR",
+        default_value_t = ToneMap::Clamp,
+        help = "How high dynamic range images are brought into 0-1 before comparing, as for rendering"
+    )]
+    tone_map: ToneMap,
+
+    #[arg(
+        long,
+        value_name = "STOPS",
+        default_value_t = 0.,
+        allow_hyphen_values = true,
+        help = "Brighten (or with a negative number, darken) high dynamic range images before tone mapping"
+    )]
+    exposure: F,
 }
 
-/// Percentage this thread has completed so far.
-struct ProcStatus {
-    thread_id: usize,
-    fraction_complete: f32,
+fn main() {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        None => run(cli.render),
+        Some(Command::Render(args)) => run(args),
+        Some(Command::Compare(args)) => compare(args),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        // 2 for being asked the impossible, like clap's own usage errors.
+        let code = match e {
+            Error::InvalidParameter(_)
+            | Error::UnsupportedFormat { .. } => 2,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
 }
 
-fn render(
-    scene: Scene,
-    camera: Camera,
-    width: usize,
-    height: usize,
-    path: &Path,
-    samples: u16,
-    thread_count: usize,
-) -> Res<()> {
-    let start = Instant::now();
+/// Print how different two images look, and perhaps where.
+fn compare(opt: CompareArgs) -> Res<()> {
+    let display = |path: &PathBuf| -> Res<Image> {
+        Ok(Image::read(path)?
+            .tone_mapped(opt.tone_map, opt.exposure))
+    };
+    let (reference, test) =
+        (display(&opt.reference)?, display(&opt.test)?);
+    let diff_options = WriteOptions::default();
+    if let Some(diff) = &opt.diff {
+        Image::check_writable(diff, &diff_options)?;
+    }
 
-    let rays_to_trace = (width as u64)
-        * (height as u64)
-        * (samples as u64);
-
-    let mut children = Vec::with_capacity(thread_count);
-    let samples_per_thread =
-        samples / (thread_count as u16);
-
-    let camera: Arc<Camera> = Arc::new(camera);
-    let scene: Arc<Scene> = Arc::new(scene);
-
-    let (tx, rx) = mpsc::channel::<ProcStatus>();
-
-    for thread_id in 0..thread_count {
-        let scene = scene.clone();
-        let camera = camera.clone();
-        let tx = tx.clone();
-        children.push(thread::spawn(move || {
-            render_image(
-                scene,
-                camera,
-                width,
-                height,
-                samples_per_thread,
-                |fraction_complete| {
-                    tx.send(ProcStatus {
-                        thread_id,
-                        fraction_complete,
-                    })
-                        .unwrap_or_else(|e| {
-                            eprintln!(
-                                "Cannot report processing status from thread {}: {:?}",
-                                thread_id, e
-                            )
-                        })
-                },
-            )
-        }));
+    let comparison =
+        Comparison::new(&reference, &test)?;
+    println!("MSE   {:.6}", comparison.mse);
+    println!("PSNR  {:.2} dB", comparison.psnr);
+    println!("SSIM  {:.4}", comparison.ssim);
+    println!("FLIP  {:.4}", comparison.flip);
+    if let Some(diff) = &opt.diff {
+        comparison
+            .error_image()
+            .write(diff, &diff_options)?;
     }
+    Ok(())
+}
 
-    track_thread_progress(thread_count, rx)?;
+fn run(opt: RenderArgs) -> Res<()> {
+    if opt.list_presets {
+        for preset in scenes::presets() {
+            println!(
+                "{:<12} {}",
+                preset.name, preset.description
+            );
+        }
+        return Ok(());
+    }
 
-    let mut images: Vec<Image> =
-        Vec::with_capacity(children.len());
-    for child in children {
-        images.push(
-            child
-                .join()
-                .expect("Failed thread: cannot join")
-                .into(),
-        );
+    let seed = opt.seed.unwrap_or_else(rand::random);
+    let mut rng = SceneRng::seed_from_u64(seed);
+    let (mut scene, camera_settings, render_settings) =
+        match &opt.scene {
+            Some(path) => {
+                let d =
+                    scene_file::load(path, &mut rng)?;
+                (d.scene, d.camera, d.render)
+            }
+            None => {
+                let preset = match &opt.preset {
+                    Some(name) => find_preset(name)?,
+                    None => scenes::presets().remove(0),
+                };
+                (
+                    (preset.build)(&mut rng),
+                    preset.camera,
+                    RenderSettings::default(),
+                )
+            }
+        };
+    if let Some(background) = opt.background {
+        scene.background = background;
+    }
+    let width = opt
+        .width
+        .or(render_settings.width)
+        .unwrap_or(400);
+    let height = opt
+        .height
+        .or(render_settings.height)
+        .unwrap_or(200);
+    let samples = opt
+        .samples
+        .or(render_settings.samples)
+        .unwrap_or(64);
+    if width == 0 || height == 0 || samples == 0 {
+        return Err(Error::InvalidParameter(
+            "--width, --height and --samples must be at least 1"
+                .to_owned(),
+        ));
+    }
+
+    let write_options = WriteOptions {
+        bit_depth: opt.bit_depth,
+        exr_compression: opt.exr_compression,
+        tone_map: opt.tone_map,
+        exposure: opt.exposure,
+        alpha: opt.alpha,
+    };
+    Image::check_writable(&opt.output, &write_options)?;
+    aov::check_writable(
+        &opt.output,
+        &write_options,
+        &opt.aov,
+        opt.separate_aovs,
+    )?;
+    let heatmap_options = WriteOptions {
+        tone_map: ToneMap::Clamp,
+        exposure: 0.,
+        ..write_options
+    };
+    if let Some(heatmap) = &opt.heatmap {
+        Image::check_writable(
+            heatmap,
+            &heatmap_options,
+        )?;
+    }
+
+    let sampling = match opt.noise_threshold {
+        None => Sampling {
+            sampler: opt.sampler,
+            seed,
+            ..Sampling::fixed(samples)
+        },
+        Some(threshold) => {
+            let max_samples = opt
+                .max_samples
+                .unwrap_or(samples.saturating_mul(16));
+            if threshold <= 0. || max_samples < samples
+            {
+                return Err(Error::InvalidParameter(
+                    "--noise-threshold must be positive, and --max-samples at least --samples"
+                        .to_owned(),
+                ));
+            }
+            Sampling {
+                samples,
+                noise_threshold: Some(threshold),
+                max_samples,
+                sampler: opt.sampler,
+                seed,
+            }
+        }
+    };
+
+    let aspect = (width as F) / (height as F);
+    eprintln!(
+        "{}x{}, {} {} samples, {} threads, seed {}.\nOutput: \"{}\"",
+        width,
+        height,
+        if sampling.max_samples > samples {
+            format!("{}-{}", samples, sampling.max_samples)
+        } else {
+            samples.to_string()
+        },
+        sampling.sampler,
+        opt.threads,
+        seed,
+        bold(
+            opt.output
+                .to_str()
+                .unwrap_or("<non-utf-8>")
+        )
+    );
+
+    let camera = camera_settings.camera(aspect)?;
+
+    let mut aovs = opt.aov.clone();
+    if opt.denoise {
+        for guide in
+            [Aov::Albedo, Aov::Normal, Aov::Position]
+        {
+            if !aovs.contains(&guide) {
+                aovs.push(guide);
+            }
+        }
     }
 
-    Image::average(&mut images).write(path)?;
+    let start = Instant::now();
+    let mut last_redraw: Option<Instant> = None;
+    let rendered = Renderer::new(width, height)
+        .sampling(sampling)
+        .threads(opt.threads)
+        .aovs(&aovs)
+        .on_progress(|progress| {
+            let finished = progress.tiles_done
+                == progress.tile_count;
+            if finished
+                || !last_redraw.is_some_and(|t| {
+                    t.elapsed() < REDRAW_INTERVAL
+                })
+            {
+                last_redraw = Some(Instant::now());
+                // Progress is a nicety, not worth failing over.
+                let _ = print_progress(progress, start);
+            }
+        })
+        .render_layers(scene, &camera);
+    println!();
+    let rendered = rendered?;
+    let (mut image, mut aov_images, sample_counts) = (
+        rendered.image,
+        rendered.aovs,
+        rendered.sample_counts,
+    );
 
+    let mut rays_traced = 0;
+    sample_counts.for_each_pixel(|_, _, count| {
+        rays_traced += count.r() as u64;
+        Ok(())
+    })?;
     eprintln!(
         "\r{} rays, rendered in {:<30}",
-        rays_to_trace,
+        rays_traced,
         bold(&format_rough_duration(start.elapsed())),
     );
+    if sampling.noise_threshold.is_some() {
+        eprintln!(
+            "{:.1} samples per pixel on average",
+            rays_traced as F / (width * height) as F
+        );
+    }
     eprintln!(
         "{} rays/millisecond",
-        (rays_to_trace as u128)
-            / start.elapsed().as_millis(),
+        (rays_traced as u128)
+            / start.elapsed().as_millis().max(1),
     );
-    Ok(())
+    if let Some(heatmap) = &opt.heatmap {
+        adaptive::heatmap(
+            &sample_counts,
+            sampling.max_samples,
+        )
+        .write(heatmap, &heatmap_options)?;
+    }
+
+    if opt.denoise {
+        let start = Instant::now();
+        let guide = |aov: Aov| {
+            aov_images
+                .iter()
+                .find(|(a, _)| *a == aov)
+                .map(|(_, image)| image)
+                .ok_or_else(|| {
+                    Error::InvalidParameter(format!(
+                        "the {} AOV wasn't rendered to guide the denoiser",
+                        aov
+                    ))
+                })
+        };
+        image = denoise::denoise(
+            &image,
+            &denoise::Guides {
+                albedo: guide(Aov::Albedo)?,
+                normal: guide(Aov::Normal)?,
+                position: guide(Aov::Position)?,
+            },
+        );
+        eprintln!(
+            "Denoised in {}ms",
+            start.elapsed().as_millis()
+        );
+    }
+    let wanted = &opt.aov;
+    aov_images.retain(|(aov, _)| wanted.contains(aov));
+
+    aov::write(
+        &opt.output,
+        &write_options,
+        &image,
+        &aov_images,
+        opt.separate_aovs,
+    )
+}
+
+fn find_preset(name: &str) -> Res<Preset> {
+    scenes::preset(name).ok_or_else(|| {
+        let names: Vec<&str> = scenes::presets()
+            .iter()
+            .map(|p| p.name)
+            .collect();
+        Error::InvalidParameter(format!(
+            "Unknown preset {:?}. Choose from: {}",
+            name,
+            names.join(", ")
+        ))
+    })
 }
 
 fn bold(text: &str) -> String {
     format!("\x1b[1m{}\x1b[m", text)
 }
 
-fn track_thread_progress(
-    threads: usize,
-    rx: Receiver<ProcStatus>,
+/// Redraw the progress line.
+fn print_progress(
+    progress: &Progress,
+    start: Instant,
 ) -> Res<()> {
-    let mut statuses = vec![0.; threads];
-
-    loop {
-        // Drain any waiting thread statuses.
-        for ProcStatus {
-            thread_id,
-            fraction_complete,
-        } in rx.try_iter()
-        {
-            statuses[thread_id] = fraction_complete;
-        }
-
-        // Print statuses. If all are completed, we can exit.
-        let mut are_finished = true;
-        {
-            let out = std::io::stdout();
-            let mut out = out.lock();
-
-            writeln!(out)?;
-            for (thread_num, fraction_complete) in
-                statuses.iter().enumerate()
-            {
-                write!(
-                    out,
-                    "{} {:2>} ",
-                    CLEAR_LINE, thread_num,
-                )?;
-                print_progress_bar(
-                    *fraction_complete,
-                    &mut out,
-                )?;
-                writeln!(
-                    out,
-                    " {:2>2.0}% ",
-                    *fraction_complete * 100.
-                )?;
-
-                if *fraction_complete < 1. {
-                    are_finished = false;
-                }
-            }
-            writeln!(out)?;
-
-            if are_finished {
-                break Ok(());
-            }
-            // We're redrawing. Move cursor back up the same number of lines
-            write!(
-                out,
-                "\x1b[{}A",
-                // A line (progress bar) per thread, plus header and footer line.
-                threads + 2
-            )?;
-        }
-
-        // Delay before rerender
-        thread::sleep(Duration::from_millis(300));
+    let fraction_complete = progress.fraction();
+    let mut out = std::io::stdout().lock();
+    write!(out, "\r{}", CLEAR_LINE)?;
+    print_progress_bar(fraction_complete, &mut out)?;
+    write!(
+        out,
+        " {:>3.0}% {}/{} tiles",
+        fraction_complete * 100.,
+        progress.tiles_done,
+        progress.tile_count,
+    )?;
+    if progress.tiles_done > 0
+        && progress.tiles_done < progress.tile_count
+    {
+        write!(
+            out,
+            ", eta {}",
+            format_remaining_secs(
+                start,
+                fraction_complete
+            )
+        )?;
     }
+    out.flush()?;
+    Ok(())
 }
 
 fn print_progress_bar(
@@ -291,60 +566,16 @@ fn print_progress_bar(
     out: &mut impl Write,
 ) -> Res<()> {
     const PROGRESS_BAR_WIDTH: i16 = 40;
-    let asdf = "m▐▓▓▓▓▓▓▓▓▓▓▓▓░░░░░░░░▌  56";
     write!(out, "▐")?;
     let columns_complete = (fraction_complete
         * (PROGRESS_BAR_WIDTH as f32))
         as i16;
-    for i in 0..(columns_complete) {
+    for _i in 0..(columns_complete) {
         write!(out, "▓")?;
     }
-    for i in columns_complete..(PROGRESS_BAR_WIDTH) {
+    for _i in columns_complete..(PROGRESS_BAR_WIDTH) {
         write!(out, "░")?;
     }
     write!(out, "▌")?;
     Ok(())
 }
-
-fn render_image(
-    scene: Arc<Scene>,
-    camera: Arc<Camera>,
-    width: usize,
-    height: usize,
-    samples: u16,
-    send_fraction_complete_status: impl Fn(f32),
-) -> Vec<Vec<Color>> {
-    let mut image =
-        vec![vec![Color::black(); width]; height];
-
-    // Send status every x rows.
-    let every_x = height / 200 + 1;
-
-    for (j, row) in image.iter_mut().enumerate() {
-        if (j.rem(every_x)) == 0 {
-            send_fraction_complete_status(
-                (j as f32) / (height as f32),
-            );
-        }
-        for (i, color) in row.iter_mut().enumerate() {
-            let mut color_samples = Color::black();
-
-            for s in 0..samples {
-                let ray: Ray = camera.ray(
-                    (i as F + randf()) / (width as F),
-                    (j as F + randf()) / (height as F),
-                );
-
-                color_samples +=
-                    ray_color(&ray, scene.as_ref(), 0);
-            }
-
-            *color = color_samples.darken(samples as F);
-        }
-    }
-
-    // We're done!
-    send_fraction_complete_status(1.);
-
-    image
-}
@@ -1,42 +1,158 @@
+use crate::color::Color;
 use crate::hitable::Hit;
 use crate::ray::Ray;
-use crate::vec3::{randf, Vec3, F};
+use crate::sampler::{
+    unit_ball_point, unit_sphere_point, Sampler,
+};
+use crate::texture::{SharedTexture, SolidColor};
+use crate::vec3::{Vec3, F, PI};
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
+use std::sync::Arc;
 
 pub enum Scatter {
-    Scattered { ray: Ray, attenuation: Vec3 },
+    /// In a direction we can't evaluate the material for otherwise
+    /// (a mirror, glass, fuzzy metal), so lights aren't sampled directly.
+    Scattered {
+        ray: Ray,
+        attenuation: Vec3,
+    },
+    /// Sampled from a distribution that `Material::eval` and
+    /// `Material::pdf` describe. `attenuation` is eval / pdf.
+    Diffuse {
+        ray: Ray,
+        attenuation: Vec3,
+        pdf: F,
+    },
     Stopped,
 }
 
 pub trait Material {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Scatter;
+    /// What happens to a ray hitting the surface, with any random
+    /// choices made by `sampler`.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        sampler: &mut dyn Sampler,
+    ) -> Scatter;
+
+    /// Light given off by the surface itself.
+    fn emitted(&self, _hit: &Hit) -> Color {
+        Color::black()
+    }
+
+    /// Whether `emitted` can be anything but black.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// How much light arriving along unit `direction` is reflected back
+    /// along the ray, including the cosine term.
+    ///
+    /// Only meaningful for materials that scatter `Scatter::Diffuse`.
+    fn eval(
+        &self,
+        _ray: &Ray,
+        _hit: &Hit,
+        _direction: &Vec3,
+    ) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    /// Probability density (per solid angle) of `scatter` choosing unit `direction`.
+    fn pdf(
+        &self,
+        _ray: &Ray,
+        _hit: &Hit,
+        _direction: &Vec3,
+    ) -> F {
+        0.
+    }
+
+    /// The surface colour, whichever way light goes: the fraction
+    /// reflected or passed on.
+    fn albedo(&self, _hit: &Hit) -> Vec3 {
+        Vec3::ONE
+    }
+}
+
+/// The hit normal, flipped if needed to face back towards the ray.
+///
+/// Spheres' normals always point outwards, but we may hit a triangle
+/// from either side.
+fn facing_normal(ray: &Ray, hit: &Hit) -> Vec3 {
+    if ray.direction.dot(&hit.normal) > 0. {
+        -hit.normal
+    } else {
+        hit.normal
+    }
 }
 
 pub struct Lambertian {
-    pub albedo: Vec3,
+    pub albedo: SharedTexture,
 }
 
-fn random_in_unit_sphere() -> Vec3 {
-    let mut p: Vec3;
-    loop {
-        p = Vec3::random() * 2.0 - Vec3::ONE;
+impl Lambertian {
+    pub fn new(albedo: Vec3) -> Self {
+        Self::textured(Arc::new(SolidColor {
+            color: albedo,
+        }))
+    }
 
-        if p.squared_length() < 1.0 {
-            return p;
-        }
+    pub fn textured(albedo: SharedTexture) -> Self {
+        Lambertian { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Scatter {
-        let target: Vec3 = hit.p
-            + hit.normal
-            + random_in_unit_sphere();
+    /// Cosine-weighted: a point on the unit sphere touching the surface.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        sampler: &mut dyn Sampler,
+    ) -> Scatter {
+        let normal = facing_normal(ray, hit);
+        let mut direction = normal
+            + unit_sphere_point(sampler.get_2d());
+        if direction.squared_length() < 1e-8 {
+            direction = normal;
+        }
+        let direction = direction.unit();
 
-        Scatter::Scattered {
-            ray: Ray::new(hit.p, target - hit.p),
-            attenuation: self.albedo,
+        Scatter::Diffuse {
+            ray: ray.next(hit.p, direction),
+            attenuation: self.albedo(hit),
+            pdf: self.pdf(ray, hit, &direction),
+        }
+    }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit.u, hit.v, &hit.p)
+    }
+
+    fn eval(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        direction: &Vec3,
+    ) -> Vec3 {
+        self.albedo(hit) * self.pdf(ray, hit, direction)
+    }
+
+    fn pdf(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        direction: &Vec3,
+    ) -> F {
+        let cosine =
+            facing_normal(ray, hit).dot(direction);
+        if cosine > 0. {
+            cosine / PI
+        } else {
+            0.
         }
     }
 }
@@ -66,10 +182,8 @@ fn refract(
 ) -> Option<Vec3> {
     let uv = v.unit();
     let dt = uv.dot(n);
-    let discriminant = 1.0
-        - ni_over_nt
-            * ni_over_nt
-            * ((dt * dt * -1.) + 1.);
+    let discriminant =
+        1.0 - ni_over_nt * ni_over_nt * (1. - dt * dt);
 
     if discriminant > 0. {
         Some(
@@ -82,17 +196,24 @@ fn refract(
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Scatter {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        sampler: &mut dyn Sampler,
+    ) -> Scatter {
+        let normal = facing_normal(ray, hit);
         let reflected =
-            reflect(&ray.direction.unit(), &hit.normal);
+            reflect(&ray.direction.unit(), &normal);
 
-        let scattered = Ray::new(
-            hit.p,
-            reflected
-                + random_in_unit_sphere() * self.fuzz,
+        let fuzz = unit_ball_point(
+            sampler.get_2d(),
+            sampler.get_1d(),
         );
+        let scattered = ray
+            .next(hit.p, reflected + fuzz * self.fuzz);
 
-        if scattered.direction.dot(&hit.normal) < 0. {
+        if scattered.direction.dot(&normal) < 0. {
             return Scatter::Stopped;
         }
 
@@ -101,6 +222,10 @@ impl Material for Metal {
             attenuation: self.albedo,
         }
     }
+
+    fn albedo(&self, _hit: &Hit) -> Vec3 {
+        self.albedo
+    }
 }
 
 pub struct Dialectric {
@@ -116,7 +241,12 @@ pub fn schlick(cosine: F, reflective_index: F) -> F {
 }
 
 impl Material for Dialectric {
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Scatter {
+    fn scatter(
+        &self,
+        ray: &Ray,
+        hit: &Hit,
+        sampler: &mut dyn Sampler,
+    ) -> Scatter {
         let reflected =
             reflect(&ray.direction, &hit.normal);
         let attenuation = Vec3::ONE;
@@ -146,7 +276,7 @@ impl Material for Dialectric {
             &outward_normal,
             ni_over_nt,
         ) {
-            if randf()
+            if sampler.get_1d()
                 < schlick(cosine, self.reflective_index)
             {
                 reflected
@@ -158,12 +288,36 @@ impl Material for Dialectric {
         };
 
         Scatter::Scattered {
-            ray: Ray::new(hit.p, direction),
+            ray: ray.next(hit.p, direction),
             attenuation,
         }
     }
 }
 
+/// Glows, and doesn't reflect anything.
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+impl Material for DiffuseLight {
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _hit: &Hit,
+        _sampler: &mut dyn Sampler,
+    ) -> Scatter {
+        Scatter::Stopped
+    }
+
+    fn emitted(&self, _hit: &Hit) -> Color {
+        self.emit
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
 impl Distribution<Metal> for Standard {
     fn sample<R: Rng + ?Sized>(
         &self,
@@ -187,8 +341,6 @@ impl Distribution<Lambertian> for Standard {
         let mut r =
             || -> F { rng.gen::<F>() * rng.gen::<F>() };
 
-        Lambertian {
-            albedo: Vec3::new(r(), r(), r()),
-        }
+        Lambertian::new(Vec3::new(r(), r(), r()))
     }
 }
@@ -0,0 +1,577 @@
+//! Wavefront `.obj` geometry and its `.mtl` material libraries.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::material::{
+    Dialectric, Lambertian, Material, Metal,
+};
+use crate::parse::{
+    number, strip_comment, vec3, ParseError,
+};
+use crate::triangle::TriangleMesh;
+use crate::vec3::{Vec3, F};
+use crate::{Error, Res};
+
+/// A material from an `.mtl` file, reduced to what we can render.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ObjMaterial {
+    Diffuse { albedo: Vec3 },
+    Metal { albedo: Vec3, fuzz: F },
+    Glass { reflective_index: F },
+}
+
+impl ObjMaterial {
+    /// Used for faces that don't name a material.
+    pub const DEFAULT: ObjMaterial =
+        ObjMaterial::Diffuse {
+            albedo: Vec3 {
+                x: 0.5,
+                y: 0.5,
+                z: 0.5,
+            },
+        };
+
+    pub fn to_material(
+        &self,
+    ) -> Box<dyn Material + Send + Sync> {
+        match *self {
+            ObjMaterial::Diffuse { albedo } => {
+                Box::new(Lambertian::new(albedo))
+            }
+            ObjMaterial::Metal { albedo, fuzz } => {
+                Box::new(Metal::new(albedo, fuzz))
+            }
+            ObjMaterial::Glass { reflective_index } => {
+                Box::new(Dialectric {
+                    reflective_index,
+                })
+            }
+        }
+    }
+}
+
+/// Fields of an `.mtl` entry that we look at.
+struct MtlEntry {
+    kd: Vec3,
+    ks: Vec3,
+    ns: F,
+    ni: F,
+    dissolve: F,
+    illum: u32,
+}
+
+impl Default for MtlEntry {
+    fn default() -> Self {
+        MtlEntry {
+            kd: Vec3::from(0.8),
+            ks: Vec3::ZERO,
+            ns: 0.,
+            ni: 1.5,
+            dissolve: 1.,
+            illum: 2,
+        }
+    }
+}
+
+impl MtlEntry {
+    /// Illumination models 4, 6, 7 and 9 are the transparent ones,
+    /// as is anything not fully opaque. Models 3 and 5 are mirrors,
+    /// with `Ns` giving the fuzz. Everything else is diffuse.
+    fn to_obj_material(&self) -> ObjMaterial {
+        if matches!(self.illum, 4 | 6 | 7 | 9)
+            || self.dissolve < 1.
+        {
+            ObjMaterial::Glass {
+                reflective_index: if self.ni > 1. {
+                    self.ni
+                } else {
+                    1.5
+                },
+            }
+        } else if matches!(self.illum, 3 | 5) {
+            ObjMaterial::Metal {
+                albedo: self.ks,
+                // Phong exponent to a rough equivalent roughness.
+                fuzz: (2. / (self.ns + 2.)).sqrt(),
+            }
+        } else {
+            ObjMaterial::Diffuse { albedo: self.kd }
+        }
+    }
+}
+
+/// Read an `.obj` file (and any `.mtl` libraries it refers to, relative to it).
+///
+/// Gives one mesh for each group/material combination.
+pub fn load_obj(path: &Path) -> Res<Vec<TriangleMesh>> {
+    let file =
+        File::open(path).map_err(Error::io(path))?;
+    let directory =
+        path.parent().unwrap_or_else(|| Path::new(""));
+    parse_obj(BufReader::new(file), path, |name| {
+        load_mtl(&directory.join(name))
+    })
+}
+
+/// Read the materials from an `.mtl` file.
+pub fn load_mtl(
+    path: &Path,
+) -> Res<HashMap<String, ObjMaterial>> {
+    let file =
+        File::open(path).map_err(Error::io(path))?;
+    parse_mtl(BufReader::new(file), path)
+}
+
+pub fn parse_mtl(
+    source: impl BufRead,
+    path: &Path,
+) -> Res<HashMap<String, ObjMaterial>> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlEntry)> = None;
+
+    for (line_number, line) in
+        source.lines().enumerate()
+    {
+        let line = line.map_err(Error::io(path))?;
+        let error = |message: String| {
+            ParseError::new(
+                path,
+                Some(line_number + 1),
+                message,
+            )
+        };
+        let mut tokens =
+            strip_comment(&line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name =
+                tokens.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err(error(
+                    "newmtl needs a name".to_owned(),
+                )
+                .into());
+            }
+            if let Some((name, entry)) = current.take()
+            {
+                materials.insert(
+                    name,
+                    entry.to_obj_material(),
+                );
+            }
+            current = Some((name, MtlEntry::default()));
+            continue;
+        }
+
+        let entry = match &mut current {
+            Some((_, entry)) => entry,
+            None => {
+                return Err(error(format!(
+                    "{} before any newmtl",
+                    keyword
+                ))
+                .into())
+            }
+        };
+        match keyword {
+            "Kd" => {
+                entry.kd = vec3(&mut tokens, "Kd")
+                    .map_err(error)?
+            }
+            "Ks" => {
+                entry.ks = vec3(&mut tokens, "Ks")
+                    .map_err(error)?
+            }
+            "Ns" => {
+                entry.ns = number(tokens.next(), "Ns")
+                    .map_err(error)?
+            }
+            "Ni" => {
+                entry.ni = number(tokens.next(), "Ni")
+                    .map_err(error)?
+            }
+            "d" => {
+                entry.dissolve =
+                    number(tokens.next(), "d")
+                        .map_err(error)?
+            }
+            "Tr" => {
+                entry.dissolve = 1.
+                    - number(tokens.next(), "Tr")
+                        .map_err(error)?
+            }
+            "illum" => {
+                entry.illum =
+                    number(tokens.next(), "illum")
+                        .map_err(error)?
+                        as u32
+            }
+            // Texture maps, ambient colour etc. aren't used.
+            _ => {}
+        }
+    }
+    if let Some((name, entry)) = current {
+        materials.insert(name, entry.to_obj_material());
+    }
+    Ok(materials)
+}
+
+/// One vertex of a face: indexes into the position, uv and normal lists.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Triangles collected for one group and material.
+struct MeshBuilder {
+    material: ObjMaterial,
+    vertex_ids: HashMap<FaceVertex, usize>,
+    vertices: Vec<FaceVertex>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: ObjMaterial) -> Self {
+        MeshBuilder {
+            material,
+            vertex_ids: HashMap::new(),
+            vertices: vec![],
+            triangles: vec![],
+        }
+    }
+
+    fn vertex(&mut self, v: FaceVertex) -> usize {
+        let vertices = &mut self.vertices;
+        *self.vertex_ids.entry(v).or_insert_with(|| {
+            vertices.push(v);
+            vertices.len() - 1
+        })
+    }
+
+    /// Normals and uvs are only kept if every vertex has one.
+    fn build(
+        self,
+        positions: &[Vec3],
+        uvs: &[(F, F)],
+        normals: &[Vec3],
+    ) -> Res<TriangleMesh> {
+        let mesh_uvs = self
+            .vertices
+            .iter()
+            .map(|(_, uv, _)| uv.map(|i| uvs[i]))
+            .collect::<Option<Vec<_>>>();
+        let mesh_normals = self
+            .vertices
+            .iter()
+            .map(|(_, _, n)| n.map(|i| normals[i]))
+            .collect::<Option<Vec<_>>>();
+        TriangleMesh::new(
+            self.vertices
+                .iter()
+                .map(|(p, _, _)| positions[*p])
+                .collect(),
+            self.triangles,
+            mesh_normals,
+            mesh_uvs,
+            self.material.to_material(),
+        )
+    }
+}
+
+/// Parse `.obj` statements. `load_mtl` is given each material library name.
+pub fn parse_obj(
+    source: impl BufRead,
+    path: &Path,
+    mut load_mtl: impl FnMut(
+        &str,
+    ) -> Res<
+        HashMap<String, ObjMaterial>,
+    >,
+) -> Res<Vec<TriangleMesh>> {
+    let mut positions: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(F, F)> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+
+    let mut materials: HashMap<String, ObjMaterial> =
+        HashMap::new();
+    let mut current_material = ObjMaterial::DEFAULT;
+
+    let mut finished: Vec<MeshBuilder> = vec![];
+    let mut current =
+        MeshBuilder::new(ObjMaterial::DEFAULT);
+
+    for (line_number, line) in
+        source.lines().enumerate()
+    {
+        let line = line.map_err(Error::io(path))?;
+        let error = |message: String| {
+            ParseError::new(
+                path,
+                Some(line_number + 1),
+                message,
+            )
+        };
+        let mut tokens =
+            strip_comment(&line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+
+        match keyword {
+            "v" => positions.push(
+                vec3(&mut tokens, "vertex position")
+                    .map_err(error)?,
+            ),
+            "vn" => normals.push(
+                vec3(&mut tokens, "vertex normal")
+                    .map_err(error)?
+                    .unit(),
+            ),
+            "vt" => {
+                let u =
+                    number(tokens.next(), "texture u")
+                        .map_err(error)?;
+                let v = match tokens.next() {
+                    Some(v) => {
+                        number(Some(v), "texture v")
+                            .map_err(error)?
+                    }
+                    None => 0.,
+                };
+                uvs.push((u, v));
+            }
+            "f" => {
+                let face = tokens
+                    .map(|token| {
+                        face_vertex(
+                            token,
+                            positions.len(),
+                            uvs.len(),
+                            normals.len(),
+                        )
+                        .map_err(error)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if face.len() < 3 {
+                    return Err(error(format!(
+                        "face needs at least three vertices, found {}",
+                        face.len()
+                    ))
+                    .into());
+                }
+                // Fan triangulation: fine for the convex polygons exporters write.
+                let first = current.vertex(face[0]);
+                for pair in face[1..].windows(2) {
+                    let b = current.vertex(pair[0]);
+                    let c = current.vertex(pair[1]);
+                    current
+                        .triangles
+                        .push([first, b, c]);
+                }
+            }
+            "g" | "o" | "usemtl" => {
+                if keyword == "usemtl" {
+                    let name = tokens
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    current_material = materials
+                        .get(&name)
+                        .ok_or_else(|| {
+                            error(format!(
+                                "unknown material {:?}",
+                                name
+                            ))
+                        })?
+                        .clone();
+                }
+                let next = MeshBuilder::new(
+                    current_material.clone(),
+                );
+                let previous = std::mem::replace(
+                    &mut current,
+                    next,
+                );
+                if !previous.triangles.is_empty() {
+                    finished.push(previous);
+                }
+            }
+            "mtllib" => {
+                for name in tokens {
+                    materials.extend(load_mtl(name)?);
+                }
+            }
+            // Smoothing groups, lines, points, curves etc. are ignored.
+            _ => {}
+        }
+    }
+    if !current.triangles.is_empty() {
+        finished.push(current);
+    }
+
+    finished
+        .into_iter()
+        .map(|m| m.build(&positions, &uvs, &normals))
+        .collect()
+}
+
+/// Parse `v`, `v/vt`, `v//vn` or `v/vt/vn`, given how many of each are
+/// defined so far (for negative, relative indices).
+fn face_vertex(
+    token: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<FaceVertex, String> {
+    let mut parts = token.split('/');
+    let index = |part: Option<&str>,
+                 count: usize,
+                 what: &str|
+     -> Result<Option<usize>, String> {
+        let part = match part {
+            None | Some("") => return Ok(None),
+            Some(p) => p,
+        };
+        let i: i64 = part.parse().map_err(|_| {
+            format!("invalid {} index {:?}", what, part)
+        })?;
+        let resolved = if i < 0 {
+            count as i64 + i
+        } else {
+            i - 1
+        };
+        if i == 0
+            || resolved < 0
+            || resolved >= count as i64
+        {
+            return Err(format!(
+                "{} index {} out of range (have {})",
+                what, i, count
+            ));
+        }
+        Ok(Some(resolved as usize))
+    };
+
+    let position =
+        index(parts.next(), position_count, "vertex")?
+            .ok_or_else(|| {
+                format!(
+                    "face vertex {:?} has no position",
+                    token
+                )
+            })?;
+    let uv = index(parts.next(), uv_count, "texture")?;
+    let normal =
+        index(parts.next(), normal_count, "normal")?;
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(
+        obj: &str,
+        mtl: &str,
+    ) -> Res<Vec<TriangleMesh>> {
+        parse_obj(
+            obj.as_bytes(),
+            Path::new("t.obj"),
+            |_| {
+                parse_mtl(
+                    mtl.as_bytes(),
+                    Path::new("t.mtl"),
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn quads_groups_and_negative_indices() {
+        let meshes = parse(
+            "
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vn 0 0 1
+            g first
+            f 1//1 2//1 3//1 4//1
+            g second
+            f -4 -3 -2
+            ",
+            "",
+        )
+        .unwrap();
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(meshes[0].triangle_count(), 2);
+        assert_eq!(meshes[1].triangle_count(), 1);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let err = parse("v 0 0 0\nv 1 x 0\n", "")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "t.obj:2: vertex position should be a number, not \"x\""
+        );
+
+        let err = parse("v 0 0 0\n\nf 1 2 3\n", "")
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "t.obj:3: vertex index 2 out of range (have 1)"
+        );
+
+        let err =
+            parse("usemtl nope\n", "").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "t.obj:1: unknown material \"nope\""
+        );
+    }
+
+    #[test]
+    fn mtl_mapping() {
+        let materials = parse_mtl(
+            "
+            newmtl red
+            Kd 1 0 0
+            newmtl mirror
+            illum 3
+            Ks 0.9 0.9 0.9
+            Ns 1000
+            newmtl glass
+            illum 2
+            d 0.2
+            Ni 1.33
+            "
+            .as_bytes(),
+            Path::new("t.mtl"),
+        )
+        .unwrap();
+        assert_eq!(
+            materials["red"],
+            ObjMaterial::Diffuse {
+                albedo: Vec3::new(1., 0., 0.)
+            }
+        );
+        assert!(matches!(
+            materials["mirror"],
+            ObjMaterial::Metal { fuzz, .. } if fuzz < 0.05
+        ));
+        assert_eq!(
+            materials["glass"],
+            ObjMaterial::Glass {
+                reflective_index: 1.33
+            }
+        );
+    }
+}
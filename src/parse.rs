@@ -0,0 +1,118 @@
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use crate::vec3::{Vec3, F};
+
+/// A problem in a text file we were asked to read, with where it happened.
+#[derive(Debug)]
+pub struct ParseError {
+    pub path: PathBuf,
+    /// One-based. None if the problem isn't with any particular line.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(
+        path: &Path,
+        line: Option<usize>,
+        message: impl Into<String>,
+    ) -> Self {
+        ParseError {
+            path: path.to_owned(),
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parse a single number, naming what it was meant to be on failure.
+pub fn number(
+    token: Option<&str>,
+    what: &str,
+) -> Result<F, String> {
+    let token = token
+        .ok_or_else(|| format!("missing {}", what))?;
+    token.parse::<F>().map_err(|_| {
+        format!(
+            "{} should be a number, not {:?}",
+            what, token
+        )
+    })
+}
+
+/// Parse three numbers as a vector.
+pub fn vec3<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    what: &str,
+) -> Result<Vec3, String> {
+    Ok(Vec3::new(
+        number(tokens.next(), what)?,
+        number(tokens.next(), what)?,
+        number(tokens.next(), what)?,
+    ))
+}
+
+/// Remove a trailing `#` comment and surrounding whitespace.
+pub fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+    .trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_display() {
+        let path = Path::new("a/b.obj");
+        assert_eq!(
+            ParseError::new(path, Some(3), "oh no")
+                .to_string(),
+            "a/b.obj:3: oh no"
+        );
+        assert_eq!(
+            ParseError::new(path, None, "missing")
+                .to_string(),
+            "a/b.obj: missing"
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        let mut t = "1 2.5 -3 x".split_whitespace();
+        assert_eq!(
+            vec3(&mut t, "point"),
+            Ok(Vec3::new(1., 2.5, -3.))
+        );
+        assert_eq!(
+            number(t.next(), "radius"),
+            Err("radius should be a number, not \"x\""
+                .to_owned())
+        );
+        assert_eq!(
+            number(t.next(), "radius"),
+            Err("missing radius".to_owned())
+        );
+        assert_eq!(strip_comment("  v 1 # hi"), "v 1");
+    }
+}
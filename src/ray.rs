@@ -4,11 +4,33 @@ use crate::vec3::F;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    /// When the ray was sent, for things that move. They go from where
+    /// they are at time 0 to where they are at time 1.
+    pub time: F,
 }
 
 impl Ray {
+    /// A ray at time 0.
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time: 0.,
+        }
+    }
+
+    /// A ray from `origin` at the same time as this one, as when it
+    /// bounces or is followed by a shadow ray.
+    pub fn next(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+    ) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time: self.time,
+        }
     }
 
     pub fn point_at(&self, t: F) -> Vec3 {
@@ -25,6 +47,7 @@ mod tests {
         let r = Ray {
             origin: Vec3::new(1., 2., 3.),
             direction: Vec3::new(0.1, 0.2, 0.3),
+            time: 0.,
         };
 
         assert_eq!(
@@ -0,0 +1,652 @@
+//! Turning a scene into an image: path tracing, spread over threads
+//! a tile at a time.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::adaptive::{RunningStats, Sampling};
+use crate::aov::Aov;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::hitable::{Hit, Hitable};
+use crate::image::Image;
+use crate::material::Scatter;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::scene::Scene;
+use crate::tiles::{Tile, TileQueue};
+use crate::vec3::{Vec3, F};
+use crate::{Error, Res};
+
+const MAX_DEPTH: i32 = 50;
+/// Width and height of the squares the image is divided into for rendering.
+const TILE_SIZE: usize = 32;
+/// How often progress is reported while waiting for tiles.
+const PROGRESS_INTERVAL: Duration =
+    Duration::from_millis(300);
+
+/// Renders scenes. Set up with `new` and the methods that follow it,
+/// then call `render`:
+///
+/// ```no_run
+/// # use chambray::{CameraSettings, Renderer, Res, Scene};
+/// # fn main() -> Res<()> {
+/// let scene = Scene::new();
+/// let camera = CameraSettings::default().camera(2.)?;
+/// let image = Renderer::new(400, 200)
+///     .samples(16)
+///     .threads(4)
+///     .on_progress(|p| eprintln!("{:.0}%", p.fraction() * 100.))
+///     .render(scene, &camera)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Renderer<'a> {
+    width: usize,
+    height: usize,
+    sampling: Sampling,
+    threads: usize,
+    aovs: Vec<Aov>,
+    progress: Option<ProgressCallback<'a>>,
+}
+
+type ProgressCallback<'a> =
+    Box<dyn FnMut(&Progress) + 'a>;
+
+/// How far along a render is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub tiles_done: usize,
+    pub tile_count: usize,
+    /// Since rendering started, including building the BVH.
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// From 0 to 1.
+    pub fn fraction(&self) -> F {
+        self.tiles_done as F
+            / self.tile_count.max(1) as F
+    }
+}
+
+/// Everything `Renderer::render_layers` makes.
+pub struct Rendered {
+    pub image: Image,
+    /// An image for each AOV asked for, in the same order.
+    pub aovs: Vec<(Aov, Image)>,
+    /// How many samples each pixel took, in every channel.
+    pub sample_counts: Image,
+}
+
+impl<'a> Renderer<'a> {
+    /// A `width` by `height` image, with 64 samples per pixel, on as
+    /// many threads as there are cores.
+    pub fn new(width: usize, height: usize) -> Self {
+        Renderer {
+            width,
+            height,
+            sampling: Sampling::fixed(64),
+            threads: thread::available_parallelism()
+                .map_or(1, |n| n.get()),
+            aovs: vec![],
+            progress: None,
+        }
+    }
+
+    /// The same number of samples for every pixel, keeping the sampler
+    /// and seed.
+    pub fn samples(mut self, samples: u16) -> Self {
+        self.sampling = Sampling {
+            sampler: self.sampling.sampler,
+            seed: self.sampling.seed,
+            ..Sampling::fixed(samples)
+        };
+        self
+    }
+
+    /// Full control over sampling, including adaptive sampling.
+    pub fn sampling(
+        mut self,
+        sampling: Sampling,
+    ) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Also render these, for `render_layers`.
+    pub fn aovs(mut self, aovs: &[Aov]) -> Self {
+        self.aovs = aovs.to_vec();
+        self
+    }
+
+    /// Called on the rendering thread as each tile is finished, and
+    /// every so often in between.
+    pub fn on_progress(
+        mut self,
+        progress: impl FnMut(&Progress) + 'a,
+    ) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Render the image.
+    pub fn render(
+        self,
+        scene: Scene,
+        camera: &Camera,
+    ) -> Res<Image> {
+        Ok(self.render_layers(scene, camera)?.image)
+    }
+
+    /// Render the image, an image for each of the AOVs asked for, and
+    /// how many samples each pixel took.
+    pub fn render_layers(
+        self,
+        mut scene: Scene,
+        camera: &Camera,
+    ) -> Res<Rendered> {
+        if self.width == 0
+            || self.height == 0
+            || self.sampling.samples == 0
+        {
+            return Err(Error::InvalidParameter(format!(
+                "can't render {}x{} pixels with {} samples each",
+                self.width, self.height, self.sampling.samples
+            )));
+        }
+        let start = Instant::now();
+        scene.build_bvh();
+
+        let Renderer {
+            width,
+            height,
+            sampling,
+            threads,
+            aovs,
+            progress: mut report,
+        } = self;
+        let aovs = &aovs;
+        let queue =
+            TileQueue::new(width, height, TILE_SIZE);
+        let (tx, rx) = mpsc::channel::<RenderedTile>();
+        let scene = &scene;
+
+        thread::scope(|s| {
+            let mut workers = vec![];
+            for _ in 0..threads {
+                let (queue, tx) = (&queue, tx.clone());
+                workers.push(s.spawn(move || {
+                    while let Some(tile) = queue.next()
+                    {
+                        let rendered = render_image(
+                            scene, camera, width,
+                            height, tile, sampling,
+                            aovs,
+                        );
+                        if tx.send(rendered).is_err() {
+                            // Nobody is listening any more.
+                            break;
+                        }
+                    }
+                }));
+            }
+            // Only the workers hold senders now, so the channel closes
+            // when they're all done.
+            drop(tx);
+
+            let mut collected = Rendered {
+                image: Image::new(width, height),
+                aovs: aovs
+                    .iter()
+                    .map(|&aov| {
+                        (aov, Image::new(width, height))
+                    })
+                    .collect(),
+                sample_counts: Image::new(
+                    width, height,
+                ),
+            };
+            let mut progress = Progress {
+                tiles_done: 0,
+                tile_count: queue.len(),
+                elapsed: Duration::ZERO,
+            };
+            loop {
+                match rx.recv_timeout(PROGRESS_INTERVAL)
+                {
+                    Ok(tile) => {
+                        collected.add(tile);
+                        progress.tiles_done += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(
+                        RecvTimeoutError::Disconnected,
+                    ) => break,
+                }
+                if let Some(report) = &mut report {
+                    progress.elapsed = start.elapsed();
+                    report(&progress);
+                }
+            }
+
+            for worker in workers {
+                // Joining here, rather than leaving it to the scope,
+                // turns a panic into an error.
+                worker.join().map_err(|panic| {
+                    let message = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| {
+                            panic
+                                .downcast_ref::<String>()
+                                .cloned()
+                        })
+                        .unwrap_or_default();
+                    Error::WorkerPanic(format!(
+                        "A rendering thread failed after {} of {} tiles: {}",
+                        progress.tiles_done,
+                        progress.tile_count,
+                        message
+                    ))
+                })?;
+            }
+            Ok(collected)
+        })
+    }
+}
+
+impl Rendered {
+    /// Put a finished tile into the images.
+    fn add(&mut self, tile: RenderedTile) {
+        let RenderedTile {
+            tile,
+            pixels,
+            aovs,
+            sample_counts,
+        } = tile;
+        let counts = sample_counts
+            .into_iter()
+            .map(|n| Color::from(Vec3::from(n as F)))
+            .collect();
+        for (image, pixels) in
+            std::iter::once((&mut self.image, pixels))
+                .chain(
+                    self.aovs
+                        .iter_mut()
+                        .map(|(_, image)| image)
+                        .zip(aovs),
+                )
+                .chain(std::iter::once((
+                    &mut self.sample_counts,
+                    counts,
+                )))
+        {
+            for (n, color) in
+                pixels.into_iter().enumerate()
+            {
+                image.set(
+                    tile.x + n % tile.width,
+                    tile.y + n / tile.width,
+                    color,
+                );
+            }
+        }
+    }
+}
+
+/// A finished tile, sent from a worker thread to be put in the image.
+struct RenderedTile {
+    tile: Tile,
+    /// Row by row, from the tile's bottom left.
+    pixels: Vec<Color>,
+    /// The same for each AOV asked for.
+    aovs: Vec<Vec<Color>>,
+    /// How many samples each pixel took.
+    sample_counts: Vec<u16>,
+}
+
+/// Light arriving back along the ray.
+///
+/// `bsdf_pdf` is the density with which the ray was picked by the last
+/// material it scattered from, if it came from `Scatter::Diffuse`:
+/// lights it hits were also sampled directly, so their contributions
+/// are weighted between the two.
+fn ray_color(
+    ray: &Ray,
+    scene: &Scene,
+    depth: i32,
+    bsdf_pdf: Option<F>,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let hit =
+        scene.hit(ray, &((0.001 as F)..F::INFINITY));
+    hit_color(ray, hit, scene, depth, bsdf_pdf, sampler)
+}
+
+/// Like `ray_color`, for a ray that has already been traced to `hit`.
+fn hit_color(
+    ray: &Ray,
+    hit: Option<Hit>,
+    scene: &Scene,
+    depth: i32,
+    bsdf_pdf: Option<F>,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let hit = match hit {
+        Some(hit) => hit,
+        None => return scene.background.color(ray),
+    };
+    if depth > MAX_DEPTH {
+        return Color::black();
+    }
+
+    let mut emitted = hit.material.emitted(&hit);
+    if let Some(bsdf_pdf) = bsdf_pdf {
+        if emitted != Color::black() {
+            let light_pdf = scene.light_pdf(
+                &ray.origin,
+                &ray.direction.unit(),
+            );
+            emitted = emitted.attenuate(Vec3::from(
+                power_heuristic(bsdf_pdf, light_pdf),
+            ));
+        }
+    }
+
+    match hit.material.scatter(ray, &hit, sampler) {
+        Scatter::Scattered {
+            ray: scattered_ray,
+            attenuation,
+        } => {
+            emitted
+                + ray_color(
+                    &scattered_ray,
+                    scene,
+                    depth + 1,
+                    None,
+                    sampler,
+                )
+                .attenuate(attenuation)
+        }
+        Scatter::Diffuse {
+            ray: scattered_ray,
+            attenuation,
+            pdf,
+        } => {
+            emitted
+                + direct_light(
+                    ray, &hit, scene, sampler,
+                )
+                + ray_color(
+                    &scattered_ray,
+                    scene,
+                    depth + 1,
+                    Some(pdf),
+                    sampler,
+                )
+                .attenuate(attenuation)
+        }
+        Scatter::Stopped => emitted,
+    }
+}
+
+/// Light reaching the hit straight from a randomly chosen light
+/// (next event estimation), weighted against the chance of the
+/// material having scattered that way itself.
+fn direct_light(
+    ray: &Ray,
+    hit: &Hit,
+    scene: &Scene,
+    sampler: &mut dyn Sampler,
+) -> Color {
+    let direction = match scene
+        .random_light_direction(&hit.p, sampler)
+    {
+        Some(d) => d,
+        None => return Color::black(),
+    };
+    let reflected =
+        hit.material.eval(ray, hit, &direction);
+    if reflected == Vec3::ZERO {
+        return Color::black();
+    }
+    let light_pdf = scene.light_pdf(&hit.p, &direction);
+    if light_pdf <= 0. {
+        return Color::black();
+    }
+
+    let shadow_ray = ray.next(hit.p, direction);
+    let light_hit = match scene
+        .hit(&shadow_ray, &((0.001 as F)..F::INFINITY))
+    {
+        Some(h) => h,
+        None => return Color::black(),
+    };
+    let bsdf_pdf =
+        hit.material.pdf(ray, hit, &direction);
+    let weight = power_heuristic(light_pdf, bsdf_pdf);
+    light_hit
+        .material
+        .emitted(&light_hit)
+        .attenuate(reflected * (weight / light_pdf))
+}
+
+/// Multiple importance sampling weight for a sample taken with density
+/// `pdf`, when `other_pdf` could also have produced it.
+fn power_heuristic(pdf: F, other_pdf: F) -> F {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0. {
+        0.
+    } else {
+        a / (a + b)
+    }
+}
+
+/// Render every sample of one tile of the image, and of each of
+/// `aovs`.
+fn render_image(
+    scene: &Scene,
+    camera: &Camera,
+    width: usize,
+    height: usize,
+    tile: Tile,
+    sampling: Sampling,
+    aovs: &[Aov],
+) -> RenderedTile {
+    let mut pixels =
+        Vec::with_capacity(tile.pixel_count());
+    let mut aov_pixels =
+        vec![
+            Vec::with_capacity(tile.pixel_count());
+            aovs.len()
+        ];
+    let mut sample_counts =
+        Vec::with_capacity(tile.pixel_count());
+    let mut sampler = sampling
+        .sampler
+        .build(sampling.samples, sampling.seed);
+
+    for j in tile.y..tile.y + tile.height {
+        for i in tile.x..tile.x + tile.width {
+            let mut color_samples = Color::black();
+            let mut aov_samples =
+                vec![Vec3::ZERO; aovs.len()];
+            let mut stats = RunningStats::default();
+
+            while sampling.wants_more(&stats) {
+                sampler.start_sample(
+                    i,
+                    j,
+                    stats.count(),
+                );
+                let (dx, dy) = sampler.get_2d();
+                let ray: Ray = camera.ray(
+                    (i as F + dx) / (width as F),
+                    (j as F + dy) / (height as F),
+                    sampler.as_mut(),
+                );
+
+                let color = if aovs.is_empty() {
+                    ray_color(
+                        &ray,
+                        scene,
+                        0,
+                        None,
+                        sampler.as_mut(),
+                    )
+                } else {
+                    // The AOVs and the colour share the first hit.
+                    let hit = scene.hit_with_ids(
+                        &ray,
+                        &((0.001 as F)..F::INFINITY),
+                    );
+                    for (sum, aov) in
+                        aov_samples.iter_mut().zip(aovs)
+                    {
+                        if stats.count() == 0
+                            || aov.is_averaged()
+                        {
+                            *sum += aov.value(
+                                camera,
+                                scene,
+                                &ray,
+                                hit.as_ref(),
+                            );
+                        }
+                    }
+                    hit_color(
+                        &ray,
+                        hit.map(|(hit, _)| hit),
+                        scene,
+                        0,
+                        None,
+                        sampler.as_mut(),
+                    )
+                };
+                stats.push(color.luminance());
+                color_samples += color;
+            }
+
+            let count = stats.count() as F;
+            pixels.push(color_samples.darken(count));
+            for ((out, sum), aov) in aov_pixels
+                .iter_mut()
+                .zip(aov_samples)
+                .zip(aovs)
+            {
+                out.push(Color::from(
+                    if aov.is_averaged() {
+                        sum / count
+                    } else {
+                        sum
+                    },
+                ));
+            }
+            sample_counts.push(stats.count() as u16);
+        }
+    }
+
+    RenderedTile {
+        tile,
+        pixels,
+        aovs: aov_pixels,
+        sample_counts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::Aabb;
+    use crate::camera::CameraSettings;
+    use std::ops::Range;
+
+    struct Broken;
+
+    impl Hitable for Broken {
+        fn hit(
+            &self,
+            _ray: &Ray,
+            _t: &Range<F>,
+        ) -> Option<Hit<'_>> {
+            panic!("broken");
+        }
+
+        fn bounding_box(&self) -> Option<Aabb> {
+            None
+        }
+    }
+
+    #[test]
+    fn a_panicking_worker_is_an_error() {
+        let mut scene = Scene::new();
+        scene.add(Box::new(Broken));
+        let camera = CameraSettings::default()
+            .camera(1.)
+            .unwrap();
+        let result = Renderer::new(8, 8)
+            .samples(1)
+            .threads(2)
+            .render(scene, &camera);
+        match result {
+            Err(Error::WorkerPanic(message)) => {
+                assert!(message.ends_with("broken"))
+            }
+            _ => panic!("expected a worker panic"),
+        }
+    }
+
+    #[test]
+    fn nothing_to_render_is_an_error() {
+        let camera = CameraSettings::default()
+            .camera(1.)
+            .unwrap();
+        for renderer in [
+            Renderer::new(0, 8),
+            Renderer::new(8, 0),
+            Renderer::new(8, 8).samples(0),
+        ] {
+            assert!(matches!(
+                renderer.render(Scene::new(), &camera),
+                Err(Error::InvalidParameter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn threads_dont_change_the_image() {
+        use crate::scenes::{random_scene, SceneRng};
+        use rand::SeedableRng;
+
+        let camera = CameraSettings::default()
+            .camera(1.5)
+            .unwrap();
+        let render = |threads| {
+            let scene = random_scene(
+                &mut SceneRng::seed_from_u64(5),
+            );
+            Renderer::new(96, 64)
+                .sampling(Sampling {
+                    noise_threshold: Some(0.2),
+                    max_samples: 16,
+                    seed: 5,
+                    ..Sampling::fixed(2)
+                })
+                .threads(threads)
+                .render_layers(scene, &camera)
+                .unwrap()
+        };
+        let (one, four) = (render(1), render(4));
+        assert!(one.image == four.image);
+        assert!(
+            one.sample_counts == four.sample_counts
+        );
+    }
+}
@@ -0,0 +1,643 @@
+//! Where the random numbers for each sample come from.
+//!
+//! A sample asks for values one dimension at a time: the spot in the
+//! pixel, the spot on the lens, then a few for each bounce. Spreading
+//! each dimension's values evenly over a pixel's samples, rather than
+//! picking them independently, makes images converge faster.
+//!
+//! Every value is worked out from the seed, pixel, sample index and
+//! dimension by hashing, so nothing depends on which thread renders
+//! which pixel.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::vec3::{Vec3, F, PI};
+
+pub trait Sampler {
+    /// Start the `index`th sample of pixel (`x`, `y`).
+    fn start_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        index: u32,
+    );
+
+    /// The next dimension's value, from 0 up to 1.
+    fn get_1d(&mut self) -> F;
+
+    /// The next two dimensions' values, spread out together.
+    fn get_2d(&mut self) -> (F, F);
+}
+
+/// The samplers to choose from.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+pub enum SamplerKind {
+    /// Every value independently random: white noise.
+    Independent,
+    /// Jittered within a shuffled grid of strata, one per sample.
+    Stratified,
+    /// The Halton sequence, Owen scrambled for each pixel.
+    Halton,
+    /// Sobol (0, 2) points with Owen scrambling, shuffled for each
+    /// pair of dimensions.
+    #[default]
+    Sobol,
+}
+
+impl SamplerKind {
+    /// A sampler for pixels that are each sampled about
+    /// `samples_per_pixel` times. Different seeds give different,
+    /// but equally good, values.
+    pub(crate) fn build(
+        self,
+        samples_per_pixel: u16,
+        seed: u64,
+    ) -> Box<dyn Sampler + Send> {
+        let state = State {
+            seed,
+            ..State::default()
+        };
+        match self {
+            SamplerKind::Independent => {
+                Box::new(Independent(state))
+            }
+            SamplerKind::Stratified => {
+                Box::new(Stratified {
+                    state,
+                    samples_per_pixel: samples_per_pixel
+                        .max(1)
+                        as u32,
+                })
+            }
+            SamplerKind::Halton => {
+                Box::new(Halton(state))
+            }
+            SamplerKind::Sobol => {
+                Box::new(Sobol(state))
+            }
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!(
+                "expected independent, stratified, halton or sobol, not {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for SamplerKind {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        f.write_str(match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        })
+    }
+}
+
+/// Where every sampler is up to.
+#[derive(Debug, Clone, Copy, Default)]
+struct State {
+    seed: u64,
+    /// Hash of the seed and pixel.
+    pixel: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl State {
+    fn start_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        index: u32,
+    ) {
+        self.pixel =
+            hash(self.seed, hash(x as u64, y as u64));
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// A hash unique to this pixel and dimension, moving on to the
+    /// next dimension.
+    fn next_dimension(&mut self) -> u64 {
+        self.dimension += 1;
+        hash(self.pixel, self.dimension as u64)
+    }
+
+    /// A random value for this pixel, sample and dimension.
+    fn random(&mut self) -> F {
+        let h = self.next_dimension();
+        to_unit(hash(h, self.index as u64) as u32)
+    }
+}
+
+struct Independent(State);
+
+impl Sampler for Independent {
+    fn start_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        index: u32,
+    ) {
+        self.0.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> F {
+        self.0.random()
+    }
+
+    fn get_2d(&mut self) -> (F, F) {
+        (self.0.random(), self.0.random())
+    }
+}
+
+struct Stratified {
+    state: State,
+    samples_per_pixel: u32,
+}
+
+impl Stratified {
+    /// Which of `count` strata this sample falls in. Each round of
+    /// `count` samples visits them all, in a shuffled order.
+    fn stratum(&mut self, count: u32) -> (u32, u64) {
+        let state = &mut self.state;
+        let h = state.next_dimension();
+        let round = (state.index / count) as u64;
+        let stratum = permutation_element(
+            state.index % count,
+            count,
+            hash(h, round) as u32,
+        );
+        (stratum, hash(h, state.index as u64 | 1 << 32))
+    }
+}
+
+impl Sampler for Stratified {
+    fn start_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        index: u32,
+    ) {
+        self.state.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> F {
+        let count = self.samples_per_pixel;
+        let (stratum, jitter) = self.stratum(count);
+        (stratum as F + to_unit(jitter as u32))
+            / count as F
+    }
+
+    fn get_2d(&mut self) -> (F, F) {
+        // The largest square grid with no more cells than samples.
+        let side =
+            (self.samples_per_pixel as F).sqrt() as u32;
+        let side = side.max(1);
+        let (stratum, jitter) =
+            self.stratum(side * side);
+        (
+            ((stratum % side) as F
+                + to_unit(jitter as u32))
+                / side as F,
+            ((stratum / side) as F
+                + to_unit((jitter >> 32) as u32))
+                / side as F,
+        )
+    }
+}
+
+struct Halton(State);
+
+/// Bases for the Halton dimensions. Beyond these, values are
+/// independently random.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43,
+    47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97, 101,
+    103, 107, 109, 113, 127, 131,
+];
+
+impl Halton {
+    fn next(&mut self) -> F {
+        let state = &mut self.0;
+        let base = match PRIMES
+            .get(state.dimension as usize)
+        {
+            Some(&base) => base,
+            None => return state.random(),
+        };
+        let seed = state.next_dimension();
+        scrambled_radical_inverse(
+            base,
+            state.index,
+            seed,
+        )
+    }
+}
+
+impl Sampler for Halton {
+    fn start_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        index: u32,
+    ) {
+        self.0.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> F {
+        self.next()
+    }
+
+    fn get_2d(&mut self) -> (F, F) {
+        (self.next(), self.next())
+    }
+}
+
+/// The digits of `index` in `base`, mirrored about the point, with
+/// each digit shuffled by a permutation picked by `seed` and the
+/// digits before it.
+///
+/// Unscrambled, dimensions with large bases line up along diagonals
+/// until there are many samples.
+fn scrambled_radical_inverse(
+    base: u32,
+    mut index: u32,
+    seed: u64,
+) -> F {
+    let inverse_base = 1. / base as f64;
+    let (mut value, mut scale, mut h) =
+        (0., inverse_base, seed);
+    // Carry on past the last digit, since shuffled zeroes aren't.
+    while scale > F::EPSILON as f64 {
+        let digit = index % base;
+        let shuffled =
+            permutation_element(digit, base, h as u32);
+        value += shuffled as f64 * scale;
+        scale *= inverse_base;
+        index /= base;
+        h = hash(h, digit as u64);
+    }
+    (value as F).min(ONE_MINUS_EPSILON)
+}
+
+struct Sobol(State);
+
+impl Sampler for Sobol {
+    fn start_sample(
+        &mut self,
+        x: usize,
+        y: usize,
+        index: u32,
+    ) {
+        self.0.start_sample(x, y, index);
+    }
+
+    fn get_1d(&mut self) -> F {
+        self.get_2d().0
+    }
+
+    /// Burley's "Practical Hash-based Owen Scrambling": shuffle the
+    /// samples, then scramble each coordinate.
+    fn get_2d(&mut self) -> (F, F) {
+        let seed = self.0.next_dimension();
+        let index = nested_uniform_scramble(
+            self.0.index,
+            seed as u32,
+        );
+        let (x, y) =
+            (index.reverse_bits(), sobol_second(index));
+        (
+            to_unit(nested_uniform_scramble(
+                x,
+                hash(seed, 1) as u32,
+            )),
+            to_unit(nested_uniform_scramble(
+                y,
+                hash(seed, 2) as u32,
+            )),
+        )
+    }
+}
+
+/// The second dimension of the Sobol sequence, as 32 bits of fraction.
+fn sobol_second(mut index: u32) -> u32 {
+    let (mut v, mut result) = (1u32 << 31, 0);
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Owen scrambling of a 32-bit fraction, by hashing.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed)
+        .reverse_bits()
+}
+
+/// Higher bits only ever depend on lower bits.
+fn laine_karras_permutation(
+    mut x: u32,
+    seed: u32,
+) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Element `i` of a random permutation of 0..`length`, picked by
+/// `seed`. From Kensler's "Correlated Multi-Jittered Sampling".
+fn permutation_element(
+    mut i: u32,
+    length: u32,
+    seed: u32,
+) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        // Values past the end are walked on from until one fits.
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % length
+}
+
+/// MurmurHash3's finaliser: every bit in affects every bit out.
+fn mix(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+    h
+}
+
+pub(crate) fn hash(a: u64, b: u64) -> u64 {
+    mix(mix(a ^ 0x9e3779b97f4a7c15).wrapping_add(b))
+}
+
+const ONE_MINUS_EPSILON: F = 1. - F::EPSILON / 2.;
+
+/// 32 bits of fraction as a value from 0 up to 1.
+fn to_unit(bits: u32) -> F {
+    ((bits as f64) / (1u64 << 32) as f64)
+        .min(ONE_MINUS_EPSILON as f64) as F
+}
+
+/// Uniform over the unit sphere's surface.
+pub(crate) fn unit_sphere_point(
+    (u, v): (F, F),
+) -> Vec3 {
+    let z = 1. - 2. * u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniform over the unit sphere's inside.
+pub(crate) fn unit_ball_point(
+    uv: (F, F),
+    w: F,
+) -> Vec3 {
+    unit_sphere_point(uv) * w.cbrt()
+}
+
+/// Uniform over the unit disk in the xy plane, keeping nearby values
+/// nearby (Shirley and Chiu's concentric mapping).
+pub(crate) fn unit_disk_point((u, v): (F, F)) -> Vec3 {
+    let (a, b) = (2. * u - 1., 2. * v - 1.);
+    if a == 0. && b == 0. {
+        return Vec3::ZERO;
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4. * (b / a))
+    } else {
+        (b, PI / 2. - PI / 4. * (a / b))
+    };
+    Vec3::new(r * theta.cos(), r * theta.sin(), 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    /// Root mean square error, over a few pixels, of estimating the
+    /// area of a quarter disk from 64 points of one pair of
+    /// dimensions.
+    fn quarter_disk_error(
+        kind: SamplerKind,
+        dimension: usize,
+    ) -> F {
+        let mut sampler = kind.build(64, 0);
+        let mut squared_error = 0.;
+        for pixel in 0..256 {
+            let mut inside = 0;
+            for i in 0..64 {
+                sampler.start_sample(pixel, 7, i);
+                for _ in 0..dimension {
+                    sampler.get_2d();
+                }
+                let (x, y) = sampler.get_2d();
+                assert!(
+                    (0. ..1.).contains(&x)
+                        && (0. ..1.).contains(&y)
+                );
+                if x * x + y * y < 1. {
+                    inside += 1;
+                }
+            }
+            squared_error +=
+                (inside as F / 64. - PI / 4.).powi(2);
+        }
+        (squared_error / 256.).sqrt()
+    }
+
+    #[test]
+    fn low_discrepancy_samplers_converge_faster() {
+        // Halton's later dimensions have big bases, which take more than
+        // 64 samples to spread out.
+        for dimension in [0, 1, 2] {
+            let white_noise = quarter_disk_error(
+                SamplerKind::Independent,
+                dimension,
+            );
+            for kind in &KINDS[1..] {
+                let error = quarter_disk_error(
+                    *kind, dimension,
+                );
+                assert!(
+                    error < white_noise * 0.7,
+                    "{} in dimension {}: {} vs {}",
+                    kind,
+                    dimension,
+                    error,
+                    white_noise
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn values_depend_on_the_seed_and_pixel_not_the_order(
+    ) {
+        for kind in KINDS {
+            let mut a = kind.build(16, 7);
+            let mut b = kind.build(16, 7);
+            a.start_sample(1, 2, 5);
+            let first = (a.get_1d(), a.get_2d());
+            b.start_sample(9, 9, 0);
+            b.get_2d();
+            b.start_sample(1, 2, 5);
+            assert_eq!(
+                (b.get_1d(), b.get_2d()),
+                first,
+                "{}",
+                kind
+            );
+            b.start_sample(2, 1, 5);
+            assert_ne!(
+                (b.get_1d(), b.get_2d()),
+                first,
+                "{}",
+                kind
+            );
+            let mut c = kind.build(16, 8);
+            c.start_sample(1, 2, 5);
+            assert_ne!(
+                (c.get_1d(), c.get_2d()),
+                first,
+                "{}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn permutations_are_permutations() {
+        for length in [1, 5, 16, 100] {
+            let mut seen: Vec<u32> = (0..length)
+                .map(|i| {
+                    permutation_element(i, length, 1234)
+                })
+                .collect();
+            seen.sort();
+            assert_eq!(
+                seen,
+                (0..length).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn radical_inverses_stay_stratified() {
+        // Any `base` values in a row starting at a multiple of `base`
+        // fall one in each `1 / base` of the range.
+        for base in [2, 3, 13] {
+            for seed in 0..4 {
+                let mut strata: Vec<u32> = (base..2
+                    * base)
+                    .map(|i| {
+                        (scrambled_radical_inverse(
+                            base, i, seed,
+                        ) * base as F)
+                            as u32
+                    })
+                    .collect();
+                strata.sort();
+                assert_eq!(
+                    strata,
+                    (0..base).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn disk_points() {
+        for (u, v) in [
+            (0., 0.),
+            (1., 0.5),
+            (0.3, 0.9),
+            (0.5, 0.5),
+        ] {
+            assert!(
+                unit_disk_point((u, v)).length()
+                    <= 1. + 1e-6
+            );
+        }
+        assert!(
+            (unit_disk_point((1., 0.5)).x - 1.).abs()
+                < 1e-6
+        );
+    }
+
+    #[test]
+    fn parse() {
+        for kind in KINDS {
+            assert_eq!(
+                kind.to_string().parse(),
+                Ok(kind)
+            );
+        }
+        assert!("random"
+            .parse::<SamplerKind>()
+            .is_err());
+    }
+}
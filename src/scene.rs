@@ -1,41 +1,450 @@
-use crate::hitable::{Hit, Hitable};
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::color::Color;
+use crate::hitable::{sphere_uv, Hit, Hitable};
+use crate::image::Image;
 use crate::ray::Ray;
-use crate::vec3::F;
+use crate::sampler::Sampler;
+use crate::vec3::{Vec3, F};
+use std::collections::HashMap;
 use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// What rays see when they hit nothing: a vertical gradient.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sky {
+    /// Colour looking straight down.
+    pub bottom: Color,
+    /// Colour looking straight up.
+    pub top: Color,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky {
+            bottom: Color::white(),
+            top: Color::sky_blue(),
+        }
+    }
+}
+
+impl Sky {
+    pub fn color(&self, ray: &Ray) -> Color {
+        let unit_direction = ray.direction.unit();
+        let t = 0.5 * (unit_direction.y + 1.);
+
+        Color::linear(self.bottom, self.top, t)
+    }
+}
+
+/// Light arriving from beyond everything in the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    Sky(Sky),
+    /// The same in every direction. Black for no light at all.
+    Solid(Color),
+    /// A latitude-longitude image wrapped around everything, mapped
+    /// like `sphere_uv`.
+    Environment(Arc<Image>),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background::Sky(Sky::default())
+    }
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Sky(sky) => sky.color(ray),
+            Background::Solid(color) => *color,
+            Background::Environment(image) => {
+                let (u, v) =
+                    sphere_uv(&ray.direction.unit());
+                image.sample(u, v)
+            }
+        }
+    }
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    /// "sky", "none", an "r,g,b" colour or an image file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sky" => Ok(Background::default()),
+            "none" | "black" => {
+                Ok(Background::Solid(Color::black()))
+            }
+            _ => {
+                let c = s
+                    .split(',')
+                    .map(|c| c.trim().parse::<F>())
+                    .collect::<Result<Vec<F>, _>>();
+                match c.as_deref() {
+                    Ok([r, g, b]) => {
+                        Ok(Background::Solid(Color::new(*r, *g, *b)))
+                    }
+                    _ if Path::new(s).extension().is_some() => {
+                        Image::read(Path::new(s))
+                            .map(|image| {
+                                Background::Environment(Arc::new(image))
+                            })
+                            .map_err(|e| e.to_string())
+                    }
+                    _ => Err(format!(
+                        "expected \"sky\", \"none\", an r,g,b colour or an image file, not {:?}",
+                        s
+                    )),
+                }
+            }
+        }
+    }
+}
+
+/// Which thing a ray hit, for telling objects apart in AOVs.
+/// Both count from 1, leaving 0 for nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HitIds {
+    /// The order the thing was added in.
+    pub object: usize,
+    /// Shared by things added with the same material name.
+    pub material: usize,
+}
 
 pub struct Scene {
+    pub background: Background,
     things: Vec<Box<dyn Hitable + Send + Sync>>,
+    /// Material ID of each thing.
+    material_ids: Vec<usize>,
+    /// Material IDs given out to names so far.
+    material_names: HashMap<String, usize>,
+    /// The material ID the next new material gets.
+    next_material_id: usize,
+    /// Acceleration structure over the bounded things.
+    /// Built by `build_bvh`, thrown away when things change.
+    bvh: Option<Bvh>,
+    /// Things without bounds (indexes into `things`), which every ray is tested against.
+    unbounded: Vec<usize>,
+    /// Indexes of bounded things, in the order the bvh knows them.
+    bounded: Vec<usize>,
+    /// Indexes of things that can be sampled as lights.
+    lights: Vec<usize>,
 }
 
 impl Scene {
     pub fn new() -> Self {
-        Scene { things: vec![] }
+        Scene {
+            background: Background::default(),
+            things: vec![],
+            material_ids: vec![],
+            material_names: HashMap::new(),
+            next_material_id: 1,
+            bvh: None,
+            unbounded: vec![],
+            bounded: vec![],
+            lights: vec![],
+        }
     }
 
+    /// Add a thing whose material is its own.
     pub fn add(
         &mut self,
         thing: Box<dyn Hitable + Send + Sync>,
+    ) {
+        let id = self.new_material_id();
+        self.add_with_material_id(thing, id);
+    }
+
+    /// Add a thing whose material is named, so that everything with
+    /// that name gets the same material ID.
+    pub fn add_with_material(
+        &mut self,
+        thing: Box<dyn Hitable + Send + Sync>,
+        material: &str,
+    ) {
+        let id = match self.material_names.get(material)
+        {
+            Some(&id) => id,
+            None => {
+                let id = self.new_material_id();
+                self.material_names
+                    .insert(material.to_owned(), id);
+                id
+            }
+        };
+        self.add_with_material_id(thing, id);
+    }
+
+    fn new_material_id(&mut self) -> usize {
+        self.next_material_id += 1;
+        self.next_material_id - 1
+    }
+
+    fn add_with_material_id(
+        &mut self,
+        thing: Box<dyn Hitable + Send + Sync>,
+        material_id: usize,
     ) {
         self.things.push(thing);
+        self.material_ids.push(material_id);
+        self.bvh = None;
     }
-}
-impl Hitable for Scene {
-    fn hit(
+
+    pub fn len(&self) -> usize {
+        self.things.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.things.is_empty()
+    }
+
+    /// Build the bounding volume hierarchy, so that rays don't have to be tested
+    /// against every thing in the scene.
+    ///
+    /// Call once everything has been added. Until then, hits are found linearly.
+    pub fn build_bvh(&mut self) {
+        self.bounded.clear();
+        self.unbounded.clear();
+        self.lights = (0..self.things.len())
+            .filter(|&i| self.things[i].is_light())
+            .collect();
+        let mut bounds =
+            Vec::with_capacity(self.things.len());
+        for (i, thing) in self.things.iter().enumerate()
+        {
+            match thing.bounding_box() {
+                Some(b) => {
+                    self.bounded.push(i);
+                    bounds.push(b);
+                }
+                None => self.unbounded.push(i),
+            }
+        }
+        self.bvh = Some(Bvh::build(&bounds));
+    }
+
+    /// A random unit direction from `origin` towards one of the lights,
+    /// if there are any.
+    ///
+    /// Lights are found by `build_bvh`.
+    pub fn random_light_direction(
+        &self,
+        origin: &Vec3,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Vec3> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let i = ((sampler.get_1d()
+            * self.lights.len() as F)
+            as usize)
+            .min(self.lights.len() - 1);
+        self.things[self.lights[i]]
+            .random_direction_toward(
+                origin,
+                sampler.get_2d(),
+            )
+    }
+
+    /// Probability density (per solid angle) of `random_light_direction`
+    /// giving unit `direction`.
+    pub fn light_pdf(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> F {
+        if self.lights.is_empty() {
+            return 0.;
+        }
+        let sum: F = self
+            .lights
+            .iter()
+            .map(|&i| {
+                self.things[i]
+                    .pdf_toward(origin, direction)
+            })
+            .sum();
+        sum / self.lights.len() as F
+    }
+
+    /// Like `hit`, but also saying which thing was hit.
+    pub fn hit_with_ids(
         &self,
         ray: &Ray,
         t: &Range<F>,
-    ) -> Option<Hit> {
+    ) -> Option<(Hit<'_>, HitIds)> {
+        let (i, hit) = self.closest_hit(ray, t)?;
+        Some((
+            hit,
+            HitIds {
+                object: i + 1,
+                material: self.material_ids[i],
+            },
+        ))
+    }
+
+    /// The closest hit, and the index of the thing hit.
+    fn closest_hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<(usize, Hit<'_>)> {
+        let bvh = match &self.bvh {
+            Some(bvh) => bvh,
+            None => return self.hit_linear(ray, t),
+        };
+
         let mut hit = None;
         let mut closest_so_far: F = t.end;
+        for &i in &self.unbounded {
+            if let Some(h) = self.things[i]
+                .hit(ray, &(t.start..closest_so_far))
+            {
+                closest_so_far = h.t;
+                hit = Some((i, h));
+            }
+        }
 
-        for thing in &self.things {
+        // Each hit narrows the range for the next, so the last
+        // thing hit is the closest.
+        let mut hit_index = None;
+        bvh.hit(
+            ray,
+            &(t.start..closest_so_far),
+            |i, t| {
+                let h = self.things[self.bounded[i]]
+                    .hit(ray, t);
+                if h.is_some() {
+                    hit_index = Some(self.bounded[i]);
+                }
+                h
+            },
+        )
+        .map(|h| (hit_index.unwrap_or_default(), h))
+        .or(hit)
+    }
+
+    fn hit_linear(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<(usize, Hit<'_>)> {
+        let mut hit = None;
+        let mut closest_so_far: F = t.end;
+
+        for (i, thing) in self.things.iter().enumerate()
+        {
             if let Some(h) = thing
                 .hit(ray, &(t.start..closest_so_far))
             {
                 closest_so_far = h.t;
-                hit = Some(h);
+                hit = Some((i, h));
             }
         }
         hit
     }
 }
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hitable for Scene {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        self.closest_hit(ray, t).map(|(_, hit)| hit)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        match &self.bvh {
+            Some(bvh) => bvh.bounds(),
+            None => self
+                .things
+                .iter()
+                .map(|t| t.bounding_box())
+                .try_fold(Aabb::EMPTY, |acc, b| {
+                    Some(acc.union(&b?))
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::Sphere;
+    use crate::material::Lambertian;
+
+    #[test]
+    fn parse_background() {
+        assert_eq!(
+            "sky".parse(),
+            Ok(Background::Sky(Sky::default()))
+        );
+        assert_eq!(
+            "none".parse(),
+            Ok(Background::Solid(Color::black()))
+        );
+        assert_eq!(
+            "0.1, 0.2,0.3".parse(),
+            Ok(Background::Solid(Color::new(
+                0.1, 0.2, 0.3
+            )))
+        );
+        assert!("1,2".parse::<Background>().is_err());
+    }
+
+    #[test]
+    fn hits_say_what_they_hit() {
+        let sphere = |x: F| {
+            Box::new(Sphere {
+                center: Vec3::new(x, 0., -5.),
+                radius: 1.,
+                material: Box::new(Lambertian::new(
+                    Vec3::ONE,
+                )),
+            })
+        };
+        let mut scene = Scene::new();
+        scene.add_with_material(sphere(0.), "grey");
+        scene.add(sphere(3.));
+        scene.add_with_material(sphere(6.), "grey");
+
+        let ids = |scene: &Scene, x: F| {
+            scene
+                .hit_with_ids(
+                    &Ray::new(
+                        Vec3::new(x, 0., 0.),
+                        Vec3::new(0., 0., -1.),
+                    ),
+                    &(0.001..F::INFINITY),
+                )
+                .map(|(_, ids)| ids)
+        };
+        let ids_of = |object, material| {
+            Some(HitIds { object, material })
+        };
+        for with_bvh in [false, true] {
+            if with_bvh {
+                scene.build_bvh();
+            }
+            assert_eq!(ids(&scene, 0.), ids_of(1, 1));
+            assert_eq!(ids(&scene, 3.), ids_of(2, 2));
+            assert_eq!(ids(&scene, 6.), ids_of(3, 1));
+            assert_eq!(ids(&scene, 9.), None);
+        }
+    }
+}
@@ -0,0 +1,1237 @@
+//! Text scene descriptions.
+//!
+//! A file is a list of blocks. Each block starts with an unindented line
+//! naming its kind, followed by indented `field value...` lines:
+//!
+//! ```text
+//! # Comments start with a hash.
+//! render
+//!     width 800
+//!     height 400
+//!     samples 128
+//!
+//! camera
+//!     look_from 12 6 0.5
+//!     look_at 0 1 0
+//!     fov 20
+//!     aperture 0.6
+//!
+//! sky
+//!     bottom 1 1 1
+//!     top 0.5 0.75 1
+//!
+//! material shiny metal
+//!     albedo 0.7 0.6 0.5
+//!     fuzz 0.1
+//!
+//! texture tiles checker
+//!     odd 0.2 0.3 0.1
+//!     even 0.9 0.9 0.9
+//!     size 0.5
+//!
+//! sphere
+//!     center 0 1 0
+//!     radius 1
+//!     material shiny
+//! ```
+//!
+//! Materials are `lambertian` (albedo, or texture), `metal` (albedo, fuzz),
+//! `dielectric` (index) or `light` (color, strength), and can be used
+//! before they are defined. So can textures, which are named like
+//! materials: `texture <name> <kind>` where kind is `solid` (color),
+//! `checker` (odd, even, size), `marble` or `turbulence` (scale), or
+//! `image` (file: a `.ppm`, `.tga` or `.ff` path).
+//! Other blocks are the shapes `triangle` (a, b, c, material), `quad`
+//! (corner, u and v edges, material), `disk` (center, normal, radius,
+//! material), `plane` (point, normal, material) and `box` (min, max,
+//! material), plus `mesh` (file: an `.obj` path, using its own
+//! materials) and `background` (color, or image), which replaces the sky
+//! with a flat colour or an environment map. Normals default to up.
+//! Shapes and meshes can also be moved with `scale` (x y z factors),
+//! `rotate` (degrees about x, then y, then z) and `translate` (x y z),
+//! applied in that order. A mesh file is only loaded once, however
+//! many times it's placed.
+//!
+//! Things can move while the camera's `shutter` (open and close times,
+//! from 0 to 1) is open: a sphere from `center` at time 0 to
+//! `center_end` at time 1, and anything else by also giving
+//! `scale_end`, `rotate_end` or `translate_end`, which default to
+//! where it starts.
+//! File paths are relative to the scene file.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::camera::{check_shutter, CameraSettings};
+use crate::color::Color;
+use crate::hitable::{Hitable, MovingSphere, Sphere};
+use crate::image::Image;
+use crate::material::{
+    Dialectric, DiffuseLight, Lambertian, Material,
+    Metal,
+};
+use crate::obj::load_obj;
+use crate::parse::{number, strip_comment, ParseError};
+use crate::scene::{Background, Scene, Sky};
+use crate::shapes::{BoxShape, Disk, Plane, Quad};
+use crate::texture::{
+    Checker, ImageTexture, NoiseKind, NoiseTexture,
+    Perlin, SharedTexture, SolidColor,
+};
+use crate::transformed::{Pose, Transformed};
+use crate::triangle::{Triangle, TriangleMesh};
+use crate::vec3::{Vec3, F};
+use crate::{Error, Res};
+
+/// The widest or tallest image a scene file may ask for.
+const MAX_SIDE: usize = 1 << 16;
+
+/// Render options a scene file may set. Unset ones are left to the caller.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples: Option<u16>,
+}
+
+pub struct SceneDescription {
+    pub scene: Scene,
+    pub camera: CameraSettings,
+    pub render: RenderSettings,
+}
+
+/// Read a scene description, with noise textures' random choices made
+/// by `rng`.
+pub fn load(
+    path: &Path,
+    rng: &mut impl Rng,
+) -> Res<SceneDescription> {
+    let source = fs::read_to_string(path)
+        .map_err(Error::io(path))?;
+    parse(&source, path, rng)
+}
+
+enum MaterialDef {
+    Lambertian { albedo: SharedTexture },
+    Metal { albedo: Vec3, fuzz: F },
+    Dielectric { index: F },
+    Light { emit: Color },
+}
+
+impl MaterialDef {
+    fn build(&self) -> Box<dyn Material + Send + Sync> {
+        match *self {
+            MaterialDef::Lambertian { ref albedo } => {
+                Box::new(Lambertian::textured(
+                    albedo.clone(),
+                ))
+            }
+            MaterialDef::Metal { albedo, fuzz } => {
+                Box::new(Metal::new(albedo, fuzz))
+            }
+            MaterialDef::Dielectric { index } => {
+                Box::new(Dialectric {
+                    reflective_index: index,
+                })
+            }
+            MaterialDef::Light { emit } => {
+                Box::new(DiffuseLight { emit })
+            }
+        }
+    }
+}
+
+struct Field<'a> {
+    key: &'a str,
+    values: Vec<&'a str>,
+    line: usize,
+}
+
+/// A block of the file, whose fields are taken one by one as they're
+/// understood. Anything left over is an error.
+struct Block<'a> {
+    path: &'a Path,
+    kind: &'a str,
+    args: Vec<&'a str>,
+    line: usize,
+    fields: Vec<Field<'a>>,
+    taken: HashSet<&'a str>,
+}
+
+impl<'a> Block<'a> {
+    fn error(
+        &self,
+        line: usize,
+        message: impl Into<String>,
+    ) -> ParseError {
+        ParseError::new(self.path, Some(line), message)
+    }
+
+    fn take(
+        &mut self,
+        key: &'a str,
+    ) -> Option<Field<'a>> {
+        self.taken.insert(key);
+        let i = self
+            .fields
+            .iter()
+            .position(|f| f.key == key)?;
+        Some(self.fields.remove(i))
+    }
+
+    fn number(
+        &mut self,
+        key: &'a str,
+    ) -> Result<Option<F>, ParseError> {
+        Ok(self.number_at(key)?.map(|(n, _)| n))
+    }
+
+    /// A number, and the line it's on.
+    fn number_at(
+        &mut self,
+        key: &'a str,
+    ) -> Result<Option<(F, usize)>, ParseError> {
+        let field = match self.take(key) {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        if field.values.len() != 1 {
+            return Err(self.error(
+                field.line,
+                format!(
+                    "{} {} should be one number",
+                    self.kind, key
+                ),
+            ));
+        }
+        number(
+            field.values.first().copied(),
+            &format!("{} {}", self.kind, key),
+        )
+        .map(|n| Some((n, field.line)))
+        .map_err(|m| self.error(field.line, m))
+    }
+
+    /// A whole number from 1 to `max`.
+    fn count(
+        &mut self,
+        key: &'a str,
+        max: usize,
+    ) -> Result<Option<usize>, ParseError> {
+        match self.number_at(key)? {
+            Some((n, _))
+                if n >= 1.
+                    && n <= max as F
+                    && n.fract() == 0. =>
+            {
+                Ok(Some(n as usize))
+            }
+            Some((_, line)) => Err(self.error(
+                line,
+                format!(
+                    "{} {} should be a whole number from 1 to {}",
+                    self.kind, key, max
+                ),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn vec3(
+        &mut self,
+        key: &'a str,
+    ) -> Result<Option<Vec3>, ParseError> {
+        Ok(self
+            .numbers(key)?
+            .map(|[x, y, z]| Vec3::new(x, y, z)))
+    }
+
+    /// Exactly `N` numbers.
+    fn numbers<const N: usize>(
+        &mut self,
+        key: &'a str,
+    ) -> Result<Option<[F; N]>, ParseError> {
+        Ok(self.numbers_at(key)?.map(|(n, _)| n))
+    }
+
+    /// Exactly `N` numbers, and the line they're on.
+    fn numbers_at<const N: usize>(
+        &mut self,
+        key: &'a str,
+    ) -> Result<Option<([F; N], usize)>, ParseError>
+    {
+        let field = match self.take(key) {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let what = format!("{} {}", self.kind, key);
+        if field.values.len() != N {
+            let count = match N {
+                2 => "two".to_owned(),
+                3 => "three".to_owned(),
+                n => n.to_string(),
+            };
+            return Err(self.error(
+                field.line,
+                format!(
+                    "{} should be {} numbers",
+                    what, count
+                ),
+            ));
+        }
+        let mut numbers = [0.; N];
+        for (n, value) in
+            numbers.iter_mut().zip(&field.values)
+        {
+            *n = number(Some(value), &what).map_err(
+                |m| self.error(field.line, m),
+            )?;
+        }
+        Ok(Some((numbers, field.line)))
+    }
+
+    /// The `scale`, `rotate` and `translate` fields, and where they
+    /// end up at time 1 from `scale_end`, `rotate_end` and
+    /// `translate_end`, or None if there are none.
+    fn motion(
+        &mut self,
+    ) -> Result<Option<(Pose, Pose)>, ParseError> {
+        let mut fields = [None; 6];
+        for (field, key) in fields.iter_mut().zip([
+            "scale",
+            "rotate",
+            "translate",
+            "scale_end",
+            "rotate_end",
+            "translate_end",
+        ]) {
+            *field = self.vec3(key)?;
+        }
+        if fields.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        let still = Pose::default();
+        let start = Pose {
+            scale: fields[0].unwrap_or(still.scale),
+            rotate: fields[1].unwrap_or(still.rotate),
+            translate: fields[2]
+                .unwrap_or(still.translate),
+        };
+        let end = Pose {
+            scale: fields[3].unwrap_or(start.scale),
+            rotate: fields[4].unwrap_or(start.rotate),
+            translate: fields[5]
+                .unwrap_or(start.translate),
+        };
+        Ok(Some((start, end)))
+    }
+
+    fn word(
+        &mut self,
+        key: &'a str,
+    ) -> Result<Option<(&'a str, usize)>, ParseError>
+    {
+        let field = match self.take(key) {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        match field.values.as_slice() {
+            [word] => Ok(Some((*word, field.line))),
+            _ => Err(self.error(
+                field.line,
+                format!(
+                    "{} {} should be one word",
+                    self.kind, key
+                ),
+            )),
+        }
+    }
+
+    fn required<T>(
+        &self,
+        value: Option<T>,
+        key: &str,
+    ) -> Result<T, ParseError> {
+        value.ok_or_else(|| {
+            self.error(
+                self.line,
+                format!(
+                    "{} needs a {}",
+                    self.kind, key
+                ),
+            )
+        })
+    }
+
+    /// Complain about any fields we didn't use.
+    fn finish(self) -> Result<(), ParseError> {
+        match self.fields.first() {
+            None => Ok(()),
+            Some(f) if self.taken.contains(f.key) => {
+                Err(self.error(
+                    f.line,
+                    format!(
+                        "{} {} is given more than once",
+                        self.kind, f.key
+                    ),
+                ))
+            }
+            Some(f) => Err(self.error(
+                f.line,
+                format!(
+                    "unknown field {:?} in {}",
+                    f.key, self.kind
+                ),
+            )),
+        }
+    }
+}
+
+/// `thing`, moved by the block's transform fields if it has any.
+fn placed(
+    block: &mut Block,
+    thing: Box<dyn Hitable + Send + Sync>,
+) -> Result<Box<dyn Hitable + Send + Sync>, ParseError>
+{
+    Ok(match block.motion()? {
+        Some((start, end)) => {
+            let thing: Arc<dyn Hitable + Send + Sync> =
+                thing.into();
+            Box::new(posed(thing, start, end))
+        }
+        None => thing,
+    })
+}
+
+/// `object` moving from `start` to `end`, or staying still if they're
+/// the same.
+fn posed<H: Hitable + ?Sized>(
+    object: Arc<H>,
+    start: Pose,
+    end: Pose,
+) -> Transformed<H> {
+    if start == end {
+        Transformed::new(object, start.transform())
+    } else {
+        Transformed::moving(object, start, end)
+    }
+}
+
+fn split_blocks<'a>(
+    source: &'a str,
+    path: &'a Path,
+) -> Result<Vec<Block<'a>>, ParseError> {
+    let mut blocks: Vec<Block> = vec![];
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let text = strip_comment(raw);
+        if text.is_empty() {
+            continue;
+        }
+        let mut words = text.split_whitespace();
+        let first = words.next().unwrap_or_default();
+        let rest: Vec<&str> = words.collect();
+
+        if raw.starts_with(char::is_whitespace) {
+            match blocks.last_mut() {
+                Some(block) => {
+                    block.fields.push(Field {
+                        key: first,
+                        values: rest,
+                        line,
+                    })
+                }
+                None => return Err(ParseError::new(
+                    path,
+                    Some(line),
+                    "indented field before any block",
+                )),
+            }
+        } else {
+            blocks.push(Block {
+                path,
+                kind: first,
+                args: rest,
+                line,
+                fields: vec![],
+                taken: HashSet::new(),
+            });
+        }
+    }
+    Ok(blocks)
+}
+
+/// `file`, relative to the directory holding the scene file at `path`.
+fn relative_to(path: &Path, file: &str) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(file)
+}
+
+/// Parse a scene description. `path` is used for error messages and to find meshes.
+pub fn parse(
+    source: &str,
+    path: &Path,
+    rng: &mut impl Rng,
+) -> Res<SceneDescription> {
+    let blocks = split_blocks(source, path)?;
+    let (texture_blocks, blocks): (Vec<_>, Vec<_>) =
+        blocks
+            .into_iter()
+            .partition(|b| b.kind == "texture");
+
+    // Textures, then materials, so they can be referred to from anywhere.
+    let mut textures: HashMap<&str, SharedTexture> =
+        HashMap::new();
+    for mut block in texture_blocks {
+        let (name, kind) = match block.args.as_slice() {
+            [name, kind] => (*name, *kind),
+            _ => {
+                return Err(block
+                    .error(
+                        block.line,
+                        "expected \"texture <name> <solid|checker|marble|turbulence|image>\"",
+                    )
+                    .into())
+            }
+        };
+        let color =
+            |block: &mut Block<'_>, key, default| {
+                block
+                    .vec3(key)
+                    .map(|c| c.unwrap_or(default))
+            };
+        let texture: SharedTexture = match kind {
+            "solid" => Arc::new(SolidColor {
+                color: color(
+                    &mut block,
+                    "color",
+                    Vec3::from(0.5),
+                )?,
+            }),
+            "checker" => Arc::new(Checker {
+                odd: Arc::new(SolidColor {
+                    color: color(
+                        &mut block,
+                        "odd",
+                        Vec3::from(0.1),
+                    )?,
+                }),
+                even: Arc::new(SolidColor {
+                    color: color(
+                        &mut block,
+                        "even",
+                        Vec3::from(0.9),
+                    )?,
+                }),
+                size: block
+                    .number("size")?
+                    .unwrap_or(1.),
+            }),
+            "marble" | "turbulence" => {
+                Arc::new(NoiseTexture {
+                    noise: Perlin::new(rng),
+                    kind: if kind == "marble" {
+                        NoiseKind::Marble
+                    } else {
+                        NoiseKind::Turbulence
+                    },
+                    scale: block
+                        .number("scale")?
+                        .unwrap_or(1.),
+                })
+            }
+            "image" => {
+                let file = block.word("file")?;
+                let (file, _) =
+                    block.required(file, "file")?;
+                Arc::new(ImageTexture {
+                    image: Image::read(&relative_to(
+                        path, file,
+                    ))?,
+                })
+            }
+            _ => {
+                return Err(block
+                    .error(
+                        block.line,
+                        format!(
+                            "unknown texture type {:?}",
+                            kind
+                        ),
+                    )
+                    .into())
+            }
+        };
+        if textures.insert(name, texture).is_some() {
+            return Err(block
+                .error(
+                    block.line,
+                    format!(
+                        "texture {:?} is defined twice",
+                        name
+                    ),
+                )
+                .into());
+        }
+        block.finish()?;
+    }
+
+    let mut materials: HashMap<&str, MaterialDef> =
+        HashMap::new();
+    let mut others = vec![];
+    for mut block in blocks {
+        if block.kind != "material" {
+            others.push(block);
+            continue;
+        }
+        let (name, kind) = match block.args.as_slice() {
+            [name, kind] => (*name, *kind),
+            _ => {
+                return Err(block
+                    .error(
+                        block.line,
+                        "expected \"material <name> <lambertian|metal|dielectric|light>\"",
+                    )
+                    .into())
+            }
+        };
+        let material = match kind {
+            "lambertian" => {
+                let albedo = block.vec3("albedo")?;
+                let texture = block.word("texture")?;
+                let albedo = match (albedo, texture) {
+                    (Some(_), Some((_, line))) => {
+                        return Err(block
+                            .error(
+                                line,
+                                "lambertian takes an albedo or a texture, not both",
+                            )
+                            .into())
+                    }
+                    (_, Some((name, line))) => textures
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| {
+                            block.error(
+                                line,
+                                format!(
+                                    "unknown texture {:?}",
+                                    name
+                                ),
+                            )
+                        })?,
+                    (albedo, None) => Arc::new(SolidColor {
+                        color: albedo.unwrap_or(Vec3::from(0.5)),
+                    }),
+                };
+                MaterialDef::Lambertian { albedo }
+            }
+            "metal" => MaterialDef::Metal {
+                albedo: block
+                    .vec3("albedo")?
+                    .unwrap_or(Vec3::from(0.5)),
+                fuzz: block
+                    .number("fuzz")?
+                    .unwrap_or(0.),
+            },
+            "dielectric" => MaterialDef::Dielectric {
+                index: block
+                    .number("index")?
+                    .unwrap_or(1.5),
+            },
+            "light" => {
+                let color = block
+                    .vec3("color")?
+                    .unwrap_or(Vec3::ONE);
+                let strength = block
+                    .number("strength")?
+                    .unwrap_or(1.);
+                MaterialDef::Light {
+                    emit: Color::from(color * strength),
+                }
+            }
+            _ => {
+                return Err(block
+                    .error(
+                        block.line,
+                        format!(
+                        "unknown material type {:?}",
+                        kind
+                    ),
+                    )
+                    .into())
+            }
+        };
+        if materials.insert(name, material).is_some() {
+            return Err(block
+                .error(
+                    block.line,
+                    format!(
+                        "material {:?} is defined twice",
+                        name
+                    ),
+                )
+                .into());
+        }
+        block.finish()?;
+    }
+
+    let mut description = SceneDescription {
+        scene: Scene::new(),
+        camera: CameraSettings::default(),
+        render: RenderSettings::default(),
+    };
+
+    let mut meshes: HashMap<
+        PathBuf,
+        Vec<Arc<TriangleMesh>>,
+    > = HashMap::new();
+    for mut block in others {
+        // The material, and its name for material IDs.
+        let material = |block: &mut Block| -> Result<
+            (Box<dyn Material + Send + Sync>, String),
+            ParseError,
+        > {
+            let word = block.word("material")?;
+            let (name, line) =
+                block.required(word, "material")?;
+            materials
+                .get(name)
+                .map(|m| (m.build(), name.to_owned()))
+                .ok_or_else(|| {
+                    block.error(
+                        line,
+                        format!(
+                            "unknown material {:?}",
+                            name
+                        ),
+                    )
+                })
+        };
+
+        if !block.args.is_empty() {
+            return Err(block
+                .error(
+                    block.line,
+                    format!(
+                        "{} doesn't take a name",
+                        block.kind
+                    ),
+                )
+                .into());
+        }
+
+        match block.kind {
+            "render" => {
+                let render = &mut description.render;
+                render.width =
+                    block.count("width", MAX_SIDE)?;
+                render.height =
+                    block.count("height", MAX_SIDE)?;
+                render.samples = block
+                    .count(
+                        "samples",
+                        u16::MAX as usize,
+                    )?
+                    .map(|s| s as u16);
+            }
+            "camera" => {
+                let defaults =
+                    CameraSettings::default();
+                let shutter = match block
+                    .numbers_at("shutter")?
+                {
+                    Some(([open, close], line)) => {
+                        check_shutter(open, close)
+                            .map_err(|m| {
+                                block.error(
+                                    line,
+                                    format!(
+                                        "camera shutter {}",
+                                        m
+                                    ),
+                                )
+                            })?;
+                        (open, close)
+                    }
+                    None => defaults.shutter,
+                };
+                description.camera = CameraSettings {
+                    look_from: block
+                        .vec3("look_from")?
+                        .unwrap_or(defaults.look_from),
+                    look_at: block
+                        .vec3("look_at")?
+                        .unwrap_or(defaults.look_at),
+                    up: block
+                        .vec3("up")?
+                        .unwrap_or(defaults.up),
+                    fov: block
+                        .number("fov")?
+                        .unwrap_or(defaults.fov),
+                    aperture: block
+                        .number("aperture")?
+                        .unwrap_or(defaults.aperture),
+                    focus_distance: block
+                        .number("focus_distance")?,
+                    shutter,
+                };
+            }
+            "sky" => {
+                let mut sky = Sky::default();
+                if let Some(bottom) =
+                    block.vec3("bottom")?
+                {
+                    sky.bottom = Color::from(bottom);
+                }
+                if let Some(top) = block.vec3("top")? {
+                    sky.top = Color::from(top);
+                }
+                description.scene.background =
+                    Background::Sky(sky);
+            }
+            "background" => {
+                let color = block.vec3("color")?;
+                let image = block.word("image")?;
+                description.scene.background =
+                    match (color, image) {
+                        (Some(color), None) => {
+                            Background::Solid(Color::from(
+                                color,
+                            ))
+                        }
+                        (None, Some((file, _))) => {
+                            Background::Environment(
+                                Arc::new(Image::read(
+                                    &relative_to(path, file),
+                                )?),
+                            )
+                        }
+                        _ => {
+                            return Err(block
+                                .error(
+                                    block.line,
+                                    "background needs a color or an image",
+                                )
+                                .into())
+                        }
+                    };
+            }
+            "sphere" => {
+                let center = block.vec3("center")?;
+                let center_end =
+                    block.vec3("center_end")?;
+                let radius = block.number("radius")?;
+                let center =
+                    block.required(center, "center")?;
+                let radius =
+                    block.required(radius, "radius")?;
+                let (material, name) =
+                    material(&mut block)?;
+                let sphere: Box<
+                    dyn Hitable + Send + Sync,
+                > = match center_end {
+                    Some(end) if end != center => {
+                        Box::new(MovingSphere {
+                            start: center,
+                            end,
+                            radius,
+                            material,
+                        })
+                    }
+                    _ => Box::new(Sphere {
+                        center,
+                        radius,
+                        material,
+                    }),
+                };
+                let thing = placed(&mut block, sphere)?;
+                description
+                    .scene
+                    .add_with_material(thing, &name);
+            }
+            "triangle" => {
+                let a = block.vec3("a")?;
+                let b = block.vec3("b")?;
+                let c = block.vec3("c")?;
+                let vertices = [
+                    block.required(a, "a")?,
+                    block.required(b, "b")?,
+                    block.required(c, "c")?,
+                ];
+                let (material, name) =
+                    material(&mut block)?;
+                let thing = placed(
+                    &mut block,
+                    Box::new(Triangle {
+                        vertices,
+                        normals: None,
+                        uvs: None,
+                        material,
+                    }),
+                )?;
+                description
+                    .scene
+                    .add_with_material(thing, &name);
+            }
+            "plane" => {
+                let point = block.vec3("point")?;
+                let normal = block.vec3("normal")?;
+                let (material, name) =
+                    material(&mut block)?;
+                let thing = placed(
+                    &mut block,
+                    Box::new(Plane {
+                        point: point
+                            .unwrap_or(Vec3::ZERO),
+                        normal: normal.unwrap_or(
+                            Vec3::new(0., 1., 0.),
+                        ),
+                        material,
+                    }),
+                )?;
+                description
+                    .scene
+                    .add_with_material(thing, &name);
+            }
+            "quad" => {
+                let corner = block.vec3("corner")?;
+                let u = block.vec3("u")?;
+                let v = block.vec3("v")?;
+                let (corner, u, v) = (
+                    block.required(corner, "corner")?,
+                    block.required(u, "u")?,
+                    block.required(v, "v")?,
+                );
+                let (material, name) =
+                    material(&mut block)?;
+                let thing = placed(
+                    &mut block,
+                    Box::new(Quad {
+                        corner,
+                        u,
+                        v,
+                        material,
+                    }),
+                )?;
+                description
+                    .scene
+                    .add_with_material(thing, &name);
+            }
+            "disk" => {
+                let center = block.vec3("center")?;
+                let normal = block.vec3("normal")?;
+                let radius = block.number("radius")?;
+                let center =
+                    block.required(center, "center")?;
+                let radius =
+                    block.required(radius, "radius")?;
+                let (material, name) =
+                    material(&mut block)?;
+                let thing = placed(
+                    &mut block,
+                    Box::new(Disk {
+                        center,
+                        normal: normal.unwrap_or(
+                            Vec3::new(0., 1., 0.),
+                        ),
+                        radius,
+                        material,
+                    }),
+                )?;
+                description
+                    .scene
+                    .add_with_material(thing, &name);
+            }
+            "box" => {
+                let min = block.vec3("min")?;
+                let max = block.vec3("max")?;
+                let (min, max) = (
+                    block.required(min, "min")?,
+                    block.required(max, "max")?,
+                );
+                let (material, name) =
+                    material(&mut block)?;
+                let thing = placed(
+                    &mut block,
+                    Box::new(BoxShape::new(
+                        min, max, material,
+                    )),
+                )?;
+                description
+                    .scene
+                    .add_with_material(thing, &name);
+            }
+            "mesh" => {
+                let file = block.word("file")?;
+                let (file, _) =
+                    block.required(file, "file")?;
+                let motion = block.motion()?;
+                let file = relative_to(path, file);
+                // Each file is only loaded once, however often it's placed.
+                if !meshes.contains_key(&file) {
+                    let loaded = load_obj(&file)?
+                        .into_iter()
+                        .map(Arc::new)
+                        .collect();
+                    meshes.insert(file.clone(), loaded);
+                }
+                for mesh in &meshes[&file] {
+                    let mesh = mesh.clone();
+                    description.scene.add(
+                        match motion {
+                            Some((start, end)) => {
+                                Box::new(posed(
+                                    mesh, start, end,
+                                ))
+                            }
+                            None => Box::new(mesh),
+                        },
+                    );
+                }
+            }
+            kind => {
+                return Err(block
+                    .error(
+                        block.line,
+                        format!(
+                            "unknown block {:?}",
+                            kind
+                        ),
+                    )
+                    .into())
+            }
+        }
+        block.finish()?;
+    }
+    Ok(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn error(source: &str) -> String {
+        parse(
+            source,
+            Path::new("t.scene"),
+            &mut StdRng::seed_from_u64(0),
+        )
+        .err()
+        .unwrap()
+        .to_string()
+    }
+
+    #[test]
+    fn example_scene() {
+        let path =
+            Path::new("scenes/three-spheres.scene");
+        let d = parse(
+            include_str!(
+                "../scenes/three-spheres.scene"
+            ),
+            path,
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        assert_eq!(d.scene.len(), 4);
+        assert_eq!(d.render.width, Some(600));
+        assert_eq!(d.render.samples, None);
+        assert_eq!(d.camera.fov, 30.);
+        assert_eq!(d.camera.up, Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn shapes() {
+        let d = parse(
+            "material m lambertian\n\
+             plane\n  material m\n\
+             quad\n  corner 0 0 0\n  u 1 0 0\n  v 0 1 0\n  material m\n\
+             disk\n  center 0 1 0\n  normal 0 0 1\n  radius 2\n  material m\n\
+             box\n  min 0 0 0\n  max 1 1 1\n  material m\n",
+            Path::new("t.scene"),
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        assert_eq!(d.scene.len(), 4);
+        assert_eq!(
+            error("quad\n  corner 0 0 0\n  u 1 0 0\n"),
+            "t.scene:1: quad needs a v"
+        );
+    }
+
+    #[test]
+    fn transforms_apply_scale_then_rotate_then_translate(
+    ) {
+        let mut d = parse(
+            "material m lambertian\n\
+             box\n  min -1 -1 -1\n  max 1 1 1\n  material m\n  scale 2 1 1\n  \
+             rotate 0 0 90\n  translate 0 0 5\n",
+            Path::new("t.scene"),
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        d.scene.build_bvh();
+        let forward = Vec3::new(0., 0., 1.);
+        let hit = d
+            .scene
+            .hit(
+                &Ray::new(
+                    Vec3::new(0., 1.5, 0.),
+                    forward,
+                ),
+                &(0.0..F::INFINITY),
+            )
+            .unwrap();
+        assert!((hit.t - 4.).abs() < 1e-4);
+        assert!(d
+            .scene
+            .hit(
+                &Ray::new(
+                    Vec3::new(1.5, 0., 0.),
+                    forward
+                ),
+                &(0.0..F::INFINITY),
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn meshes_can_be_placed_more_than_once() {
+        let dir = std::env::temp_dir().join(format!(
+            "chambray-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("tri.obj"),
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n",
+        )
+        .unwrap();
+        let d = parse(
+            "mesh\n  file tri.obj\n\
+             mesh\n  file tri.obj\n  translate 5 0 0\n",
+            &dir.join("t.scene"),
+            &mut StdRng::seed_from_u64(0),
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        let bounds =
+            d.unwrap().scene.bounding_box().unwrap();
+        assert_eq!(bounds.max, Vec3::new(6., 1., 0.));
+    }
+
+    #[test]
+    fn things_move_while_the_shutter_is_open() {
+        let mut d = parse(
+            "camera\n  shutter 0 0.5\n\
+             material m lambertian\n\
+             sphere\n  center 0 0 0\n  center_end 0 4 0\n  radius 1\n  material m\n\
+             box\n  min -1 -1 -1\n  max 1 1 1\n  material m\n  \
+             translate 10 0 0\n  translate_end 10 0 6\n",
+            Path::new("t.scene"),
+            &mut StdRng::seed_from_u64(0),
+        )
+        .unwrap();
+        assert_eq!(d.camera.shutter, (0., 0.5));
+        let late = CameraSettings {
+            shutter: (0.5, 2.),
+            ..d.camera
+        };
+        assert!(matches!(
+            late.camera(1.),
+            Err(Error::InvalidParameter(_))
+        ));
+        d.scene.build_bvh();
+        let bounds = d.scene.bounding_box().unwrap();
+        assert_eq!(bounds.min, Vec3::from(-1.));
+        assert!(
+            (bounds.max - Vec3::new(11., 5., 7.))
+                .length()
+                < 1e-4
+        );
+        let up = |time| Ray {
+            origin: Vec3::new(0., -5., 0.),
+            direction: Vec3::new(0., 1., 0.),
+            time,
+        };
+        let all = 0.0..F::INFINITY;
+        let early = d.scene.hit(&up(0.), &all).unwrap();
+        let late = d.scene.hit(&up(1.), &all).unwrap();
+        assert!((early.t - 4.).abs() < 1e-4);
+        assert!((late.t - 8.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(
+            error(
+                "sphere\n  center 0 0 0\n  radus 1\n"
+            ),
+            "t.scene:1: sphere needs a radius"
+        );
+        assert_eq!(
+            error("sphere\n  center 0 0 0\n  radius 1\n  material x\n  radus 1\n"),
+            "t.scene:4: unknown material \"x\""
+        );
+        assert_eq!(
+            error("material m metal\n  fuzz 0\n  colour 1 1 1\n"),
+            "t.scene:3: unknown field \"colour\" in material"
+        );
+        assert_eq!(
+            error("\ncamera\n  fov wide\n"),
+            "t.scene:3: camera fov should be a number, not \"wide\""
+        );
+        assert_eq!(
+            error("camera\n  fov 40\n  shutter 0.5 0.2\n"),
+            "t.scene:3: camera shutter should open and close at times from 0 to 1, in that order, not 0.5 and 0.2"
+        );
+        assert_eq!(
+            error("camera\n  look_at 1 2\n"),
+            "t.scene:2: camera look_at should be three numbers"
+        );
+        assert_eq!(
+            error("render\n  width 10\n  width 20\n"),
+            "t.scene:3: render width is given more than once"
+        );
+        assert_eq!(
+            error("  fov 2\n"),
+            "t.scene:1: indented field before any block"
+        );
+        assert_eq!(
+            error("render\n  samples 1.5\n"),
+            "t.scene:2: render samples should be a whole number from 1 to 65535"
+        );
+        assert_eq!(
+            error("render\n  samples 100000\n"),
+            "t.scene:2: render samples should be a whole number from 1 to 65535"
+        );
+        assert_eq!(
+            error("render\n  height 10\n  width 1e12\n"),
+            "t.scene:3: render width should be a whole number from 1 to 65536"
+        );
+        assert_eq!(
+            error("material m lambertian\n  texture wood\n"),
+            "t.scene:2: unknown texture \"wood\""
+        );
+        assert_eq!(
+            error("texture t checker\n  size 1\ntexture t solid\n"),
+            "t.scene:3: texture \"t\" is defined twice"
+        );
+        assert_eq!(
+            error("background\n"),
+            "t.scene:1: background needs a color or an image"
+        );
+        assert_eq!(
+            error("teapot\n"),
+            "t.scene:1: unknown block \"teapot\""
+        );
+    }
+}
@@ -1,17 +1,147 @@
-use crate::hitable::Sphere;
-use crate::material::{Dialectric, Lambertian, Metal};
-use crate::scene::Scene;
-use crate::vec3::{randf, Vec3};
+use crate::camera::CameraSettings;
+use crate::color::Color;
+use crate::hitable::{MovingSphere, Sphere};
+use crate::material::{
+    Dialectric, DiffuseLight, Lambertian, Material,
+    Metal,
+};
+use crate::scene::{Background, Scene};
+use crate::shapes::{BoxShape, Plane, Quad};
+use crate::texture::{
+    Checker, NoiseKind, NoiseTexture, Perlin,
+    SharedTexture, SolidColor,
+};
+use crate::transformed::{Pose, Transformed};
+use crate::vec3::Vec3;
 use crate::vec3::{F, PI};
+use rand::Rng;
+use std::sync::Arc;
 
-pub(crate) fn random_scene() -> Scene {
+/// What scenes' random choices come from. Unlike `rand`'s `StdRng`,
+/// its numbers for a seed are promised never to change, so seeded
+/// renders stay the same.
+pub type SceneRng = rand_chacha::ChaCha12Rng;
+
+/// A built-in scene, with a camera that shows it off.
+pub struct Preset {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Makes the scene, with any random choices made by the given
+    /// generator.
+    pub build: fn(&mut SceneRng) -> Scene,
+    pub camera: CameraSettings,
+}
+
+/// Every built-in scene. The first is the default.
+pub fn presets() -> Vec<Preset> {
+    vec![
+        Preset {
+            name: "random",
+            description: "Lots of small random spheres around three big ones",
+            build: random_scene,
+            camera: CameraSettings::default(),
+        },
+        Preset {
+            name: "standard",
+            description: "Diffuse, metal and hollow glass spheres on a plain",
+            build: standard_scene,
+            camera: CameraSettings {
+                look_from: Vec3::new(-2., 2., 1.),
+                look_at: Vec3::new(0., 0., -1.),
+                up: Vec3::new(0., 1., 0.),
+                fov: 40.,
+                aperture: 0.,
+                focus_distance: None,
+                shutter: (0., 0.),
+            },
+        },
+        Preset {
+            name: "camera-test",
+            description: "Two touching spheres, to check the field of view",
+            build: camera_test_scene,
+            camera: CameraSettings {
+                look_from: Vec3::new(0., 0., 0.),
+                look_at: Vec3::new(0., 0., -1.),
+                up: Vec3::new(0., 1., 0.),
+                fov: 90.,
+                aperture: 0.,
+                focus_distance: None,
+                shutter: (0., 0.),
+            },
+        },
+        Preset {
+            name: "cornell-box",
+            description: "A closed room lit only by a ceiling light",
+            build: cornell_box,
+            camera: CameraSettings {
+                look_from: Vec3::new(278., 278., -800.),
+                look_at: Vec3::new(278., 278., 0.),
+                up: Vec3::new(0., 1., 0.),
+                fov: 40.,
+                aperture: 0.,
+                focus_distance: None,
+                shutter: (0., 0.),
+            },
+        },
+        Preset {
+            name: "small-light",
+            description: "Spheres in the dark, lit by one small glowing sphere",
+            build: small_light,
+            camera: CameraSettings {
+                look_from: Vec3::new(0., 2., 6.),
+                look_at: Vec3::new(0., 0.5, 0.),
+                up: Vec3::new(0., 1., 0.),
+                fov: 35.,
+                aperture: 0.,
+                focus_distance: None,
+                shutter: (0., 0.),
+            },
+        },
+        Preset {
+            name: "textures",
+            description: "A marble sphere on a checkered floor",
+            build: textures,
+            camera: CameraSettings {
+                look_from: Vec3::new(13., 2., 3.),
+                look_at: Vec3::new(0., 1., 0.),
+                up: Vec3::new(0., 1., 0.),
+                fov: 20.,
+                aperture: 0.,
+                focus_distance: None,
+                shutter: (0., 0.),
+            },
+        },
+        Preset {
+            name: "motion-blur",
+            description: "Falling spheres and a spinning box, blurred by a slow shutter",
+            build: motion_blur,
+            camera: CameraSettings {
+                look_from: Vec3::new(0., 2., 8.),
+                look_at: Vec3::new(0., 1., 0.),
+                up: Vec3::new(0., 1., 0.),
+                fov: 30.,
+                aperture: 0.,
+                focus_distance: None,
+                shutter: (0., 1.),
+            },
+        },
+    ]
+}
+
+pub fn preset(name: &str) -> Option<Preset> {
+    presets().into_iter().find(|p| p.name == name)
+}
+
+pub(crate) fn random_scene(
+    rng: &mut SceneRng,
+) -> Scene {
     let mut scene = Scene::new();
-    scene.add(Box::new(Sphere {
-        center: Vec3::new(0., -1000., 0.),
-        radius: 1000.,
-        material: Box::new(Lambertian {
-            albedo: Vec3::new(0.5, 0.5, 0.5),
-        }),
+    scene.add(Box::new(Plane {
+        point: Vec3::ZERO,
+        normal: Vec3::new(0., 1., 0.),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.5, 0.5, 0.5,
+        ))),
     }));
 
     scene.add(Box::new(Sphere {
@@ -24,9 +154,9 @@ pub(crate) fn random_scene() -> Scene {
     scene.add(Box::new(Sphere {
         center: Vec3::new(-4., 1., 0.),
         radius: 1.,
-        material: Box::new(Lambertian {
-            albedo: Vec3::new(0.4, 0.2, 0.1),
-        }),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.4, 0.2, 0.1,
+        ))),
     }));
     scene.add(Box::new(Sphere {
         center: Vec3::new(4., 1., 0.),
@@ -39,11 +169,11 @@ pub(crate) fn random_scene() -> Scene {
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat: F = randf();
+            let choose_mat: F = rng.gen();
             let center = Vec3::new(
-                (a as F) + 0.9 * randf(),
+                (a as F) + 0.9 * rng.gen::<F>(),
                 0.2,
-                (b as F) + 0.9 * randf(),
+                (b as F) + 0.9 * rng.gen::<F>(),
             );
 
             if (center - Vec3::new(4., 0.2, 0.))
@@ -54,9 +184,11 @@ pub(crate) fn random_scene() -> Scene {
                     center,
                     radius: 0.2,
                     material: if choose_mat < 0.8 {
-                        Box::new(rand::random::<Lambertian>())
+                        Box::new(
+                            rng.gen::<Lambertian>(),
+                        )
                     } else if choose_mat < 0.95 {
-                        Box::new(rand::random::<Metal>())
+                        Box::new(rng.gen::<Metal>())
                     } else {
                         // glass
                         Box::new(Dialectric {
@@ -70,47 +202,48 @@ pub(crate) fn random_scene() -> Scene {
 
     scene
 }
-fn camera_test_scene() -> Scene {
+
+fn camera_test_scene(_rng: &mut SceneRng) -> Scene {
     let r = (PI / 4.).cos();
     let mut scene = Scene::new();
 
     scene.add(Box::new(Sphere {
         center: Vec3::new(-r, 0., -1.),
         radius: r,
-        material: Box::new(Lambertian {
-            albedo: Vec3::new(0.1, 0.1, 0.3),
-        }),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.1, 0.1, 0.3,
+        ))),
     }));
 
     scene.add(Box::new(Sphere {
         center: Vec3::new(r, 0., -1.),
         radius: r,
-        material: Box::new(Lambertian {
-            albedo: Vec3::new(0.3, 0.1, 0.1),
-        }),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.3, 0.1, 0.1,
+        ))),
     }));
 
     scene
 }
 
-fn standard_scene() -> Scene {
+fn standard_scene(_rng: &mut SceneRng) -> Scene {
     // Create scene
     let mut scene = Scene::new();
 
     scene.add(Box::new(Sphere {
         center: Vec3::new(0., 0., -1.),
         radius: 0.5,
-        material: Box::new(Lambertian {
-            albedo: Vec3::new(0.1, 0.2, 0.5),
-        }),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.1, 0.2, 0.5,
+        ))),
     }));
 
     scene.add(Box::new(Sphere {
         center: Vec3::new(0., -100.5, -1.),
         radius: 100.,
-        material: Box::new(Lambertian {
-            albedo: Vec3::new(0.8, 0.8, 0.),
-        }),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.8, 0.8, 0.,
+        ))),
     }));
 
     scene.add(Box::new(Sphere {
@@ -139,3 +272,272 @@ fn standard_scene() -> Scene {
 
     scene
 }
+
+fn quad(
+    corner: Vec3,
+    u: Vec3,
+    v: Vec3,
+    material: Box<dyn Material + Send + Sync>,
+) -> Quad {
+    Quad {
+        corner,
+        u,
+        v,
+        material,
+    }
+}
+
+fn cornell_box(_rng: &mut SceneRng) -> Scene {
+    let mut scene = Scene::new();
+    scene.background =
+        Background::Solid(Color::black());
+
+    let red = || {
+        Box::new(Lambertian::new(Vec3::new(
+            0.65, 0.05, 0.05,
+        )))
+    };
+    let white = || {
+        Box::new(Lambertian::new(Vec3::new(
+            0.73, 0.73, 0.73,
+        )))
+    };
+    let green = || {
+        Box::new(Lambertian::new(Vec3::new(
+            0.12, 0.45, 0.15,
+        )))
+    };
+    let size = 555.;
+    let up = Vec3::new(0., size, 0.);
+    let across = Vec3::new(size, 0., 0.);
+    let deep = Vec3::new(0., 0., size);
+
+    // Left and right walls.
+    scene.add(Box::new(quad(across, up, deep, red())));
+    scene.add(Box::new(quad(
+        Vec3::ZERO,
+        up,
+        deep,
+        green(),
+    )));
+    // Floor, ceiling and back wall.
+    scene.add(Box::new(quad(
+        Vec3::ZERO,
+        across,
+        deep,
+        white(),
+    )));
+    scene.add(Box::new(quad(
+        up,
+        across,
+        deep,
+        white(),
+    )));
+    scene.add(Box::new(quad(
+        deep,
+        across,
+        up,
+        white(),
+    )));
+
+    // Just below the ceiling, so it doesn't overlap.
+    scene.add(Box::new(quad(
+        Vec3::new(213., size - 1., 227.),
+        Vec3::new(130., 0., 0.),
+        Vec3::new(0., 0., 105.),
+        Box::new(DiffuseLight {
+            emit: Color::new(15., 15., 15.),
+        }),
+    )));
+
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(190., 90., 190.),
+        radius: 90.,
+        material: Box::new(Dialectric {
+            reflective_index: 1.5,
+        }),
+    }));
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(370., 120., 370.),
+        radius: 120.,
+        material: white(),
+    }));
+
+    scene
+}
+
+fn small_light(_rng: &mut SceneRng) -> Scene {
+    let mut scene = Scene::new();
+    scene.background =
+        Background::Solid(Color::black());
+
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(0., -1000., 0.),
+        radius: 1000.,
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.5, 0.5, 0.5,
+        ))),
+    }));
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(-1.2, 0.5, 0.),
+        radius: 0.5,
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.7, 0.2, 0.1,
+        ))),
+    }));
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(0., 0.5, 0.),
+        radius: 0.5,
+        material: Box::new(Metal::new(
+            Vec3::new(0.8, 0.8, 0.8),
+            0.2,
+        )),
+    }));
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(1.2, 0.5, 0.),
+        radius: 0.5,
+        material: Box::new(Dialectric {
+            reflective_index: 1.5,
+        }),
+    }));
+
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(1., 3., 1.),
+        radius: 0.1,
+        material: Box::new(DiffuseLight {
+            emit: Color::new(400., 380., 340.),
+        }),
+    }));
+
+    scene
+}
+
+fn textures(rng: &mut SceneRng) -> Scene {
+    let mut scene = Scene::new();
+    let solid = |r, g, b| -> SharedTexture {
+        Arc::new(SolidColor {
+            color: Vec3::new(r, g, b),
+        })
+    };
+
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(0., -1000., 0.),
+        radius: 1000.,
+        material: Box::new(Lambertian::textured(
+            Arc::new(Checker {
+                odd: solid(0.2, 0.3, 0.1),
+                even: solid(0.9, 0.9, 0.9),
+                size: 1.,
+            }),
+        )),
+    }));
+    scene.add(Box::new(Sphere {
+        center: Vec3::new(0., 1., 0.),
+        radius: 1.,
+        material: Box::new(Lambertian::textured(
+            Arc::new(NoiseTexture {
+                noise: Perlin::new(rng),
+                kind: NoiseKind::Marble,
+                scale: 4.,
+            }),
+        )),
+    }));
+
+    scene
+}
+
+fn motion_blur(_rng: &mut SceneRng) -> Scene {
+    let mut scene = Scene::new();
+
+    scene.add(Box::new(Plane {
+        point: Vec3::ZERO,
+        normal: Vec3::new(0., 1., 0.),
+        material: Box::new(Lambertian::new(Vec3::new(
+            0.5, 0.5, 0.5,
+        ))),
+    }));
+    for (x, color) in [
+        (-2., Vec3::new(0.7, 0.2, 0.1)),
+        (2., Vec3::new(0.1, 0.2, 0.7)),
+    ] {
+        scene.add(Box::new(MovingSphere {
+            start: Vec3::new(x, 1.5, 0.),
+            end: Vec3::new(x, 0.5, 0.),
+            radius: 0.5,
+            material: Box::new(Lambertian::new(color)),
+        }));
+    }
+    let cube = Arc::new(BoxShape::new(
+        Vec3::from(-0.5),
+        Vec3::from(0.5),
+        Box::new(Metal::new(
+            Vec3::new(0.8, 0.8, 0.8),
+            0.1,
+        )),
+    ));
+    let pose = |turn| Pose {
+        rotate: Vec3::new(0., turn, 20.),
+        translate: Vec3::new(0., 1., 0.),
+        ..Pose::default()
+    };
+    scene.add(Box::new(Transformed::moving(
+        cube,
+        pose(0.),
+        pose(45.),
+    )));
+
+    scene
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hitable::Hitable;
+    use crate::ray::Ray;
+    use rand::SeedableRng;
+
+    #[test]
+    fn preset_names_are_unique() {
+        let presets = presets();
+        for (i, p) in presets.iter().enumerate() {
+            assert!(presets[..i]
+                .iter()
+                .all(|other| other.name != p.name));
+            assert_eq!(
+                preset(p.name).unwrap().name,
+                p.name
+            );
+        }
+        assert!(preset("nope").is_none());
+    }
+
+    #[test]
+    fn random_scene_follows_the_seed() {
+        // Where rays straight down from a grid land.
+        let heights = |seed| -> Vec<Option<F>> {
+            let scene = random_scene(
+                &mut SceneRng::seed_from_u64(seed),
+            );
+            (0..400)
+                .map(|i| {
+                    let ray = Ray::new(
+                        Vec3::new(
+                            (i % 20) as F - 10.,
+                            10.,
+                            (i / 20) as F - 10.,
+                        ),
+                        Vec3::new(0., -1., 0.),
+                    );
+                    scene
+                        .hit(
+                            &ray,
+                            &(0.001..F::INFINITY),
+                        )
+                        .map(|hit| hit.t)
+                })
+                .collect()
+        };
+        assert_eq!(heights(1), heights(1));
+        assert_ne!(heights(1), heights(2));
+    }
+}
@@ -0,0 +1,611 @@
+//! Flat shapes, and boxes made of them.
+
+use crate::aabb::Aabb;
+use crate::hitable::{Hit, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Vec3, F, PI};
+use std::ops::Range;
+
+/// An infinite plane through `point`, facing along `normal`.
+///
+/// Texture coordinates are distances along the plane, so textures
+/// repeat every unit.
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hitable for Plane {
+    fn hit(
+        &self,
+        ray: &Ray,
+        within_t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        let normal = self.normal.unit();
+        let t = hit_plane(
+            ray,
+            within_t,
+            &self.point,
+            &normal,
+        )?;
+        let p = ray.point_at(t);
+        let (a, b) = normal.orthonormal_basis();
+        let d = p - self.point;
+        Some(Hit {
+            t,
+            p,
+            normal,
+            u: d.dot(&a),
+            v: d.dot(&b),
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+/// A parallelogram from `corner` along the `u` and `v` edges.
+///
+/// It faces the side from which `u` turns anticlockwise into `v`.
+/// Texture coordinates go from 0 to 1 along each edge.
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hitable for Quad {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        hit_parallelogram(
+            ray,
+            t,
+            &self.corner,
+            &self.u,
+            &self.v,
+            self.material.as_ref(),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(parallelogram_bounds(
+            &self.corner,
+            &self.u,
+            &self.v,
+        ))
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Towards a point picked uniformly over the area.
+    fn random_direction_toward(
+        &self,
+        origin: &Vec3,
+        (s, t): (F, F),
+    ) -> Option<Vec3> {
+        let point =
+            self.corner + self.u * s + self.v * t;
+        direction_to(origin, &point)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> F {
+        solid_angle_pdf(
+            self.hit(
+                &Ray::new(*origin, *direction),
+                &(0.0..F::INFINITY),
+            ),
+            direction,
+            self.u.cross(&self.v).length(),
+        )
+    }
+}
+
+/// A flat disk, facing along `normal`.
+///
+/// Texture coordinates are the angle round, from 0 to 1, and the
+/// distance from the centre, from 0 to 1 at the rim.
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: F,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hitable for Disk {
+    fn hit(
+        &self,
+        ray: &Ray,
+        within_t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        let normal = self.normal.unit();
+        let t = hit_plane(
+            ray,
+            within_t,
+            &self.center,
+            &normal,
+        )?;
+        let p = ray.point_at(t);
+        let d = p - self.center;
+        let distance_squared = d.squared_length();
+        if distance_squared > self.radius * self.radius
+        {
+            return None;
+        }
+        let (a, b) = normal.orthonormal_basis();
+        Some(Hit {
+            t,
+            p,
+            normal,
+            u: (d.dot(&b).atan2(d.dot(&a)) + PI)
+                / (2. * PI),
+            v: distance_squared.sqrt() / self.radius,
+            material: self.material.as_ref(),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // How far the rim reaches along each axis.
+        let n = self.normal.unit();
+        let reach = |n: F| {
+            self.radius * (1. - n * n).max(0.).sqrt()
+        };
+        let extent = Vec3::new(
+            reach(n.x),
+            reach(n.y),
+            reach(n.z),
+        );
+        Some(Aabb::new(
+            self.center - extent,
+            self.center + extent,
+        ))
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    /// Towards a point picked uniformly over the area.
+    fn random_direction_toward(
+        &self,
+        origin: &Vec3,
+        (s, t): (F, F),
+    ) -> Option<Vec3> {
+        let (a, b) =
+            self.normal.unit().orthonormal_basis();
+        let r = self.radius * s.sqrt();
+        let phi = 2. * PI * t;
+        let point = self.center
+            + a * (r * phi.cos())
+            + b * (r * phi.sin());
+        direction_to(origin, &point)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> F {
+        solid_angle_pdf(
+            self.hit(
+                &Ray::new(*origin, *direction),
+                &(0.0..F::INFINITY),
+            ),
+            direction,
+            PI * self.radius * self.radius,
+        )
+    }
+}
+
+/// An axis-aligned box, made of six quads facing out.
+///
+/// Glowing boxes light whatever they're hit by, but aren't sampled as
+/// lights.
+pub struct BoxShape {
+    /// Corner, `u` and `v` of each side.
+    sides: [[Vec3; 3]; 6],
+    bounds: Aabb,
+    material: Box<dyn Material + Send + Sync>,
+}
+
+impl BoxShape {
+    /// The box between two opposite corners.
+    pub fn new(
+        a: Vec3,
+        b: Vec3,
+        material: Box<dyn Material + Send + Sync>,
+    ) -> Self {
+        let bounds = Aabb::new(a, b);
+        let (min, max) = (bounds.min, bounds.max);
+        let size = max - min;
+        let dx = Vec3::new(size.x, 0., 0.);
+        let dy = Vec3::new(0., size.y, 0.);
+        let dz = Vec3::new(0., 0., size.z);
+        BoxShape {
+            sides: [
+                [
+                    Vec3::new(min.x, min.y, max.z),
+                    dx,
+                    dy,
+                ],
+                [min, dy, dx],
+                [
+                    Vec3::new(max.x, min.y, min.z),
+                    dy,
+                    dz,
+                ],
+                [min, dz, dy],
+                [
+                    Vec3::new(min.x, max.y, min.z),
+                    dz,
+                    dx,
+                ],
+                [min, dx, dz],
+            ],
+            bounds,
+            material,
+        }
+    }
+}
+
+impl Hitable for BoxShape {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        let mut closest: Option<Hit> = None;
+        for [corner, u, v] in &self.sides {
+            let end =
+                closest.as_ref().map_or(t.end, |h| h.t);
+            if let Some(hit) = hit_parallelogram(
+                ray,
+                &(t.start..end),
+                corner,
+                u,
+                v,
+                self.material.as_ref(),
+            ) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+/// Where a ray meets the plane through `point` with unit `normal`.
+fn hit_plane(
+    ray: &Ray,
+    within_t: &Range<F>,
+    point: &Vec3,
+    normal: &Vec3,
+) -> Option<F> {
+    let denominator = normal.dot(&ray.direction);
+    if denominator.abs() < F::EPSILON {
+        // Parallel to the plane.
+        return None;
+    }
+    let t = normal.dot(&(*point - ray.origin))
+        / denominator;
+    if within_t.contains(&t) {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+fn hit_parallelogram<'a>(
+    ray: &Ray,
+    within_t: &Range<F>,
+    corner: &Vec3,
+    u: &Vec3,
+    v: &Vec3,
+    material: &'a (dyn Material + Send + Sync),
+) -> Option<Hit<'a>> {
+    let n = u.cross(v);
+    let normal = n.unit();
+    let t = hit_plane(ray, within_t, corner, &normal)?;
+    let p = ray.point_at(t);
+    // How far along each edge `p` is, from 0 to 1 inside.
+    let d = p - *corner;
+    let w = n / n.squared_length();
+    let a = w.dot(&d.cross(v));
+    let b = w.dot(&u.cross(&d));
+    if !(0. ..=1.).contains(&a)
+        || !(0. ..=1.).contains(&b)
+    {
+        return None;
+    }
+    Some(Hit {
+        t,
+        p,
+        normal,
+        u: a,
+        v: b,
+        material,
+    })
+}
+
+fn parallelogram_bounds(
+    corner: &Vec3,
+    u: &Vec3,
+    v: &Vec3,
+) -> Aabb {
+    Aabb::new(*corner, *corner + *u)
+        .grow(*corner + *v)
+        .grow(*corner + *u + *v)
+}
+
+/// The unit direction from `origin` to `point`, if they're apart.
+fn direction_to(
+    origin: &Vec3,
+    point: &Vec3,
+) -> Option<Vec3> {
+    let to_point = *point - *origin;
+    if to_point.squared_length() == 0. {
+        return None;
+    }
+    Some(to_point.unit())
+}
+
+/// Probability density per solid angle of picking unit `direction`,
+/// given what a ray that way hits, when points are picked uniformly
+/// over `area`.
+fn solid_angle_pdf(
+    hit: Option<Hit>,
+    direction: &Vec3,
+    area: F,
+) -> F {
+    match hit {
+        Some(hit) => {
+            let cosine =
+                direction.dot(&hit.normal).abs();
+            if cosine == 0. {
+                return 0.;
+            }
+            hit.t * hit.t / (cosine * area)
+        }
+        None => 0.,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::{DiffuseLight, Lambertian};
+
+    fn grey() -> Box<dyn Material + Send + Sync> {
+        Box::new(Lambertian::new(Vec3::from(0.5)))
+    }
+
+    fn light() -> Box<dyn Material + Send + Sync> {
+        Box::new(DiffuseLight {
+            emit: Color::white(),
+        })
+    }
+
+    const ALL: Range<F> = 0.0..F::INFINITY;
+
+    #[test]
+    fn quad_edges_and_normal() {
+        let quad = Quad {
+            corner: Vec3::new(-1., -1., 0.),
+            u: Vec3::new(2., 0., 0.),
+            v: Vec3::new(0., 4., 0.),
+            material: grey(),
+        };
+        let hit = quad
+            .hit(
+                &Ray::new(
+                    Vec3::new(0.5, 2., 5.),
+                    Vec3::new(0., 0., -1.),
+                ),
+                &ALL,
+            )
+            .unwrap();
+        assert_eq!(hit.t, 5.);
+        assert_eq!(hit.normal, Vec3::new(0., 0., 1.));
+        assert_eq!((hit.u, hit.v), (0.75, 0.75));
+        assert!(quad
+            .hit(
+                &Ray::new(
+                    Vec3::new(1.5, 0., 5.),
+                    Vec3::new(0., 0., -1.),
+                ),
+                &ALL,
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn disk_and_plane() {
+        let disk = Disk {
+            center: Vec3::new(0., 1., 0.),
+            normal: Vec3::new(0., 2., 0.),
+            radius: 2.,
+            material: grey(),
+        };
+        let down = Vec3::new(0., -1., 0.);
+        let hit = disk
+            .hit(
+                &Ray::new(Vec3::new(1., 3., 0.), down),
+                &ALL,
+            )
+            .unwrap();
+        assert_eq!(hit.t, 2.);
+        assert_eq!(hit.normal, Vec3::new(0., 1., 0.));
+        assert_eq!(hit.v, 0.5);
+        assert!(disk
+            .hit(
+                &Ray::new(Vec3::new(2.5, 3., 0.), down),
+                &ALL
+            )
+            .is_none());
+        let bounds = disk.bounding_box().unwrap();
+        assert_eq!(bounds.min, Vec3::new(-2., 1., -2.));
+        assert_eq!(bounds.max, Vec3::new(2., 1., 2.));
+
+        let plane = Plane {
+            point: Vec3::ZERO,
+            normal: Vec3::new(0., 1., 0.),
+            material: grey(),
+        };
+        let far = Vec3::new(1000., 3., -1000.);
+        assert_eq!(
+            plane
+                .hit(&Ray::new(far, down), &ALL)
+                .unwrap()
+                .t,
+            3.
+        );
+        assert!(plane
+            .hit(&Ray::new(far, -down), &ALL)
+            .is_none());
+    }
+
+    #[test]
+    fn box_sides_face_out() {
+        let shape = BoxShape::new(
+            Vec3::new(1., 2., 3.),
+            Vec3::new(-1., -2., -3.),
+            grey(),
+        );
+        for axis in 0..3 {
+            for sign in [-1., 1.] {
+                let mut outward = [0.; 3];
+                outward[axis] = sign;
+                let outward = Vec3::new(
+                    outward[0], outward[1], outward[2],
+                );
+                let hit = shape
+                    .hit(
+                        &Ray::new(
+                            outward * 10.,
+                            -outward,
+                        ),
+                        &ALL,
+                    )
+                    .unwrap();
+                assert_eq!(hit.normal, outward);
+                assert_eq!(
+                    hit.t,
+                    10. - [1., 2., 3.][axis]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn light_directions_hit_the_shape() {
+        let lights: [Box<dyn Hitable>; 2] = [
+            Box::new(Quad {
+                corner: Vec3::new(-1., 4., -2.),
+                u: Vec3::new(2., 0., 0.),
+                v: Vec3::new(0., 1., 4.),
+                material: light(),
+            }),
+            Box::new(Disk {
+                center: Vec3::new(0., 4., 0.),
+                normal: Vec3::new(0., -1., 1.),
+                radius: 1.5,
+                material: light(),
+            }),
+        ];
+        let origin = Vec3::ZERO;
+        for light in &lights {
+            assert!(light.is_light());
+            // The mean of 1 / pdf over sampled directions is the
+            // solid angle the shape covers.
+            let n = 64;
+            let mut solid_angle = 0.;
+            for i in 0..n {
+                for j in 0..n {
+                    let sample = (
+                        (i as F + 0.5) / n as F,
+                        (j as F + 0.5) / n as F,
+                    );
+                    let direction = light
+                        .random_direction_toward(
+                            &origin, sample,
+                        )
+                        .unwrap();
+                    assert!(light
+                        .hit(
+                            &Ray::new(
+                                origin, direction
+                            ),
+                            &ALL
+                        )
+                        .is_some());
+                    let pdf = light.pdf_toward(
+                        &origin, &direction,
+                    );
+                    assert!(pdf > 0.);
+                    solid_angle += 1. / pdf;
+                }
+            }
+            // Count it by brute force, over evenly spread directions.
+            let m = 512;
+            let mut hits = 0;
+            for i in 0..m {
+                for j in 0..m {
+                    let z = 1.
+                        - 2. * (i as F + 0.5) / m as F;
+                    let phi = 2. * PI * (j as F + 0.5)
+                        / m as F;
+                    let r = (1. - z * z).sqrt();
+                    let direction = Vec3::new(
+                        r * phi.cos(),
+                        z,
+                        r * phi.sin(),
+                    );
+                    if light
+                        .hit(
+                            &Ray::new(
+                                origin, direction,
+                            ),
+                            &ALL,
+                        )
+                        .is_some()
+                    {
+                        hits += 1;
+                    }
+                }
+            }
+            let counted =
+                4. * PI * hits as F / (m * m) as F;
+            let estimated = solid_angle / (n * n) as F;
+            assert!(
+                (estimated / counted - 1.).abs() < 0.02,
+                "{} vs {}",
+                estimated,
+                counted
+            );
+            assert_eq!(
+                light.pdf_toward(
+                    &origin,
+                    &Vec3::new(0., -1., 0.)
+                ),
+                0.
+            );
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::image::Image;
+use crate::vec3::{Vec3, F};
+
+/// A colour that varies over a surface.
+pub trait Texture {
+    /// Colour at surface coordinates `u`, `v` (each 0-1), which are at point `p`.
+    fn value(&self, u: F, v: F, p: &Vec3) -> Vec3;
+}
+
+pub type SharedTexture = Arc<dyn Texture + Send + Sync>;
+
+/// The same colour everywhere.
+pub struct SolidColor {
+    pub color: Vec3,
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: F, _v: F, _p: &Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+/// Alternating cubes of two textures, filling space.
+pub struct Checker {
+    pub odd: SharedTexture,
+    pub even: SharedTexture,
+    /// Width of each cube.
+    pub size: F,
+}
+
+impl Texture for Checker {
+    fn value(&self, u: F, v: F, p: &Vec3) -> Vec3 {
+        let cell =
+            |c: F| (c / self.size).floor() as i64;
+        if (cell(p.x) + cell(p.y) + cell(p.z))
+            .rem_euclid(2)
+            == 0
+        {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+const PERLIN_POINT_COUNT: usize = 256;
+
+/// Ken Perlin's gradient noise.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permute_x: Vec<usize>,
+    permute_y: Vec<usize>,
+    permute_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(rng: &mut impl Rng) -> Self {
+        let gradients = (0..PERLIN_POINT_COUNT)
+            .map(|_| loop {
+                let v = Vec3::new(
+                    rng.gen::<F>() * 2. - 1.,
+                    rng.gen::<F>() * 2. - 1.,
+                    rng.gen::<F>() * 2. - 1.,
+                );
+                let length = v.squared_length();
+                if length > 1e-4 && length <= 1. {
+                    break v.unit();
+                }
+            })
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> =
+                (0..PERLIN_POINT_COUNT).collect();
+            p.shuffle(rng);
+            p
+        };
+        Perlin {
+            gradients,
+            permute_x: permutation(),
+            permute_y: permutation(),
+            permute_z: permutation(),
+        }
+    }
+
+    /// Smooth noise, between about -1 and 1.
+    pub fn noise(&self, p: &Vec3) -> F {
+        let floor = |c: F| c.floor();
+        let (fx, fy, fz) =
+            (floor(p.x), floor(p.y), floor(p.z));
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) =
+            (fx as i64, fy as i64, fz as i64);
+        let mask = |n: i64| {
+            (n & (PERLIN_POINT_COUNT as i64 - 1))
+                as usize
+        };
+
+        // Hermite smoothing stops grid lines showing.
+        let smooth = |t: F| t * t * (3. - 2. * t);
+        let (uu, vv, ww) =
+            (smooth(u), smooth(v), smooth(w));
+
+        let mut sum = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self
+                        .permute_x[mask(i + di)]
+                        ^ self.permute_y[mask(j + dj)]
+                        ^ self.permute_z[mask(k + dk)]];
+                    let (a, b, c) =
+                        (di as F, dj as F, dk as F);
+                    let weight =
+                        Vec3::new(u - a, v - b, w - c);
+                    sum += (a * uu
+                        + (1. - a) * (1. - uu))
+                        * (b * vv
+                            + (1. - b) * (1. - vv))
+                        * (c * ww
+                            + (1. - c) * (1. - ww))
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+        sum
+    }
+
+    /// Several octaves of noise summed, always positive.
+    pub fn turbulence(
+        &self,
+        p: &Vec3,
+        depth: usize,
+    ) -> F {
+        let mut sum = 0.;
+        let mut p = *p;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            sum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = p * 2.;
+        }
+        sum.abs()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    /// Soft, cloudy turbulence.
+    Turbulence,
+    /// Veins of turbulence through stripes.
+    Marble,
+}
+
+/// Grey procedural noise.
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub kind: NoiseKind,
+    /// Higher is finer.
+    pub scale: F,
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: F, _v: F, p: &Vec3) -> Vec3 {
+        let p = *p * self.scale;
+        match self.kind {
+            NoiseKind::Turbulence => {
+                Vec3::ONE * self.noise.turbulence(&p, 7)
+            }
+            NoiseKind::Marble => {
+                Vec3::ONE
+                    * 0.5
+                    * (1.
+                        + (p.z
+                            + 10.
+                                * self
+                                    .noise
+                                    .turbulence(&p, 7))
+                        .sin())
+            }
+        }
+    }
+}
+
+/// An image wrapped over the surface, bilinearly filtered.
+///
+/// `u` runs left to right and `v` bottom to top, repeating outside 0-1.
+pub struct ImageTexture {
+    pub image: Image,
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: F, v: F, _p: &Vec3) -> Vec3 {
+        self.image.sample(u, v).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn solid(c: F) -> SharedTexture {
+        Arc::new(SolidColor {
+            color: Vec3::from(c),
+        })
+    }
+
+    #[test]
+    fn checker_alternates() {
+        let checker = Checker {
+            odd: solid(0.),
+            even: solid(1.),
+            size: 1.,
+        };
+        let at = |x, y, z| {
+            checker.value(0., 0., &Vec3::new(x, y, z)).x
+        };
+        assert_eq!(at(0.5, 0.5, 0.5), 1.);
+        assert_eq!(at(1.5, 0.5, 0.5), 0.);
+        assert_eq!(at(-0.5, 0.5, 0.5), 0.);
+        assert_eq!(at(-0.5, -0.5, 0.5), 1.);
+    }
+
+    #[test]
+    fn noise_is_smooth_and_bounded() {
+        let perlin =
+            Perlin::new(&mut StdRng::seed_from_u64(1));
+        let mut previous =
+            perlin.noise(&Vec3::new(0., 0.3, 0.7));
+        for i in 1..1000 {
+            let p = Vec3::new(i as F * 0.01, 0.3, 0.7);
+            let n = perlin.noise(&p);
+            assert!(n.abs() <= 1.5);
+            assert!((n - previous).abs() < 0.1);
+            previous = n;
+        }
+        // Zero at lattice points.
+        assert_eq!(
+            perlin.noise(&Vec3::new(3., 4., 5.)),
+            0.
+        );
+    }
+
+    #[test]
+    fn image_lookup() {
+        let mut image = Image::new(2, 1);
+        image.set(0, 0, Color::black());
+        image.set(1, 0, Color::white());
+        let texture = ImageTexture { image };
+        let at =
+            |u| texture.value(u, 0.5, &Vec3::ZERO).x;
+        // Pixel centres.
+        assert_eq!(at(0.25), 0.);
+        assert_eq!(at(0.75), 1.);
+        // Half way between, and wrapping around the edge.
+        assert_eq!(at(0.5), 0.5);
+        assert_eq!(at(0.), 0.5);
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A rectangle of the image, rendered in one go by one thread.
+///
+/// Pixel coordinates are the same as `Image`'s: `y` counts up from the bottom row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// Tiles waiting to be rendered, shared between worker threads.
+///
+/// Each thread takes the next tile whenever it finishes one, so
+/// threads stay busy until the whole image is done no matter how
+/// uneven the tiles are.
+pub struct TileQueue {
+    tiles: Vec<Tile>,
+    next: AtomicUsize,
+}
+
+impl TileQueue {
+    /// Cover the image in square tiles. Those on the right and top
+    /// edges may be smaller.
+    pub fn new(
+        width: usize,
+        height: usize,
+        tile_size: usize,
+    ) -> Self {
+        let mut tiles = vec![];
+        for y in (0..height).step_by(tile_size) {
+            for x in (0..width).step_by(tile_size) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: tile_size.min(width - x),
+                    height: tile_size.min(height - y),
+                });
+            }
+        }
+        TileQueue {
+            tiles,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Claim a tile to render, or None if they've all been taken.
+    pub fn next(&self) -> Option<Tile> {
+        let i =
+            self.next.fetch_add(1, Ordering::Relaxed);
+        self.tiles.get(i).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_image_once() {
+        let (width, height) = (70, 33);
+        let queue = TileQueue::new(width, height, 16);
+        assert_eq!(queue.len(), 5 * 3);
+
+        let mut covered = vec![0; width * height];
+        while let Some(tile) = queue.next() {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[y * width + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+        assert_eq!(queue.next(), None);
+    }
+}
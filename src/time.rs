@@ -1,22 +1,7 @@
-use std::io;
-use std::io::Write;
 use std::ops::Rem;
 use std::time::{Duration, Instant};
 
-pub fn print_progress(
-    start: Instant,
-    fraction_complete: f32,
-) {
-    print!(
-        "\r{:>3}% {:>20} elapsed. eta {}.      ",
-        (fraction_complete * 100.).floor(),
-        format_rough_duration(start.elapsed()),
-        format_remaining_secs(start, fraction_complete)
-    );
-    io::stdout().flush().unwrap();
-}
-
-fn format_remaining_secs(
+pub fn format_remaining_secs(
     start: Instant,
     fraction_complete: f32,
 ) -> String {
@@ -0,0 +1,268 @@
+//! Turning unbounded linear light into the 0-1 range that low dynamic
+//! range images can hold.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use crate::color::Color;
+use crate::image::Image;
+use crate::vec3::{Vec3, F};
+
+/// How values above 1 are brought into range.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMap {
+    /// Cut off at 1, so highlights lose their detail.
+    #[default]
+    Clamp,
+    /// L / (1 + L), on luminance to keep hues.
+    Reinhard,
+    /// Reinhard, reaching white at the given luminance, or at the
+    /// brightest pixel if there's none.
+    ExtendedReinhard { white: Option<F> },
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve.
+    Hable,
+}
+
+impl ToneMap {
+    /// The names `FromStr` accepts.
+    pub const NAMES: &'static str =
+        "clamp, reinhard, extended-reinhard[:WHITE], aces or hable";
+
+    /// Map one pixel, with `white` the luminance for extended Reinhard
+    /// to make white.
+    fn map(self, color: Color, white: F) -> Color {
+        let v: Vec3 = color.into();
+        let v = match self {
+            ToneMap::Clamp => v,
+            ToneMap::Reinhard => {
+                scale_luminance(v, |l| l / (1. + l))
+            }
+            ToneMap::ExtendedReinhard { .. } => {
+                scale_luminance(v, |l| {
+                    l * (1. + l / (white * white))
+                        / (1. + l)
+                })
+            }
+            ToneMap::Aces => per_channel(v, |x| {
+                (x * (2.51 * x + 0.03))
+                    / (x * (2.43 * x + 0.59) + 0.14)
+            }),
+            ToneMap::Hable => {
+                // Hable brightens by two before the curve.
+                let white = hable(11.2);
+                per_channel(v, |x| {
+                    hable(2. * x) / white
+                })
+            }
+        };
+        per_channel(v, |x| x.clamp(0., 1.)).into()
+    }
+}
+
+fn per_channel(v: Vec3, f: impl Fn(F) -> F) -> Vec3 {
+    Vec3::new(f(v.x), f(v.y), f(v.z))
+}
+
+fn scale_luminance(
+    v: Vec3,
+    f: impl Fn(F) -> F,
+) -> Vec3 {
+    let l = Color::from(v).luminance();
+    if l <= 0. {
+        return v;
+    }
+    v * (f(l) / l)
+}
+
+fn hable(x: F) -> F {
+    let (a, b, c, d, e, f) =
+        (0.15, 0.5, 0.1, 0.2, 0.02, 0.3);
+    (x * (a * x + c * b) + d * e)
+        / (x * (a * x + b) + d * f)
+        - e / f
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None => match s {
+                "clamp" => Ok(ToneMap::Clamp),
+                "reinhard" => Ok(ToneMap::Reinhard),
+                "extended-reinhard" => {
+                    Ok(ToneMap::ExtendedReinhard { white: None })
+                }
+                "aces" => Ok(ToneMap::Aces),
+                "hable" => Ok(ToneMap::Hable),
+                _ => Err(format!(
+                    "expected {}, not {:?}",
+                    ToneMap::NAMES,
+                    s
+                )),
+            },
+            Some(("extended-reinhard", white)) => {
+                match white.parse::<F>() {
+                    Ok(w) if w > 0. => {
+                        Ok(ToneMap::ExtendedReinhard {
+                            white: Some(w),
+                        })
+                    }
+                    _ => Err(format!(
+                        "white point {:?} should be a positive number",
+                        white
+                    )),
+                }
+            }
+            Some(_) => Err(format!(
+                "only extended-reinhard takes a white point, not {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for ToneMap {
+    fn fmt(
+        &self,
+        f: &mut Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            ToneMap::Clamp => write!(f, "clamp"),
+            ToneMap::Reinhard => write!(f, "reinhard"),
+            ToneMap::ExtendedReinhard {
+                white: None,
+            } => {
+                write!(f, "extended-reinhard")
+            }
+            ToneMap::ExtendedReinhard {
+                white: Some(white),
+            } => {
+                write!(f, "extended-reinhard:{}", white)
+            }
+            ToneMap::Aces => write!(f, "aces"),
+            ToneMap::Hable => write!(f, "hable"),
+        }
+    }
+}
+
+impl Image {
+    /// Scale by 2^`exposure` then tone map into 0-1, ready for
+    /// sRGB encoding.
+    pub fn tone_mapped(
+        &self,
+        tone_map: ToneMap,
+        exposure: F,
+    ) -> Image {
+        let scale = (2. as F).powf(exposure);
+        let white = match tone_map {
+            ToneMap::ExtendedReinhard {
+                white: Some(w),
+            } => w,
+            _ => {
+                let mut brightest: F = 0.;
+                for j in 0..self.height() {
+                    for i in 0..self.width() {
+                        brightest = brightest.max(
+                            self.get(i, j).luminance(),
+                        );
+                    }
+                }
+                // A black image needs no white point.
+                (brightest * scale).max(1e-6)
+            }
+        };
+        let mut out =
+            Image::new(self.width(), self.height());
+        for j in 0..self.height() {
+            for i in 0..self.width() {
+                let c: Vec3 = self.get(i, j).into();
+                out.set(
+                    i,
+                    j,
+                    tone_map
+                        .map((c * scale).into(), white),
+                );
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_stay_in_range_and_keep_order() {
+        for name in [
+            "clamp",
+            "reinhard",
+            "extended-reinhard:8",
+            "aces",
+            "hable",
+        ] {
+            let tone_map: ToneMap =
+                name.parse().unwrap();
+            assert_eq!(tone_map.to_string(), name);
+            let mut previous = -1.;
+            for i in 0..100 {
+                let x = (i as F * 0.2).powi(2);
+                let y = tone_map
+                    .map(Color::new(x, x, x), 8.)
+                    .r();
+                assert!(
+                    (0. ..=1.).contains(&y),
+                    "{} {}",
+                    name,
+                    y
+                );
+                assert!(
+                    y >= previous,
+                    "{} {}",
+                    name,
+                    x
+                );
+                previous = y;
+            }
+            assert!(
+                tone_map
+                    .map(Color::black(), 8.)
+                    .luminance()
+                    < 1e-6,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn extended_reinhard_reaches_white() {
+        let mut image = Image::new(2, 1);
+        image.set(0, 0, Color::new(0.5, 0.5, 0.5));
+        image.set(1, 0, Color::new(4., 4., 4.));
+        let mapped = image.tone_mapped(
+            ToneMap::ExtendedReinhard { white: None },
+            1.,
+        );
+        assert!(
+            (mapped.get(1, 0).r() - 1.).abs() < 1e-5
+        );
+        // Exposure of one stop doubles, then 1 * (1 + 1/64) / 2.
+        assert!(
+            (mapped.get(0, 0).r() - 0.5078125).abs()
+                < 1e-5
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("filmic".parse::<ToneMap>().is_err());
+        assert!("aces:2".parse::<ToneMap>().is_err());
+        assert!("extended-reinhard:-1"
+            .parse::<ToneMap>()
+            .is_err());
+    }
+}
@@ -0,0 +1,478 @@
+//! Moving, turning and resizing things, and placing copies of them.
+
+use crate::aabb::Aabb;
+use crate::hitable::{Hit, Hitable};
+use crate::ray::Ray;
+use crate::vec3::{Transform, Vec3, F};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Scaling, then rotating by degrees about x, then y, then z, then
+/// moving: a transform whose parts can be blended over time.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Pose {
+    pub scale: Vec3,
+    pub rotate: Vec3,
+    pub translate: Vec3,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Pose {
+            scale: Vec3::ONE,
+            rotate: Vec3::ZERO,
+            translate: Vec3::ZERO,
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform(&self) -> Transform {
+        self.transform_with(&self.rotation())
+    }
+
+    /// Just the turning part.
+    fn rotation(&self) -> Transform {
+        Transform::rotate(
+            Vec3::new(1., 0., 0.),
+            self.rotate.x,
+        )
+        .then(&Transform::rotate(
+            Vec3::new(0., 1., 0.),
+            self.rotate.y,
+        ))
+        .then(&Transform::rotate(
+            Vec3::new(0., 0., 1.),
+            self.rotate.z,
+        ))
+    }
+
+    /// The transform, given its already worked out `rotation`.
+    fn transform_with(
+        &self,
+        rotation: &Transform,
+    ) -> Transform {
+        Transform::scale(self.scale)
+            .then(rotation)
+            .then(&Transform::translate(self.translate))
+    }
+
+    /// Partway to `other`, by `f` from 0 to 1.
+    pub fn lerp(&self, other: &Pose, f: F) -> Pose {
+        let mix = |a: Vec3, b: Vec3| a + (b - a) * f;
+        Pose {
+            scale: mix(self.scale, other.scale),
+            rotate: mix(self.rotate, other.rotate),
+            translate: mix(
+                self.translate,
+                other.translate,
+            ),
+        }
+    }
+}
+
+/// Something hitable, transformed from its own space into the scene's.
+///
+/// The object is shared, so one mesh can be placed many times without
+/// copying it. Glowing things are only sampled as lights if they stay
+/// still and the transform keeps angles the same, but glow when hit
+/// either way.
+pub struct Transformed<H: Hitable + ?Sized> {
+    object: Arc<H>,
+    motion: Motion,
+    bounds: Option<Aabb>,
+}
+
+enum Motion {
+    Still(Transform),
+    /// From one pose at time 0 to the other at time 1, staying put
+    /// before and after.
+    Moving {
+        start: Pose,
+        end: Pose,
+        /// The rotation, if it's the same all the way, so that it
+        /// needn't be worked out again for every ray.
+        rotation: Option<Transform>,
+    },
+}
+
+impl Motion {
+    fn at(&self, time: F) -> Transform {
+        match self {
+            Motion::Still(transform) => *transform,
+            Motion::Moving {
+                start,
+                end,
+                rotation,
+            } => {
+                let pose =
+                    start.lerp(end, time.clamp(0., 1.));
+                match rotation {
+                    Some(rotation) => {
+                        pose.transform_with(rotation)
+                    }
+                    None => pose.transform(),
+                }
+            }
+        }
+    }
+}
+
+impl<H: Hitable + ?Sized> Transformed<H> {
+    pub fn new(
+        object: Arc<H>,
+        transform: Transform,
+    ) -> Self {
+        let bounds = object
+            .bounding_box()
+            .map(|b| corner_bounds(&b, &transform));
+        Transformed {
+            object,
+            motion: Motion::Still(transform),
+            bounds,
+        }
+    }
+
+    /// Moving from `start` at time 0 to `end` at time 1.
+    pub fn moving(
+        object: Arc<H>,
+        start: Pose,
+        end: Pose,
+    ) -> Self {
+        let bounds = object.bounding_box().map(|b| {
+            if start.rotate == end.rotate {
+                // Every point moves in a straight line.
+                corner_bounds(&b, &start.transform())
+                    .union(&corner_bounds(
+                        &b,
+                        &end.transform(),
+                    ))
+            } else {
+                // Turning, a point can't get further from where the
+                // origin goes than its distance from the origin,
+                // scaled.
+                let reach = (0..8)
+                    .map(|i| corner(&b, i).length())
+                    .fold(0., F::max);
+                let stretch = |s: Vec3| {
+                    s.x.abs()
+                        .max(s.y.abs())
+                        .max(s.z.abs())
+                };
+                let r = Vec3::from(
+                    reach
+                        * stretch(start.scale)
+                            .max(stretch(end.scale)),
+                );
+                Aabb::new(
+                    start.translate,
+                    end.translate,
+                )
+                .grow(start.translate - r)
+                .grow(start.translate + r)
+                .grow(end.translate - r)
+                .grow(end.translate + r)
+            }
+        });
+        Transformed {
+            object,
+            motion: Motion::Moving {
+                start,
+                end,
+                rotation: (start.rotate == end.rotate)
+                    .then(|| start.rotation()),
+            },
+            bounds,
+        }
+    }
+}
+
+/// Corner `i`, from 0 to 7, of a box.
+fn corner(b: &Aabb, i: usize) -> Vec3 {
+    let (min, max) = (b.min, b.max);
+    Vec3::new(
+        [min.x, max.x][i & 1],
+        [min.y, max.y][i >> 1 & 1],
+        [min.z, max.z][i >> 2],
+    )
+}
+
+/// The box around a transformed box.
+fn corner_bounds(
+    b: &Aabb,
+    transform: &Transform,
+) -> Aabb {
+    (0..8).fold(Aabb::EMPTY, |bounds, i| {
+        bounds.grow(transform.point(&corner(b, i)))
+    })
+}
+
+impl<H: Hitable + ?Sized> Hitable for Transformed<H> {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        let transform = self.motion.at(ray.time);
+        let inverse = transform.inverse();
+        // Directions aren't normalised, so distances along the ray
+        // are the same in both spaces.
+        let local = ray.next(
+            inverse.point(&ray.origin),
+            inverse.vector(&ray.direction),
+        );
+        let hit = self.object.hit(&local, t)?;
+        Some(Hit {
+            p: ray.point_at(hit.t),
+            normal: transform
+                .normal(&hit.normal)
+                .unit(),
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    fn is_light(&self) -> bool {
+        match &self.motion {
+            Motion::Still(transform) => {
+                transform.keeps_angles()
+                    && self.object.is_light()
+            }
+            Motion::Moving { .. } => false,
+        }
+    }
+
+    fn random_direction_toward(
+        &self,
+        origin: &Vec3,
+        sample: (F, F),
+    ) -> Option<Vec3> {
+        let transform = self.motion.at(0.);
+        let direction =
+            self.object.random_direction_toward(
+                &transform.inverse().point(origin),
+                sample,
+            )?;
+        Some(transform.vector(&direction).unit())
+    }
+
+    /// The same as the object's, as solid angles are kept.
+    fn pdf_toward(
+        &self,
+        origin: &Vec3,
+        direction: &Vec3,
+    ) -> F {
+        let inverse = self.motion.at(0.).inverse();
+        self.object.pdf_toward(
+            &inverse.point(origin),
+            &inverse.vector(direction).unit(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::hitable::Sphere;
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::shapes::{BoxShape, Quad};
+
+    const ALL: Range<F> = 0.0..F::INFINITY;
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-4
+    }
+
+    #[test]
+    fn instances_share_and_move_their_object() {
+        // A unit square facing +z, turned to face +x and moved.
+        let square = Arc::new(Quad {
+            corner: Vec3::ZERO,
+            u: Vec3::new(1., 0., 0.),
+            v: Vec3::new(0., 1., 0.),
+            material: Box::new(Lambertian::new(
+                Vec3::from(0.5),
+            )),
+        });
+        let turn = Transform::rotate(
+            Vec3::new(0., 1., 0.),
+            90.,
+        );
+        let instances: Vec<_> = [0., 10.]
+            .iter()
+            .map(|&x| {
+                Transformed::new(
+                    square.clone(),
+                    turn.then(&Transform::translate(
+                        Vec3::new(x, 0., 0.),
+                    )),
+                )
+            })
+            .collect();
+        assert_eq!(Arc::strong_count(&square), 3);
+
+        let second = &instances[1];
+        let bounds = second.bounding_box().unwrap();
+        assert!(close(
+            bounds.min,
+            Vec3::new(10., 0., -1.)
+        ));
+        assert!(close(
+            bounds.max,
+            Vec3::new(10., 1., 0.)
+        ));
+        let hit = second
+            .hit(
+                &Ray::new(
+                    Vec3::new(15., 0.5, -0.25),
+                    Vec3::new(-2., 0., 0.),
+                ),
+                &ALL,
+            )
+            .unwrap();
+        assert!((hit.t - 2.5).abs() < 1e-5);
+        assert!(close(
+            hit.p,
+            Vec3::new(10., 0.5, -0.25)
+        ));
+        assert!(close(
+            hit.normal,
+            Vec3::new(1., 0., 0.)
+        ));
+        assert!((hit.u - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn stretched_spheres() {
+        let sphere = Arc::new(Sphere {
+            center: Vec3::ZERO,
+            radius: 1.,
+            material: Box::new(DiffuseLight {
+                emit: Color::white(),
+            }),
+        });
+        let egg = Transformed::new(
+            sphere.clone(),
+            Transform::scale(Vec3::new(1., 2., 1.)),
+        );
+        let hit = egg
+            .hit(
+                &Ray::new(
+                    Vec3::new(0., 5., 0.),
+                    Vec3::new(0., -1., 0.),
+                ),
+                &ALL,
+            )
+            .unwrap();
+        assert!((hit.t - 3.).abs() < 1e-5);
+        // Off the axis, the normal leans towards the long side.
+        let side = egg
+            .hit(
+                &Ray::new(
+                    Vec3::new(0.6, 5., 0.),
+                    Vec3::new(0., -1., 0.),
+                ),
+                &ALL,
+            )
+            .unwrap();
+        let expected = Vec3::new(
+            side.p.x,
+            side.p.y / 4.,
+            side.p.z,
+        )
+        .unit();
+        assert!(close(side.normal, expected));
+        assert!(!egg.is_light());
+
+        // Moved and enlarged, it's sampled like a sphere there.
+        let moved = Transformed::new(
+            sphere,
+            Transform::scale(Vec3::from(2.)).then(
+                &Transform::translate(Vec3::new(
+                    0., 10., 0.,
+                )),
+            ),
+        );
+        let there = Sphere {
+            center: Vec3::new(0., 10., 0.),
+            radius: 2.,
+            material: Box::new(DiffuseLight {
+                emit: Color::white(),
+            }),
+        };
+        assert!(moved.is_light());
+        let origin = Vec3::new(1., 0., 0.);
+        let direction = moved
+            .random_direction_toward(
+                &origin,
+                (0.3, 0.7),
+            )
+            .unwrap();
+        assert!(moved
+            .hit(&Ray::new(origin, direction), &ALL)
+            .is_some());
+        let (pdf, expected) = (
+            moved.pdf_toward(&origin, &direction),
+            there.pdf_toward(&origin, &direction),
+        );
+        assert!((pdf / expected - 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn moving_instances_are_bounded_all_the_way() {
+        let cube = Arc::new(BoxShape::new(
+            Vec3::from(-1.),
+            Vec3::from(1.),
+            Box::new(Lambertian::new(Vec3::from(0.5))),
+        ));
+        let end = Pose {
+            translate: Vec3::new(10., 0., 0.),
+            ..Pose::default()
+        };
+        let sliding = Transformed::moving(
+            cube.clone(),
+            Pose::default(),
+            end,
+        );
+        let bounds = sliding.bounding_box().unwrap();
+        assert!(close(bounds.min, Vec3::from(-1.)));
+        assert!(close(
+            bounds.max,
+            Vec3::new(11., 1., 1.)
+        ));
+        let at = |time| Ray {
+            origin: Vec3::new(5., 0., 5.),
+            direction: Vec3::new(0., 0., -1.),
+            time,
+        };
+        assert!(sliding.hit(&at(0.), &ALL).is_none());
+        let hit = sliding.hit(&at(0.5), &ALL).unwrap();
+        assert!((hit.t - 4.).abs() < 1e-5);
+        assert!(close(hit.p, Vec3::new(5., 0., 1.)));
+
+        // Turning a quarter of the way round, the corners reach out
+        // further than they do at either end.
+        let turning = Transformed::moving(
+            cube,
+            Pose::default(),
+            Pose {
+                rotate: Vec3::new(0., 90., 0.),
+                ..Pose::default()
+            },
+        );
+        let bounds = turning.bounding_box().unwrap();
+        let corner = Pose {
+            rotate: Vec3::new(0., 45., 0.),
+            ..Pose::default()
+        }
+        .transform()
+        .point(&Vec3::from(1.));
+        assert!(corner.x > 1.);
+        assert!(bounds.max.x >= corner.x);
+        assert!(!turning.is_light());
+    }
+}
@@ -0,0 +1,356 @@
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::hitable::{Hit, Hitable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Vec3, F};
+use crate::{Error, Res};
+use std::ops::Range;
+
+/// A single, standalone triangle.
+///
+/// Vertices are counter-clockwise when seen from the front.
+pub struct Triangle {
+    pub vertices: [Vec3; 3],
+    /// Per-vertex normals for smooth shading. Flat if None.
+    pub normals: Option<[Vec3; 3]>,
+    pub uvs: Option<[(F, F); 3]>,
+    pub material: Box<dyn Material + Send + Sync>,
+}
+
+impl Hitable for Triangle {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        hit_triangle(
+            ray,
+            t,
+            &self.vertices,
+            self.normals.as_ref(),
+            self.uvs.as_ref(),
+            self.material.as_ref(),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle_bounds(&self.vertices))
+    }
+}
+
+/// Indexed triangles sharing one vertex buffer and material.
+///
+/// Keeps its own bounding volume hierarchy, so the scene sees it as
+/// a single object.
+pub struct TriangleMesh {
+    vertices: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<(F, F)>>,
+    triangles: Vec<[usize; 3]>,
+    material: Box<dyn Material + Send + Sync>,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    /// `normals` and `uvs`, if given, have one entry per vertex.
+    ///
+    /// Fails if a triangle refers to a vertex that doesn't exist.
+    pub fn new(
+        vertices: Vec<Vec3>,
+        triangles: Vec<[usize; 3]>,
+        normals: Option<Vec<Vec3>>,
+        uvs: Option<Vec<(F, F)>>,
+        material: Box<dyn Material + Send + Sync>,
+    ) -> Res<Self> {
+        if let Some(i) = triangles
+            .iter()
+            .flatten()
+            .find(|&&i| i >= vertices.len())
+        {
+            return Err(Error::InvalidParameter(format!(
+                "triangle vertex {} is outside the {} vertices",
+                i,
+                vertices.len()
+            )));
+        }
+        let lengths = [
+            ("normals", normals.as_ref().map(Vec::len)),
+            ("uvs", uvs.as_ref().map(Vec::len)),
+        ];
+        for (what, len) in lengths {
+            match len {
+                Some(len) if len != vertices.len() => {
+                    return Err(
+                        Error::InvalidParameter(
+                            format!(
+                                "{} {} for {} vertices",
+                                len,
+                                what,
+                                vertices.len()
+                            ),
+                        ),
+                    )
+                }
+                _ => {}
+            }
+        }
+
+        let bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|tri| {
+                triangle_bounds(
+                    &tri.map(|i| vertices[i]),
+                )
+            })
+            .collect();
+
+        Ok(TriangleMesh {
+            bvh: Bvh::build(&bounds),
+            vertices,
+            normals,
+            uvs,
+            triangles,
+            material,
+        })
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(
+        &self,
+        ray: &Ray,
+        t: &Range<F>,
+    ) -> Option<Hit<'_>> {
+        self.bvh.hit(ray, t, |i, t| {
+            let tri = self.triangles[i];
+            hit_triangle(
+                ray,
+                t,
+                &tri.map(|i| self.vertices[i]),
+                self.normals
+                    .as_ref()
+                    .map(|n| tri.map(|i| n[i]))
+                    .as_ref(),
+                self.uvs
+                    .as_ref()
+                    .map(|uv| tri.map(|i| uv[i]))
+                    .as_ref(),
+                self.material.as_ref(),
+            )
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+}
+
+fn triangle_bounds(v: &[Vec3; 3]) -> Aabb {
+    Aabb::new(v[0], v[1]).grow(v[2])
+}
+
+/// Möller–Trumbore intersection.
+fn hit_triangle<'a>(
+    ray: &Ray,
+    within_t: &Range<F>,
+    v: &[Vec3; 3],
+    normals: Option<&[Vec3; 3]>,
+    uvs: Option<&[(F, F); 3]>,
+    material: &'a (dyn Material + Send + Sync),
+) -> Option<Hit<'a>> {
+    let edge1 = v[1] - v[0];
+    let edge2 = v[2] - v[0];
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    // Relative to the sizes involved, so that tiny triangles, or rays
+    // with short directions, still hit.
+    if determinant * determinant
+        <= F::EPSILON
+            * F::EPSILON
+            * edge1.squared_length()
+            * edge2.squared_length()
+            * ray.direction.squared_length()
+    {
+        // Parallel to the triangle (or it's degenerate).
+        return None;
+    }
+    let inverse_determinant = 1. / determinant;
+
+    let to_origin = ray.origin - v[0];
+    let b1 = to_origin.dot(&p) * inverse_determinant;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+    let q = to_origin.cross(&edge1);
+    let b2 =
+        ray.direction.dot(&q) * inverse_determinant;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+
+    let t = edge2.dot(&q) * inverse_determinant;
+    if !within_t.contains(&t) {
+        return None;
+    }
+
+    let normal = match normals {
+        Some(n) => (n[0] * (1. - b1 - b2)
+            + n[1] * b1
+            + n[2] * b2)
+            .unit(),
+        None => edge1.cross(&edge2).unit(),
+    };
+
+    // Without uvs, the barycentric coordinates will do.
+    let (u, v) = match uvs {
+        Some(uv) => (
+            uv[0].0 * (1. - b1 - b2)
+                + uv[1].0 * b1
+                + uv[2].0 * b2,
+            uv[0].1 * (1. - b1 - b2)
+                + uv[1].1 * b1
+                + uv[2].1 * b2,
+        ),
+        None => (b1, b2),
+    };
+
+    Some(Hit {
+        t,
+        p: ray.point_at(t),
+        normal,
+        u,
+        v,
+        material,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+
+    fn grey() -> Box<dyn Material + Send + Sync> {
+        Box::new(Lambertian::new(Vec3::from(0.5)))
+    }
+
+    /// Two triangles making a unit square on the z=0 plane, facing +z.
+    fn square() -> TriangleMesh {
+        TriangleMesh::new(
+            vec![
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(0., 1., 0.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            None,
+            None,
+            grey(),
+        )
+        .unwrap()
+    }
+
+    fn down_at(x: F, y: F) -> Ray {
+        Ray::new(
+            Vec3::new(x, y, 1.),
+            Vec3::new(0., 0., -1.),
+        )
+    }
+
+    #[test]
+    fn hits_mesh() {
+        let mesh = square();
+        let t = 0.001..F::INFINITY;
+        for (x, y) in
+            [(0.25, 0.75), (0.75, 0.25), (0.5, 0.5)]
+        {
+            let hit =
+                mesh.hit(&down_at(x, y), &t).unwrap();
+            assert!((hit.t - 1.).abs() < 1e-6);
+            assert_eq!(
+                hit.normal,
+                Vec3::new(0., 0., 1.)
+            );
+        }
+        assert!(mesh
+            .hit(&down_at(1.5, 0.5), &t)
+            .is_none());
+        assert!(mesh
+            .hit(&down_at(0.5, 0.5), &(0.0..0.5))
+            .is_none());
+    }
+
+    #[test]
+    fn smooth_normals_are_interpolated() {
+        let tri = Triangle {
+            vertices: [
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(0., 1., 0.),
+            ],
+            normals: Some([
+                Vec3::new(0., 0., 1.),
+                Vec3::new(1., 0., 0.),
+                Vec3::new(0., 0., 1.),
+            ]),
+            uvs: None,
+            material: grey(),
+        };
+        let hit = tri
+            .hit(&down_at(0.5, 0.), &(0.0..F::INFINITY))
+            .unwrap();
+        let expected = Vec3::new(1., 0., 1.).unit();
+        assert!(
+            (hit.normal - expected).length() < 1e-6
+        );
+    }
+
+    #[test]
+    fn rejects_bad_indices_and_lengths() {
+        let error = |triangles, uvs| {
+            TriangleMesh::new(
+                vec![Vec3::ZERO],
+                triangles,
+                None,
+                uvs,
+                grey(),
+            )
+            .err()
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(
+            error(vec![[0, 0, 1]], None),
+            "triangle vertex 1 is outside the 1 vertices"
+        );
+        assert_eq!(
+            error(vec![[0, 0, 0]], Some(vec![])),
+            "0 uvs for 1 vertices"
+        );
+    }
+
+    #[test]
+    fn tiny_triangles_are_hit() {
+        let tiny = Triangle {
+            vertices: [
+                Vec3::new(0., 0., 0.),
+                Vec3::new(1e-4, 0., 0.),
+                Vec3::new(0., 1e-4, 0.),
+            ],
+            normals: None,
+            uvs: None,
+            material: grey(),
+        };
+        assert!(tiny
+            .hit(
+                &down_at(2e-5, 2e-5),
+                &(0.0..F::INFINITY)
+            )
+            .is_some());
+    }
+}
@@ -5,11 +5,6 @@ pub type F = f32;
 
 pub const PI: F = std::f64::consts::PI as F;
 
-/// Rand between 0-1. Centralised so that I could experiment with different rand functions easily..
-pub fn randf() -> F {
-    rand::random()
-}
-
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct Vec3 {
     pub x: F,
@@ -33,9 +28,6 @@ impl Vec3 {
         Vec3 { x, y, z }
     }
 
-    pub fn random() -> Self {
-        Self::new(randf(), randf(), randf())
-    }
     pub fn length(&self) -> F {
         self.squared_length().sqrt()
     }
@@ -62,6 +54,53 @@ impl Vec3 {
 
         *self / len
     }
+
+    /// Component-wise minimum.
+    pub fn min(&self, b: &Self) -> Self {
+        Self::new(
+            self.x.min(b.x),
+            self.y.min(b.y),
+            self.z.min(b.z),
+        )
+    }
+
+    /// Component-wise maximum.
+    pub fn max(&self, b: &Self) -> Self {
+        Self::new(
+            self.x.max(b.x),
+            self.y.max(b.y),
+            self.z.max(b.z),
+        )
+    }
+
+    /// Two unit vectors perpendicular to this (unit) vector and each other.
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        // Duff et al., "Building an Orthonormal Basis, Revisited".
+        let sign = (1. as F).copysign(self.z);
+        let a = -1. / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3::new(
+                1. + sign * self.x * self.x * a,
+                sign * b,
+                -sign * self.x,
+            ),
+            Vec3::new(
+                b,
+                sign + self.y * self.y * a,
+                -self.y,
+            ),
+        )
+    }
+
+    /// Component by axis number: 0 is x, 1 is y, 2 is z.
+    pub fn axis(&self, axis: usize) -> F {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
+    }
 }
 
 impl ops::Add<Vec3> for Vec3 {
@@ -159,6 +198,210 @@ impl From<F> for Vec3 {
     }
 }
 
+/// A 4x4 matrix, row by row, acting on column vectors.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Mat4 {
+    pub rows: [[F; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        rows: [
+            [1., 0., 0., 0.],
+            [0., 1., 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ],
+    };
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut rows = [[0.; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate()
+            {
+                *value = self.rows[j][i];
+            }
+        }
+        Mat4 { rows }
+    }
+
+    /// Transform a point, which moves with translation. The bottom
+    /// row is assumed to be 0, 0, 0, 1.
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.vector(p)
+            + Vec3::new(
+                self.rows[0][3],
+                self.rows[1][3],
+                self.rows[2][3],
+            )
+    }
+
+    /// Transform a direction, which doesn't.
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let row = |r: &[F; 4]| {
+            r[0] * v.x + r[1] * v.y + r[2] * v.z
+        };
+        Vec3::new(
+            row(&self.rows[0]),
+            row(&self.rows[1]),
+            row(&self.rows[2]),
+        )
+    }
+}
+
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Self;
+    fn mul(self, rhs: Mat4) -> Self::Output {
+        let mut rows = [[0.; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate()
+            {
+                *value = (0..4)
+                    .map(|k| {
+                        self.rows[i][k] * rhs.rows[k][j]
+                    })
+                    .sum();
+            }
+        }
+        Mat4 { rows }
+    }
+}
+
+/// A translation, rotation, scaling or any combination of them.
+///
+/// Keeps its inverse alongside, built up step by step, so that it
+/// never has to be worked out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Transform {
+    matrix: Mat4,
+    inverse: Mat4,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        matrix: Mat4::IDENTITY,
+        inverse: Mat4::IDENTITY,
+    };
+
+    pub fn translate(offset: Vec3) -> Self {
+        let by = |offset: Vec3| {
+            let mut m = Mat4::IDENTITY;
+            m.rows[0][3] = offset.x;
+            m.rows[1][3] = offset.y;
+            m.rows[2][3] = offset.z;
+            m
+        };
+        Transform {
+            matrix: by(offset),
+            inverse: by(-offset),
+        }
+    }
+
+    /// Stretch along each axis. None of the factors may be zero.
+    pub fn scale(factors: Vec3) -> Self {
+        let by = |f: Vec3| {
+            let mut m = Mat4::IDENTITY;
+            m.rows[0][0] = f.x;
+            m.rows[1][1] = f.y;
+            m.rows[2][2] = f.z;
+            m
+        };
+        Transform {
+            matrix: by(factors),
+            inverse: by(Vec3::ONE / factors),
+        }
+    }
+
+    /// Turn anticlockwise, looking back along `axis`, by `degrees`.
+    pub fn rotate(axis: Vec3, degrees: F) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let t = 1. - cos;
+        let matrix = Mat4 {
+            rows: [
+                [
+                    cos + t * a.x * a.x,
+                    t * a.x * a.y - sin * a.z,
+                    t * a.x * a.z + sin * a.y,
+                    0.,
+                ],
+                [
+                    t * a.x * a.y + sin * a.z,
+                    cos + t * a.y * a.y,
+                    t * a.y * a.z - sin * a.x,
+                    0.,
+                ],
+                [
+                    t * a.x * a.z - sin * a.y,
+                    t * a.y * a.z + sin * a.x,
+                    cos + t * a.z * a.z,
+                    0.,
+                ],
+                [0., 0., 0., 1.],
+            ],
+        };
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    /// This, followed by `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            matrix: next.matrix * self.matrix,
+            inverse: self.inverse * next.inverse,
+        }
+    }
+
+    /// The transform that undoes this one.
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    pub fn point(&self, p: &Vec3) -> Vec3 {
+        self.matrix.point(p)
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        self.matrix.vector(v)
+    }
+
+    /// Transform a surface normal, so that it stays perpendicular to
+    /// the surface. The result isn't a unit vector.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        self.inverse.transpose().vector(n)
+    }
+
+    /// Whether angles stay the same: nothing is stretched more in one
+    /// direction than another.
+    pub fn keeps_angles(&self) -> bool {
+        let axes = [
+            Vec3::new(1., 0., 0.),
+            Vec3::new(0., 1., 0.),
+            Vec3::new(0., 0., 1.),
+        ]
+        .map(|axis| self.vector(&axis));
+        let scale = axes[0].squared_length();
+        let close =
+            |a: F, b: F| (a - b).abs() <= 1e-4 * scale;
+        (0..3).all(|i| {
+            close(axes[i].squared_length(), scale)
+                && close(
+                    axes[i].dot(&axes[(i + 1) % 3]),
+                    0.,
+                )
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -197,6 +440,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn orthonormal_basis() {
+        for w in [
+            Vec3::new(0., 0., 1.),
+            Vec3::new(0., 0., -1.),
+            Vec3::new(1., 2., -3.).unit(),
+        ] {
+            let (u, v) = w.orthonormal_basis();
+            assert!(u.dot(&v).abs() < 1e-6);
+            assert!(u.dot(&w).abs() < 1e-6);
+            assert!(v.dot(&w).abs() < 1e-6);
+            assert!((u.length() - 1.).abs() < 1e-6);
+            assert!((v.length() - 1.).abs() < 1e-6);
+        }
+    }
+
     #[test]
     fn units() {
         assert_eq!(
@@ -208,4 +467,68 @@ mod tests {
             }
         )
     }
+
+    fn close(a: Vec3, b: Vec3) -> bool {
+        (a - b).length() < 1e-5
+    }
+
+    #[test]
+    fn transforms_compose_and_invert() {
+        let quarter_turn = Transform::rotate(
+            Vec3::new(0., 0., 2.),
+            90.,
+        );
+        assert!(close(
+            quarter_turn.point(&Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 1., 0.)
+        ));
+        let t = Transform::scale(Vec3::new(2., 1., 1.))
+            .then(&quarter_turn)
+            .then(&Transform::translate(Vec3::new(
+                0., 0., 5.,
+            )));
+        let p = Vec3::new(1., 2., 3.);
+        assert!(close(
+            t.point(&p),
+            Vec3::new(-2., 2., 8.)
+        ));
+        assert!(close(
+            t.inverse().point(&t.point(&p)),
+            p
+        ));
+        assert!(close(
+            t.vector(&Vec3::new(1., 0., 0.)),
+            Vec3::new(0., 2., 0.)
+        ));
+        assert_eq!(
+            t.matrix().rows[3],
+            [0., 0., 0., 1.]
+        );
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let t = Transform::scale(Vec3::new(1., 4., 1.))
+            .then(&Transform::rotate(
+                Vec3::new(1., 1., 0.),
+                30.,
+            ));
+        let (surface, normal) = (
+            Vec3::new(1., -1., 0.),
+            Vec3::new(1., 1., 0.),
+        );
+        assert!(
+            t.vector(&surface)
+                .dot(&t.normal(&normal))
+                .abs()
+                < 1e-5
+        );
+        assert!(!t.keeps_angles());
+        assert!(Transform::rotate(
+            Vec3::new(1., 2., 3.),
+            40.
+        )
+        .then(&Transform::scale(Vec3::from(3.)))
+        .keeps_angles());
+    }
 }
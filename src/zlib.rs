@@ -0,0 +1,991 @@
+//! Just enough zlib (RFC 1950) and deflate (RFC 1951) for image files,
+//! plus the CRC-32 that PNG chunks use.
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier positions with the same hash to try before giving up.
+const MAX_CHAIN: usize = 128;
+/// Symbols per block. Each block gets its own Huffman codes.
+const BLOCK_SYMBOLS: usize = 1 << 16;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23,
+    27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163,
+    195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3,
+    3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97,
+    129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+    3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7,
+    7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// The order code length code lengths are stored in.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13,
+    2, 14, 1, 15,
+];
+
+/// The CRC of each byte value.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            bit += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| {
+        CRC_TABLE[((c ^ b as u32) & 0xff) as usize]
+            ^ (c >> 8)
+    })
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums can't overflow within this many bytes.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// A zlib stream holding `data`, deflated.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // 32K window, default compression level.
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+/// The data in a zlib stream, which is an error if it's longer than
+/// `limit` bytes.
+pub fn decompress(
+    stream: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, String> {
+    let (header, rest) = match stream {
+        [cmf, flg, rest @ ..] => ((*cmf, *flg), rest),
+        _ => {
+            return Err(
+                "zlib stream is cut short".to_owned()
+            )
+        }
+    };
+    if header.0 & 0x0f != 8
+        || !(header.0 as u16 * 256 + header.1 as u16)
+            .is_multiple_of(31)
+    {
+        return Err(
+            "not a deflate zlib stream".to_owned()
+        );
+    }
+    if header.1 & 0x20 != 0 {
+        return Err(
+            "zlib preset dictionaries aren't supported"
+                .to_owned(),
+        );
+    }
+    let (data, used) = inflate(rest, limit)?;
+    match rest.get(used..used + 4) {
+        Some(checksum)
+            if checksum
+                == adler32(&data).to_be_bytes() =>
+        {
+            Ok(data)
+        }
+        Some(_) => {
+            Err("zlib checksum doesn't match"
+                .to_owned())
+        }
+        None => {
+            Err("zlib stream is cut short".to_owned())
+        }
+    }
+}
+
+/// Writes bits least significant first, as deflate wants.
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.bits |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes go most significant bit first.
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = (code.reverse_bits()
+            >> (16 - length as u32))
+            as u32;
+        self.write(reversed, length as u32);
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Symbol {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE.partition_point(|&b| b <= length) - 1
+}
+
+fn distance_code(distance: u16) -> usize {
+    DISTANCE_BASE.partition_point(|&b| b <= distance)
+        - 1
+}
+
+/// Find repeats with hash chains, greedily taking the longest match.
+fn find_matches(data: &[u8]) -> Vec<Symbol> {
+    const HASH_BITS: u32 = 15;
+    let hash = |i: usize| {
+        let v = (data[i] as u32) << 16
+            | (data[i + 1] as u32) << 8
+            | data[i + 2] as u32;
+        (v.wrapping_mul(0x9e37_79b1)
+            >> (32 - HASH_BITS)) as usize
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; WINDOW_SIZE];
+    let insert =
+        |i: usize,
+         head: &mut Vec<usize>,
+         previous: &mut Vec<usize>| {
+            if i + MIN_MATCH <= data.len() {
+                let h = hash(i);
+                previous[i % WINDOW_SIZE] = head[h];
+                head[h] = i;
+            }
+        };
+
+    let mut symbols = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX
+                && i - candidate < WINDOW_SIZE
+                && chain < MAX_CHAIN
+            {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == max {
+                        break;
+                    }
+                }
+                let next =
+                    previous[candidate % WINDOW_SIZE];
+                // Stale entries from beyond the window point forwards.
+                if next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best.0 >= MIN_MATCH {
+            symbols.push(Symbol::Match {
+                length: best.0 as u16,
+                distance: best.1 as u16,
+            });
+            for j in i..i + best.0 {
+                insert(j, &mut head, &mut previous);
+            }
+            i += best.0;
+        } else {
+            symbols.push(Symbol::Literal(data[i]));
+            insert(i, &mut head, &mut previous);
+            i += 1;
+        }
+    }
+    symbols
+}
+
+/// Huffman code lengths for symbol `frequencies`, none longer than `limit`.
+fn code_lengths(
+    frequencies: &[u32],
+    limit: u8,
+) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    loop {
+        let lengths = huffman_lengths(&frequencies);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        // Flatten the distribution and try again.
+        for f in
+            frequencies.iter_mut().filter(|f| **f > 0)
+        {
+            *f = (*f / 2).max(1);
+        }
+    }
+}
+
+fn huffman_lengths(frequencies: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; frequencies.len()];
+    let used: Vec<usize> = (0..frequencies.len())
+        .filter(|&i| frequencies[i] > 0)
+        .collect();
+    match used.as_slice() {
+        [] => return lengths,
+        // A code needs at least two symbols.
+        [only] => {
+            lengths[*only] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // Nodes are leaves (the symbols) then joins; parents link them up.
+    let mut weights: Vec<u64> = used
+        .iter()
+        .map(|&i| frequencies[i] as u64)
+        .collect();
+    let mut parents = vec![usize::MAX; used.len()];
+    let mut queue: Vec<usize> =
+        (0..used.len()).collect();
+    while queue.len() > 1 {
+        queue.sort_by_key(|&n| {
+            std::cmp::Reverse(weights[n])
+        });
+        let (a, b) = (
+            queue.pop().unwrap(),
+            queue.pop().unwrap(),
+        );
+        let node = weights.len();
+        weights.push(weights[a] + weights[b]);
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        queue.push(node);
+    }
+    for (leaf, &symbol) in used.iter().enumerate() {
+        let mut depth = 0;
+        let mut node = leaf;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[symbol] =
+            depth.min(u8::MAX as usize) as u8;
+    }
+    lengths
+}
+
+/// Canonical codes for code `lengths`.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; 16];
+    for &l in lengths {
+        count[l as usize] += 1;
+    }
+    count[0] = 0;
+    let mut next = [0u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|&l| {
+            if l == 0 {
+                0
+            } else {
+                let c = next[l as usize];
+                next[l as usize] += 1;
+                c
+            }
+        })
+        .collect()
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let literal = (0..288)
+        .map(|s| match s {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        })
+        .collect();
+    (literal, vec![5; 30])
+}
+
+/// Run-length encode the code lengths of a dynamic block header, as
+/// (code length symbol, extra bits value).
+fn encode_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < lengths.len() {
+        let l = lengths[i];
+        let run = lengths[i..]
+            .iter()
+            .take_while(|&&x| x == l)
+            .count();
+        if l == 0 && run >= 3 {
+            let n = run.min(138);
+            out.push(if n <= 10 {
+                (17, n as u8 - 3)
+            } else {
+                (18, n as u8 - 11)
+            });
+            i += n;
+        } else if l != 0 && run >= 4 {
+            out.push((l, 0));
+            let n = (run - 1).min(6);
+            out.push((16, n as u8 - 3));
+            i += n + 1;
+        } else {
+            out.push((l, 0));
+            i += 1;
+        }
+    }
+    out
+}
+
+fn extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Cost in bits of `symbols` with the given codes, not counting any header.
+fn block_bits(
+    symbols: &[Symbol],
+    literal_lengths: &[u8],
+    distance_lengths: &[u8],
+) -> usize {
+    symbols
+        .iter()
+        .map(|s| match *s {
+            Symbol::Literal(b) => {
+                literal_lengths[b as usize] as usize
+            }
+            Symbol::Match { length, distance } => {
+                let l = length_code(length);
+                let d = distance_code(distance);
+                (literal_lengths[257 + l]
+                    + LENGTH_EXTRA[l]
+                    + distance_lengths[d]
+                    + DISTANCE_EXTRA[d])
+                    as usize
+            }
+        })
+        .sum::<usize>()
+        + literal_lengths[256] as usize
+}
+
+fn write_symbols(
+    out: &mut BitWriter,
+    symbols: &[Symbol],
+    literal_lengths: &[u8],
+    distance_lengths: &[u8],
+) {
+    let literal_codes =
+        canonical_codes(literal_lengths);
+    let distance_codes =
+        canonical_codes(distance_lengths);
+    for s in symbols {
+        match *s {
+            Symbol::Literal(b) => out.write_code(
+                literal_codes[b as usize],
+                literal_lengths[b as usize],
+            ),
+            Symbol::Match { length, distance } => {
+                let l = length_code(length);
+                out.write_code(
+                    literal_codes[257 + l],
+                    literal_lengths[257 + l],
+                );
+                out.write(
+                    (length - LENGTH_BASE[l]) as u32,
+                    LENGTH_EXTRA[l] as u32,
+                );
+                let d = distance_code(distance);
+                out.write_code(
+                    distance_codes[d],
+                    distance_lengths[d],
+                );
+                out.write(
+                    (distance - DISTANCE_BASE[d])
+                        as u32,
+                    DISTANCE_EXTRA[d] as u32,
+                );
+            }
+        }
+    }
+    out.write_code(
+        literal_codes[256],
+        literal_lengths[256],
+    );
+}
+
+/// Raw deflate, choosing stored, fixed or dynamic Huffman coding for
+/// each block, whichever is smallest.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let symbols = find_matches(data);
+    let mut out = BitWriter {
+        out: vec![],
+        bits: 0,
+        count: 0,
+    };
+    let mut position = 0;
+    let mut blocks =
+        symbols.chunks(BLOCK_SYMBOLS).peekable();
+    if blocks.peek().is_none() {
+        // An empty stored block.
+        out.write(1, 3);
+        out.align();
+        out.out.extend([0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u32;
+        let size: usize = block
+            .iter()
+            .map(|s| match s {
+                Symbol::Literal(_) => 1,
+                Symbol::Match { length, .. } => {
+                    *length as usize
+                }
+            })
+            .sum();
+        let raw = &data[position..position + size];
+        position += size;
+
+        let mut literal_frequencies = [0u32; 286];
+        let mut distance_frequencies = [0u32; 30];
+        literal_frequencies[256] = 1;
+        for s in block {
+            match *s {
+                Symbol::Literal(b) => {
+                    literal_frequencies[b as usize] += 1
+                }
+                Symbol::Match { length, distance } => {
+                    literal_frequencies
+                        [257 + length_code(length)] +=
+                        1;
+                    distance_frequencies
+                        [distance_code(distance)] += 1;
+                }
+            }
+        }
+        let literal_lengths =
+            code_lengths(&literal_frequencies, 15);
+        let mut distance_lengths =
+            code_lengths(&distance_frequencies, 15);
+        // Some decoders dislike an empty distance code.
+        if distance_lengths.iter().all(|&l| l == 0) {
+            distance_lengths[0] = 1;
+        }
+
+        let literal_count = 257.max(
+            literal_lengths
+                .iter()
+                .rposition(|&l| l > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+        let distance_count = distance_lengths
+            .iter()
+            .rposition(|&l| l > 0)
+            .unwrap_or(0)
+            + 1;
+        let header_lengths: Vec<u8> = literal_lengths
+            [..literal_count]
+            .iter()
+            .chain(&distance_lengths[..distance_count])
+            .copied()
+            .collect();
+        let encoded = encode_lengths(&header_lengths);
+        let mut length_frequencies = [0u32; 19];
+        for &(symbol, _) in &encoded {
+            length_frequencies[symbol as usize] += 1;
+        }
+        let length_lengths =
+            code_lengths(&length_frequencies, 7);
+        let length_count = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&s| length_lengths[s] > 0)
+                .unwrap_or(0)
+                + 1,
+        );
+
+        let dynamic_bits = 17
+            + 3 * length_count
+            + encoded
+                .iter()
+                .map(|&(s, _)| {
+                    length_lengths[s as usize] as usize
+                        + extra_bits(s) as usize
+                })
+                .sum::<usize>()
+            + block_bits(
+                block,
+                &literal_lengths,
+                &distance_lengths,
+            );
+        let (fixed_literal, fixed_distance) =
+            fixed_lengths();
+        let fixed_bits = 3 + block_bits(
+            block,
+            &fixed_literal,
+            &fixed_distance,
+        );
+        let stored_bits = (size / 0xffff + 1)
+            * (5 * 8 + 8)
+            + size * 8;
+
+        if stored_bits <= fixed_bits.min(dynamic_bits) {
+            let mut pieces =
+                raw.chunks(0xffff).peekable();
+            if pieces.peek().is_none() {
+                out.write(last, 3);
+                out.align();
+                out.out.extend([0, 0, 0xff, 0xff]);
+            }
+            while let Some(piece) = pieces.next() {
+                let final_piece = last != 0
+                    && pieces.peek().is_none();
+                out.write(final_piece as u32, 3);
+                out.align();
+                let n = piece.len() as u16;
+                out.out.extend(n.to_le_bytes());
+                out.out.extend((!n).to_le_bytes());
+                out.out.extend(piece);
+            }
+        } else if fixed_bits <= dynamic_bits {
+            out.write(last | 1 << 1, 3);
+            write_symbols(
+                &mut out,
+                block,
+                &fixed_literal,
+                &fixed_distance,
+            );
+        } else {
+            out.write(last | 2 << 1, 3);
+            out.write(literal_count as u32 - 257, 5);
+            out.write(distance_count as u32 - 1, 5);
+            out.write(length_count as u32 - 4, 4);
+            for &s in &CODE_LENGTH_ORDER[..length_count]
+            {
+                out.write(length_lengths[s] as u32, 3);
+            }
+            let length_codes =
+                canonical_codes(&length_lengths);
+            for &(s, extra) in &encoded {
+                out.write_code(
+                    length_codes[s as usize],
+                    length_lengths[s as usize],
+                );
+                out.write(extra as u32, extra_bits(s));
+            }
+            write_symbols(
+                &mut out,
+                block,
+                &literal_lengths,
+                &distance_lengths,
+            );
+        }
+    }
+    out.align();
+    out.out
+}
+
+/// Reads bits least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    at: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(
+        &mut self,
+        count: u32,
+    ) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .data
+                .get(self.at)
+                .ok_or("deflate data is cut short")?;
+            value |=
+                ((*byte as u32 >> self.bit) & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.at += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit > 0 {
+            self.bit = 0;
+            self.at += 1;
+        }
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+struct Decoder {
+    /// How many codes there are of each length.
+    count: [u16; 16],
+    /// Symbols in code order.
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    fn new(lengths: &[u8]) -> Decoder {
+        let mut count = [0u16; 16];
+        for &l in lengths {
+            count[l as usize] += 1;
+        }
+        count[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len()
+            as u16)
+            .filter(|&s| lengths[s as usize] > 0)
+            .collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Decoder { count, symbols }
+    }
+
+    fn decode(
+        &self,
+        input: &mut BitReader,
+    ) -> Result<u16, String> {
+        let (mut code, mut first, mut index) =
+            (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= input.bits(1)? as i32;
+            let count = self.count[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols
+                    [(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code in deflate data"
+            .to_owned())
+    }
+}
+
+/// Raw inflate, stopping with an error if there's more than `limit`
+/// bytes of data. Returns the data and how many bytes of `input` it
+/// used.
+pub fn inflate(
+    input: &[u8],
+    limit: usize,
+) -> Result<(Vec<u8>, usize), String> {
+    let mut input = BitReader {
+        data: input,
+        at: 0,
+        bit: 0,
+    };
+    let mut out: Vec<u8> = vec![];
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let header = input
+                    .data
+                    .get(input.at..input.at + 4)
+                    .ok_or(
+                        "deflate data is cut short",
+                    )?;
+                let n = u16::from_le_bytes([
+                    header[0], header[1],
+                ]);
+                if n != !u16::from_le_bytes([
+                    header[2], header[3],
+                ]) {
+                    return Err("stored block length is corrupt".to_owned());
+                }
+                input.at += 4;
+                let stored = input
+                    .data
+                    .get(
+                        input.at..input.at + n as usize,
+                    )
+                    .ok_or(
+                        "deflate data is cut short",
+                    )?;
+                if out.len() + stored.len() > limit {
+                    return Err(too_long());
+                }
+                out.extend(stored);
+                input.at += n as usize;
+            }
+            1 => {
+                let (literal, distance) =
+                    fixed_lengths();
+                inflate_block(
+                    &mut input,
+                    &mut out,
+                    limit,
+                    &Decoder::new(&literal),
+                    &Decoder::new(&distance),
+                )?;
+            }
+            2 => {
+                let literal_count =
+                    input.bits(5)? as usize + 257;
+                let distance_count =
+                    input.bits(5)? as usize + 1;
+                let length_count =
+                    input.bits(4)? as usize + 4;
+                let mut length_lengths = [0u8; 19];
+                for &s in
+                    &CODE_LENGTH_ORDER[..length_count]
+                {
+                    length_lengths[s] =
+                        input.bits(3)? as u8;
+                }
+                let length_decoder =
+                    Decoder::new(&length_lengths);
+                let mut lengths = vec![];
+                while lengths.len()
+                    < literal_count + distance_count
+                {
+                    let (value, repeat) =
+                        match length_decoder.decode(&mut input)? {
+                            16 => (
+                                *lengths.last().ok_or(
+                                    "repeated code length with nothing before it",
+                                )?,
+                                3 + input.bits(2)?,
+                            ),
+                            17 => (0, 3 + input.bits(3)?),
+                            18 => (0, 11 + input.bits(7)?),
+                            l => (l as u8, 1),
+                        };
+                    lengths.extend(
+                        std::iter::repeat_n(
+                            value,
+                            repeat as usize,
+                        ),
+                    );
+                }
+                if lengths.len()
+                    > literal_count + distance_count
+                {
+                    return Err(
+                        "too many code lengths"
+                            .to_owned(),
+                    );
+                }
+                inflate_block(
+                    &mut input,
+                    &mut out,
+                    limit,
+                    &Decoder::new(
+                        &lengths[..literal_count],
+                    ),
+                    &Decoder::new(
+                        &lengths[literal_count..],
+                    ),
+                )?;
+            }
+            _ => {
+                return Err(
+                    "invalid deflate block type"
+                        .to_owned(),
+                )
+            }
+        }
+        if last {
+            input.align();
+            return Ok((out, input.at));
+        }
+    }
+}
+
+fn too_long() -> String {
+    "deflate data is longer than expected".to_owned()
+}
+
+fn inflate_block(
+    input: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Decoder,
+    distances: &Decoder,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(input)? as usize;
+        match symbol {
+            0..=255 if out.len() == limit => {
+                return Err(too_long())
+            }
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let l = symbol - 257;
+                if l >= LENGTH_BASE.len() {
+                    return Err("invalid length code"
+                        .to_owned());
+                }
+                let length = LENGTH_BASE[l] as usize
+                    + input
+                        .bits(LENGTH_EXTRA[l] as u32)?
+                        as usize;
+                let d =
+                    distances.decode(input)? as usize;
+                if d >= DISTANCE_BASE.len() {
+                    return Err(
+                        "invalid distance code"
+                            .to_owned(),
+                    );
+                }
+                let distance = DISTANCE_BASE[d]
+                    as usize
+                    + input.bits(
+                        DISTANCE_EXTRA[d] as u32,
+                    )? as usize;
+                if distance > out.len() {
+                    return Err("distance is before the start of the data".to_owned());
+                }
+                if out.len() + length > limit {
+                    return Err(too_long());
+                }
+                // Copies can overlap what they're writing.
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut noise = 12345u32;
+        let mut random = || {
+            noise = noise
+                .wrapping_mul(1_103_515_245)
+                .wrapping_add(12345);
+            (noise >> 16) as u8
+        };
+        let inputs: Vec<Vec<u8>> = vec![
+            vec![],
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabcabcabc".to_vec(),
+            vec![7; 100_000],
+            (0..200_000).map(|_| random()).collect(),
+            (0..300_000u32)
+                .map(|i| ((i % 251) ^ (i / 1000)) as u8)
+                .collect(),
+        ];
+        for data in inputs {
+            let stream = compress(&data);
+            assert_eq!(
+                decompress(&stream, usize::MAX)
+                    .unwrap(),
+                data
+            );
+        }
+        // Repetitive data should actually shrink.
+        assert!(compress(&[7; 100_000]).len() < 1000);
+        // And it mustn't grow past what the caller expects.
+        assert!(decompress(
+            &compress(&[7; 100_000]),
+            1000
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn reads_other_encoders() {
+        // zlib.compress(b"hello hello hello hello") from Python.
+        let stream = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+            0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03,
+            0x08, 0xb1,
+        ];
+        assert_eq!(
+            decompress(&stream, usize::MAX).unwrap(),
+            b"hello hello hello hello"
+        );
+        assert!(decompress(&stream[..10], usize::MAX)
+            .is_err());
+        assert_eq!(
+            decompress(&stream, 23).unwrap().len(),
+            23
+        );
+        assert!(decompress(&stream, 22).is_err());
+    }
+}
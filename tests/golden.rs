@@ -0,0 +1,245 @@
+//! Renders small seeded scenes and compares them with the reference
+//! images in `tests/golden`, so changes to how things look don't go
+//! unnoticed.
+//!
+//! After a change that's meant to alter renders, run
+//! `UPDATE_GOLDEN=1 cargo test --test golden` to replace the
+//! references. When a render doesn't match, it's left next to an
+//! image of the differences in the target directory's `tmp/golden`.
+
+use std::convert::TryInto;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Lowest peak signal-to-noise ratio, in decibels, for any channel.
+/// Seeded renders are identical on one machine, but other platforms'
+/// maths libraries may send the odd sample elsewhere. For scale, a
+/// different seed gives about 35 dB, and darkening metal by 3% about
+/// 48 dB.
+const MIN_PSNR: f64 = 50.;
+
+/// How much the difference image brightens differences by.
+const DIFF_SCALE: f32 = 10.;
+
+struct Image {
+    width: usize,
+    height: usize,
+    /// Linear RGB, bottom row first, as stored in PFM.
+    pixels: Vec<[f32; 3]>,
+}
+
+fn read_pfm(path: &Path) -> Image {
+    let bytes = fs::read(path).unwrap_or_else(|e| {
+        panic!(
+            "Can't read {}: {}. Run with UPDATE_GOLDEN=1 to create it.",
+            path.display(),
+            e
+        )
+    });
+    // Three lines of header: "PF", the size and the scale, whose sign
+    // gives the byte order.
+    let mut lines = bytes.splitn(4, |&b| b == b'\n');
+    let mut header = || {
+        String::from_utf8_lossy(lines.next().unwrap())
+            .trim()
+            .to_owned()
+    };
+    assert_eq!(header(), "PF", "{}", path.display());
+    let size = header();
+    let mut size = size
+        .split_whitespace()
+        .map(|n| n.parse::<usize>().unwrap());
+    let (width, height) =
+        (size.next().unwrap(), size.next().unwrap());
+    let little_endian =
+        header().parse::<f32>().unwrap() < 0.;
+    let data = lines.next().unwrap();
+    assert_eq!(data.len(), width * height * 12);
+    let pixels = data
+        .chunks(12)
+        .map(|pixel| {
+            let mut rgb = [0.; 3];
+            for (c, bytes) in
+                rgb.iter_mut().zip(pixel.chunks(4))
+            {
+                let bytes = bytes.try_into().unwrap();
+                *c = if little_endian {
+                    f32::from_le_bytes(bytes)
+                } else {
+                    f32::from_be_bytes(bytes)
+                };
+            }
+            rgb
+        })
+        .collect();
+    Image {
+        width,
+        height,
+        pixels,
+    }
+}
+
+/// Per-channel differences between two images of the same size, over
+/// values clamped to 0-1 so that a firefly counts no more than a
+/// white pixel.
+struct Difference {
+    rmse: [f64; 3],
+}
+
+impl Difference {
+    fn between(a: &Image, b: &Image) -> Self {
+        let mut sums = [0.; 3];
+        for (p, q) in a.pixels.iter().zip(&b.pixels) {
+            for c in 0..3 {
+                let d = p[c].clamp(0., 1.) as f64
+                    - q[c].clamp(0., 1.) as f64;
+                sums[c] += d * d;
+            }
+        }
+        let n = a.pixels.len() as f64;
+        Difference {
+            rmse: sums.map(|s| (s / n).sqrt()),
+        }
+    }
+
+    /// In decibels, infinite for identical channels.
+    fn psnr(&self) -> [f64; 3] {
+        self.rmse.map(|e| -20. * e.log10())
+    }
+}
+
+/// An image of how far apart each pixel's channels are, as a binary
+/// PPM.
+fn write_diff(path: &Path, a: &Image, b: &Image) {
+    let mut out =
+        format!("P6\n{} {}\n255\n", a.width, a.height)
+            .into_bytes();
+    // PPM rows go top to bottom.
+    for y in (0..a.height).rev() {
+        for x in 0..a.width {
+            let i = y * a.width + x;
+            for c in 0..3 {
+                let d = (a.pixels[i][c].clamp(0., 1.)
+                    - b.pixels[i][c].clamp(0., 1.))
+                .abs();
+                out.push(
+                    ((d * DIFF_SCALE).min(1.) * 255.)
+                        .round()
+                        as u8,
+                );
+            }
+        }
+    }
+    fs::write(path, out).unwrap();
+}
+
+/// Render with `args` plus a fixed size and seed, and check the result
+/// against `tests/golden/<name>.pfm`.
+fn check(name: &str, args: &[&str]) {
+    let out_dir =
+        Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join("golden");
+    fs::create_dir_all(&out_dir).unwrap();
+    let actual_path =
+        out_dir.join(format!("{}.pfm", name));
+    let output =
+        Command::new(env!("CARGO_BIN_EXE_chambray"))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .args(args)
+            .args([
+                "--width",
+                "64",
+                "--height",
+                "48",
+                "--samples",
+                "32",
+                "--seed",
+                "1",
+                "--threads",
+                "2",
+            ])
+            .arg(&actual_path)
+            .output()
+            .unwrap();
+    assert!(
+        output.status.success(),
+        "{} failed to render: {}",
+        name,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let golden_path: PathBuf = [
+        env!("CARGO_MANIFEST_DIR"),
+        "tests",
+        "golden",
+        &format!("{}.pfm", name),
+    ]
+    .iter()
+    .collect();
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::copy(&actual_path, &golden_path).unwrap();
+        return;
+    }
+
+    let (actual, expected) = (
+        read_pfm(&actual_path),
+        read_pfm(&golden_path),
+    );
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height),
+        "{} is the wrong size",
+        name
+    );
+    let difference =
+        Difference::between(&actual, &expected);
+    let psnr = difference.psnr();
+    if psnr.iter().any(|&p| p < MIN_PSNR) {
+        let diff_path =
+            out_dir.join(format!("{}.diff.ppm", name));
+        write_diff(&diff_path, &actual, &expected);
+        panic!(
+            "{} doesn't match its reference: PSNR {:.1}, {:.1}, {:.1} dB (RMSE {:.4}, {:.4}, {:.4}), needs {} dB.\nRender: {}\nDifferences: {}",
+            name,
+            psnr[0],
+            psnr[1],
+            psnr[2],
+            difference.rmse[0],
+            difference.rmse[1],
+            difference.rmse[2],
+            MIN_PSNR,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn diffuse_metal_and_glass() {
+    check("standard", &["--preset", "standard"]);
+}
+
+#[test]
+fn area_light_in_a_closed_room() {
+    check("cornell-box", &["--preset", "cornell-box"]);
+}
+
+#[test]
+fn noise_and_checker_textures() {
+    check("textures", &["--preset", "textures"]);
+}
+
+#[test]
+fn random_spheres() {
+    check("random", &["--preset", "random"]);
+}
+
+#[test]
+fn scene_file() {
+    check(
+        "three-spheres",
+        &["--scene", "scenes/three-spheres.scene"],
+    );
+}